@@ -12,7 +12,12 @@ fn criterion_benches(c: &mut Criterion) {
 
     c.bench_function("tokenize simd - c", |b| {
         b.iter(|| {
-            tokenize::<64>(black_box(c_text), black_box(C::TOKENS)).for_each(|c| {
+            tokenize::<64>(
+                black_box(c_text),
+                black_box(C::TOKENS),
+                black_box(C::KEYWORD_STARTS),
+            )
+            .for_each(|c| {
                 black_box(c);
             })
         })
@@ -20,7 +25,12 @@ fn criterion_benches(c: &mut Criterion) {
 
     c.bench_function("tokenize simd - rust", |b| {
         b.iter(|| {
-            tokenize::<64>(black_box(rust_text), black_box(Rust::TOKENS)).for_each(|c| {
+            tokenize::<64>(
+                black_box(rust_text),
+                black_box(Rust::TOKENS),
+                black_box(Rust::KEYWORD_STARTS),
+            )
+            .for_each(|c| {
                 black_box(c);
             })
         })
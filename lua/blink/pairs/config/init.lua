@@ -1,13 +1,25 @@
 --- @class (exact) blink.pairs.ConfigStrict
 --- @field mappings blink.pairs.MappingsConfig
 --- @field highlights blink.pairs.HighlightsConfig
+--- @field max_parsed_buffers? number Maximum number of buffers to keep parsed, evicting the least recently used buffers which are reparsed when next accessed
+--- @field max_line_length number | false Lines longer than this many bytes are skipped when parsing, i.e. in minified files
+--- @field parse_budget blink.pairs.ParseBudget | false Parses large buffers over multiple event loop ticks, parsing up to the budget on each tick
+--- @field parse_in_background boolean Runs full parses on a background thread, using the previous parse until done. Takes precedence over `parse_budget`
 --- @field debug boolean
 
+--- @class (exact) blink.pairs.ParseBudget
+--- @field time_ms? number Milliseconds to parse for on each tick
+--- @field bytes? number Bytes to parse on each tick
+
 local validate = require('blink.pairs.config.utils').validate
 --- @type blink.pairs.ConfigStrict
 local config = {
   mappings = require('blink.pairs.config.mappings').default,
   highlights = require('blink.pairs.config.highlights').default,
+  max_parsed_buffers = nil,
+  max_line_length = 10000,
+  parse_budget = { time_ms = 10 },
+  parse_in_background = false,
   debug = false,
 }
 
@@ -20,6 +32,10 @@ function M.validate(cfg)
   validate('config', {
     mappings = { cfg.mappings, 'table' },
     highlights = { cfg.highlights, 'table' },
+    max_parsed_buffers = { cfg.max_parsed_buffers, 'number', true },
+    max_line_length = { cfg.max_line_length, { 'number', 'boolean' } },
+    parse_budget = { cfg.parse_budget, { 'table', 'boolean' } },
+    parse_in_background = { cfg.parse_in_background, 'boolean' },
     debug = { cfg.debug, 'boolean' },
   }, cfg)
 
@@ -1,6 +1,10 @@
 --- @class (exact) blink.pairs.Config : blink.pairs.ConfigStrict, {}
 --- @field mappings? blink.pairs.MappingsConfigPartial
 --- @field highlights? blink.pairs.HighlightsConfigPartial
+--- @field max_parsed_buffers? number
+--- @field max_line_length? number | false
+--- @field parse_budget? blink.pairs.ParseBudget | false
+--- @field parse_in_background? boolean
 --- @field debug? boolean
 
 --- @class (exact) blink.pairs.MappingsConfigPartial : blink.pairs.MappingsConfig
@@ -1,14 +1,62 @@
 local highlighter = {}
 
+--- Matches for the window being drawn, fetched once per redraw in `on_win`
+--- @type { bufnr: number, start_line: number, end_line: number, matches: blink.pairs.RangeMatches }?
+local viewport
+
+--- Finds the index of the first match on or after the given line
+--- @param lines number[]
+--- @param line_number number
+local function first_match_idx(lines, line_number)
+  local low, high = 1, #lines + 1
+  while low < high do
+    local mid = math.floor((low + high) / 2)
+    if lines[mid] < line_number then
+      low = mid + 1
+    else
+      high = mid
+    end
+  end
+  return low
+end
+
 --- @param config blink.pairs.HighlightsConfig
 function highlighter.register(config)
+  local rust = require('blink.pairs.rust')
+
   vim.api.nvim_set_decoration_provider(config.ns, {
-    on_win = function(_, _, bufnr) return require('blink.pairs.watcher').attach(bufnr) end,
+    on_win = function(_, _, bufnr, toprow, botrow)
+      viewport = nil
+      if not require('blink.pairs.watcher').attach(bufnr) then return false end
+
+      viewport = {
+        bufnr = bufnr,
+        start_line = toprow,
+        end_line = botrow + 1,
+        matches = rust.get_range_matches(bufnr, toprow, botrow + 1),
+      }
+    end,
     on_line = function(_, _, bufnr, line_number)
-      for _, match in ipairs(require('blink.pairs.rust').get_line_matches(bufnr, line_number)) do
-        vim.api.nvim_buf_set_extmark(bufnr, config.ns, line_number, match.col, {
-          end_col = match.col + match[1]:len(),
-          hl_group = config.groups[match.stack_height % #config.groups + 1],
+      -- `botrow` is an estimate, so fall back to fetching the line on its own
+      local matches
+      if
+        viewport ~= nil
+        and viewport.bufnr == bufnr
+        and line_number >= viewport.start_line
+        and line_number < viewport.end_line
+      then
+        matches = viewport.matches
+      else
+        matches = rust.get_range_matches(bufnr, line_number, line_number + 1)
+      end
+
+      local lines, cols, lens, depths = matches.line, matches.col, matches.len, matches.depth
+      for idx = first_match_idx(lines, line_number), #lines do
+        if lines[idx] ~= line_number then break end
+
+        vim.api.nvim_buf_set_extmark(bufnr, config.ns, line_number, cols[idx], {
+          end_col = cols[idx] + lens[idx],
+          hl_group = config.groups[depths[idx] % #config.groups + 1],
           hl_mode = 'combine',
           priority = config.priority,
           ephemeral = true,
@@ -19,6 +19,10 @@ function pairs.setup(user_config)
   pairs.download_if_available(function(err)
     if err then error(err) end
 
+    if config.max_parsed_buffers ~= nil then
+      require('blink.pairs.rust').set_max_buffers(config.max_parsed_buffers)
+    end
+    require('blink.pairs.rust').set_max_line_length(config.max_line_length or nil)
     if config.mappings.enabled then require('blink.pairs.mappings').register(config.mappings.pairs) end
     if config.highlights.enabled then require('blink.pairs.highlighter').register(config.highlights) end
   end)
@@ -49,12 +53,220 @@ function pairs.download_if_available(callback)
   }, callback)
 end
 
+--- @class (exact) blink.pairs.LanguageDefinition
+--- @field delimiters? { [1]: string, [2]: string }[] e.g. { { '(', ')' }, { '{{', '}}' } }
+--- @field line_comment? string[]
+--- @field block_comment? { [1]: string, [2]: string, nested?: boolean }[] Set `nested` for comments which may be nested, i.e. `/* /* */ */`
+--- @field string? string[]
+--- @field char? (string | { [1]: string, max_chars?: integer })[] Set `max_chars` for literals with multiple characters between the quotes, e.g. { { "'", max_chars = 4 } }
+--- @field block_string? { [1]: string, [2]: string }[]
+--- @field inline_span? table<string, { [1]: string, [2]: string }>
+--- @field block_span? table<string, { [1]: string, [2]: string }>
+--- @field keyword_pairs? string[][] Opening keyword, any middle keywords, then the closing keyword, e.g. { { 'do', 'end' }, { 'if', 'elseif', 'else', 'end' } }
+--- @field interpolation? { [1]: string, [2]: string, [3]: string }[] Opening pattern of the string or block string, then the interpolation pair, which may open with a backslash, e.g. { { '`', '${', '}' } } or { { '"', '\\(', ')' } }
+--- @field heredoc? { [1]: string, indented?: boolean, spaced?: boolean }[] Opening pattern of heredocs ending on a line with the terminator captured after it. Set `indented` when the terminator may be indented and `spaced` when whitespace is allowed before the terminator, e.g. { { '<<-', indented = true, spaced = true }, { '<<', spaced = true } }
+--- @field regex? { [1]: string, [2]: string }[] Regex literals, which only open after operators, opening delimiters and keywords such as `return`, e.g. { { '/', '/' } }
+--- @field at_line_start? string[] Patterns which only match at the start of the line, e.g. { '=begin', '=end' }
+--- @field after_indent? string[] Patterns which only match at the start of the line after any indentation, e.g. { '```' }
+--- @field after_whitespace? string[] Patterns which only match at the start of the line or after whitespace, e.g. { '#' }
+--- @field not_after_identifier? string[] Patterns which don't match directly after an identifier character, e.g. { "'" }
+--- @field not_after? { [1]: string, [2]: string }[] Patterns which don't match directly after any of the given ASCII characters, e.g. { { '(', '$' } }
+--- @field escape? table<string, string> Escape inside the strings, block strings and block comments, by their opening pattern: 'none', 'backslash' (default), 'doubled' or a single ASCII character, e.g. { ['`'] = 'none' }. Block comments have no escapes by default
+
+--- Registers a language for the given filetype at runtime, taking priority over any built-in language
+--- Buffers which have already been parsed will not be updated until they're reparsed
+--- @param filetype string
+--- @param definition blink.pairs.LanguageDefinition
+function pairs.register_language(filetype, definition)
+  require('blink.pairs.rust').register_language(filetype, definition)
+end
+
+--- Parsed text, owned by Lua and freed when garbage collected. Handles returned by `get_buffer`
+--- share their state with the buffer parsed by the highlighter
+--- @class blink.pairs.ParsedBuffer
+--- @field parse fun(self, filetype: string, lines: string[]): boolean Replaces the contents with a full parse
+--- @field reparse fun(self, filetype: string, lines: string[], start_line?: number, old_end_line?: number, new_end_line?: number, get_lines?: fun(start: number, end_: number): string[]): number?
+--- @field line_matches fun(self, line: number, token_type?: number, encoding?: blink.pairs.PositionEncoding): blink.pairs.Match[]
+--- @field range_matches fun(self, start_line: number, end_line: number, token_type?: number, encoding?: blink.pairs.PositionEncoding): blink.pairs.RangeMatches
+--- @field span_at fun(self, line: number, col: number, encoding?: blink.pairs.PositionEncoding): string?
+--- @field match_at fun(self, line: number, col: number, encoding?: blink.pairs.PositionEncoding): blink.pairs.Match?
+--- @field match_pair fun(self, line: number, col: number, encoding?: blink.pairs.PositionEncoding): blink.pairs.Match[]?
+--- @field diagnostics fun(self, encoding?: blink.pairs.PositionEncoding): blink.pairs.Diagnostic[]
+
+-- Parse text which doesn't belong to a buffer, i.e. for scratch text in other plugins
+--- @param filetype string
+--- @param lines string[]
+--- @return blink.pairs.ParsedBuffer? Nil when the filetype isn't supported
+function pairs.parse(filetype, lines)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.parse then return nil end
+
+  return blink_pairs.parse(filetype, lines)
+end
+
+-- Get the parsed buffer for a buffer which has been attached to
+--- @param bufnr number
+--- @return blink.pairs.ParsedBuffer?
+function pairs.get_buffer(bufnr)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_buffer then return nil end
+
+  return blink_pairs.get_buffer(bufnr)
+end
+
+--- Unit of the columns passed in and returned, defaulting to bytes. Display columns use the
+--- given tabstop, defaulting to 8
+--- @alias blink.pairs.PositionEncoding 'byte' | 'utf-8' | 'codepoint' | 'utf-32' | 'utf-16' | 'display' | { [1]: 'display', tabstop?: number }
+
 -- Get match at a given position in a buffer
-function pairs.get_match_at(bufnr, row, col)
+--- @param bufnr number
+--- @param row number 0-indexed
+--- @param col number 0-indexed
+--- @param encoding? blink.pairs.PositionEncoding
+--- @return blink.pairs.Match?
+function pairs.get_match_at(bufnr, row, col, encoding)
   local ok, blink_pairs = pcall(require, 'blink_pairs')
   if not ok or not blink_pairs.get_match_at then return nil end
 
-  return blink_pairs.get_match_at(bufnr, row, col)
+  return blink_pairs.get_match_at(bufnr, row, col, encoding)
+end
+
+-- Get the opening and closing match of the pair at a given position in a buffer
+--- @param bufnr number
+--- @param row number 0-indexed
+--- @param col number 0-indexed
+--- @param encoding? blink.pairs.PositionEncoding
+--- @return blink.pairs.Match[]?
+function pairs.get_match_pair(bufnr, row, col, encoding)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_match_pair then return nil end
+
+  return blink_pairs.get_match_pair(bufnr, row, col, encoding)
+end
+
+--- @class (exact) blink.pairs.Diagnostic
+--- @field line number 0-indexed
+--- @field col number 0-indexed column, in bytes unless another encoding was requested
+--- @field token string Text of the offending token, i.e. `(` or `/*`
+--- @field reason 'unmatched_opening' | 'stray_closing' | 'mismatched_closing' | 'unterminated_string' | 'unterminated_block_string' | 'unterminated_block_comment' | 'unterminated_span'
+
+-- Get unmatched delimiters and unterminated strings, comments and spans in a buffer
+--- @param bufnr number
+--- @param encoding? blink.pairs.PositionEncoding
+--- @return blink.pairs.Diagnostic[]
+function pairs.get_diagnostics(bufnr, encoding)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_diagnostics then return {} end
+
+  return blink_pairs.get_diagnostics(bufnr, encoding)
+end
+
+--- @class (exact) blink.pairs.Match
+--- @field [1] string Opening delimiter
+--- @field [2]? string Closing delimiter
+--- @field span? string
+--- @field line number 0-indexed
+--- @field col number 0-indexed column, in bytes unless another encoding was requested
+--- @field stack_height? number
+
+--- Matches in a range of lines as parallel arrays, where the nth match is made up of the nth
+--- element of each array
+--- @class (exact) blink.pairs.RangeMatches
+--- @field line number[] 0-indexed
+--- @field col number[] 0-indexed column, in bytes unless another encoding was requested
+--- @field len number[] Length in bytes
+--- @field depth number[] Stack height of the delimiter, or -1 for other tokens
+--- @field kind number[] 0 for opening, 1 for closing, 2 for non-pair tokens and 3 for middle keywords, i.e. `else`
+
+--- @class (exact) blink.pairs.TextRange
+--- @field start_line number 0-indexed
+--- @field start_col number 0-indexed column, in bytes unless another encoding was requested
+--- @field end_line number 0-indexed
+--- @field end_col number 0-indexed column, in bytes unless another encoding was requested, exclusive
+
+--- @class (exact) blink.pairs.EnclosingPair
+--- @field opening blink.pairs.Match
+--- @field closing blink.pairs.Match
+--- @field outer blink.pairs.TextRange Range including the delimiters, like `a(`
+--- @field inner blink.pairs.TextRange Range excluding the delimiters and surrounding whitespace, like `i(`
+
+-- Get the pair enclosing a position in a buffer, for building text objects
+--- @param bufnr number
+--- @param row number 0-indexed
+--- @param col number 0-indexed
+--- @param count? number Get the nth enclosing pair, defaults to 1
+--- @param filter? string Only consider pairs with this opening delimiter, i.e. `(`
+--- @param encoding? blink.pairs.PositionEncoding
+--- @return blink.pairs.EnclosingPair?
+function pairs.get_enclosing_pair(bufnr, row, col, count, filter, encoding)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_enclosing_pair then return nil end
+
+  return blink_pairs.get_enclosing_pair(bufnr, row, col, count, filter, encoding)
+end
+
+--- @class (exact) blink.pairs.Region
+--- @field [1] number Start row, 0-indexed
+--- @field [2] number Start column, 0-indexed byte column
+--- @field [3] number End row, 0-indexed
+--- @field [4] number End column, 0-indexed byte column, exclusive
+--- @field [5] string Filetype of the region
+
+-- Set the regions of a buffer in other languages, i.e. from tree-sitter injections, which are
+-- parsed with their own filetype and delimiter stack. Replaces the previous regions. Regions
+-- whose first or last line is edited, other than in place, are dropped until set again
+--- @param bufnr number
+--- @param regions blink.pairs.Region[]
+--- @return boolean Whether the buffer has been parsed
+function pairs.set_regions(bufnr, regions)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.set_regions then return false end
+
+  return blink_pairs.set_regions(bufnr, regions)
+end
+
+-- Get the lines skipped when parsing a buffer for being longer than `max_line_length`
+--- @param bufnr number
+--- @return number[] 0-indexed lines
+function pairs.get_unparsed_lines(bufnr)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_unparsed_lines then return {} end
+
+  return blink_pairs.get_unparsed_lines(bufnr)
+end
+
+-- Get the generation of the buffer, incremented whenever a full parse replaces it, and whether a
+-- background parse is in progress
+--- @param bufnr number
+--- @return number? generation `nil` when the buffer isn't parsed
+--- @return boolean is_parsing
+function pairs.get_generation(bufnr)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.get_generation then return nil, false end
+
+  return blink_pairs.get_generation(bufnr)
+end
+
+-- Free the parsed buffer, which happens automatically when the buffer is unloaded
+--- @param bufnr number
+function pairs.free_buffer(bufnr)
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.free_buffer then return end
+
+  blink_pairs.free_buffer(bufnr)
+end
+
+--- @class (exact) blink.pairs.MemoryUsage
+--- @field buffers table<number, number> Approximate bytes used by each parsed buffer
+--- @field total number
+
+-- Get the approximate memory used by the parsed buffers
+--- @return blink.pairs.MemoryUsage
+function pairs.memory_usage()
+  local ok, blink_pairs = pcall(require, 'blink_pairs')
+  if not ok or not blink_pairs.memory_usage then return { buffers = {}, total = 0 } end
+
+  return blink_pairs.memory_usage()
 end
 
 return pairs
@@ -1,22 +1,117 @@
 local watcher = {
   --- @type table<number, boolean>
   watched_bufnrs = {},
+  --- Incremented on each full parse, so that a budgeted parse stops continuing once superseded
+  --- @type table<number, number>
+  parse_ids = {},
 }
 
+--- Continues a budgeted full parse on the next event loop tick until the whole buffer is parsed,
+--- redrawing the newly parsed lines
+--- @param bufnr number
+--- @param parse_id number
+local function continue_parse(bufnr, parse_id)
+  vim.schedule(function()
+    if watcher.parse_ids[bufnr] ~= parse_id or not vim.api.nvim_buf_is_valid(bufnr) then return end
+
+    local get_lines = function(start, end_) return vim.api.nvim_buf_get_lines(bufnr, start, end_, false) end
+    local budget = require('blink.pairs.config').parse_budget or nil
+    local did_parse, start_line, end_line, done =
+      require('blink.pairs.rust').continue_parse(bufnr, vim.bo[bufnr].filetype, get_lines, budget)
+    if not did_parse then return end
+
+    if end_line > start_line then vim.api.nvim__buf_redraw_range(bufnr, start_line, end_line) end
+    if not done then continue_parse(bufnr, parse_id) end
+  end)
+end
+
+--- Polls the generation of the buffer until its background parse finishes, then redraws the buffer
+--- when the new parse was swapped in
+--- @param bufnr number
+--- @param parse_id number
+--- @param generation? number Generation before the background parse started
+local function wait_for_background_parse(bufnr, parse_id, generation)
+  local timer = assert(vim.uv.new_timer())
+  timer:start(
+    5,
+    5,
+    vim.schedule_wrap(function()
+      if timer:is_closing() then return end
+      if watcher.parse_ids[bufnr] ~= parse_id or not vim.api.nvim_buf_is_valid(bufnr) then
+        return timer:close()
+      end
+
+      local new_generation, is_parsing = require('blink.pairs.rust').get_generation(bufnr)
+      if is_parsing then return end
+      timer:close()
+
+      if new_generation ~= nil and new_generation ~= generation then
+        vim.api.nvim__buf_redraw_range(bufnr, 0, vim.api.nvim_buf_line_count(bufnr))
+      end
+    end)
+  )
+end
+
+--- Starts a full parse of the buffer on a background thread
+--- @param bufnr number
+--- @return boolean Whether the buffer is parseable
+local function parse_buffer_async(bufnr)
+  local rust = require('blink.pairs.rust')
+  local generation = rust.get_generation(bufnr)
+  local lines = vim.api.nvim_buf_get_lines(bufnr, 0, -1, false)
+  if not rust.parse_buffer_async(bufnr, vim.bo[bufnr].filetype, lines) then return false end
+
+  watcher.parse_ids[bufnr] = (watcher.parse_ids[bufnr] or 0) + 1
+  wait_for_background_parse(bufnr, watcher.parse_ids[bufnr], generation)
+  return true
+end
+
 --- Runs a full parse on the buffer when start_line, old_end_line, and new_end_line are not provided.
---- Otherwise, incrementally parses the buffer.
+--- Otherwise, incrementally parses the buffer, continuing past new_end_line when the edit changed
+--- the state of the following lines (i.e. opened a block comment)
+---
+--- With a parse budget, full parses only parse up to the budget and continue on the following
+--- event loop ticks. With `parse_in_background`, full parses run on a background thread instead
 --- @param bufnr number
 --- @param start_line? number
 --- @param old_end_line? number
 --- @param new_end_line? number
 --- @return boolean Whether the buffer is parseable
 local function parse_buffer(bufnr, start_line, old_end_line, new_end_line)
+  if start_line == nil and require('blink.pairs.config').parse_in_background then
+    return parse_buffer_async(bufnr)
+  end
+
   local start_time = vim.uv.hrtime()
+  local budget = require('blink.pairs.config').parse_budget or nil
+  local is_full_parse = start_line == nil
 
-  local lines = vim.api.nvim_buf_get_lines(bufnr, start_line or 0, new_end_line or -1, false)
+  -- Budgeted full parses fetch the lines as needed
+  local lines = (is_full_parse and budget ~= nil) and {}
+    or vim.api.nvim_buf_get_lines(bufnr, start_line or 0, new_end_line or -1, false)
+  local get_lines = function(start, end_) return vim.api.nvim_buf_get_lines(bufnr, start, end_, false) end
 
   local rust = require('blink.pairs.rust')
-  local did_parse = rust.parse_buffer(bufnr, vim.bo[bufnr].filetype, lines, start_line, old_end_line, new_end_line)
+  local did_parse, end_line, done = rust.parse_buffer(
+    bufnr,
+    vim.bo[bufnr].filetype,
+    lines,
+    start_line,
+    old_end_line,
+    new_end_line,
+    get_lines,
+    budget
+  )
+
+  -- Lines after the edited range changed, so they must be redrawn
+  if did_parse and new_end_line ~= nil and end_line ~= nil and end_line > new_end_line then
+    vim.api.nvim__buf_redraw_range(bufnr, new_end_line, end_line)
+  end
+
+  if did_parse and is_full_parse then
+    watcher.parse_ids[bufnr] = (watcher.parse_ids[bufnr] or 0) + 1
+    if not done then continue_parse(bufnr, watcher.parse_ids[bufnr]) end
+  end
 
   if did_parse and require('blink.pairs.config').debug then
     vim.print('parsing time: ' .. (vim.uv.hrtime() - start_time) / 1e6 .. ' ms')
@@ -39,7 +134,11 @@ function watcher.attach(bufnr)
 
   local last_changedtick = 0
   vim.api.nvim_buf_attach(bufnr, false, {
-    on_detach = function() watcher.watched_bufnrs[bufnr] = nil end,
+    on_detach = function()
+      watcher.watched_bufnrs[bufnr] = nil
+      watcher.parse_ids[bufnr] = nil
+      require('blink.pairs.rust').free_buffer(bufnr)
+    end,
 
     -- Full parse
     on_reload = function() parse_buffer(bufnr) end,
@@ -55,10 +154,13 @@ function watcher.attach(bufnr)
       if changedtick == last_changedtick then return end
       last_changedtick = changedtick
 
-      local did_incremental_parse = parse_buffer(bufnr, start, old_end, new_end)
+      local did_parse = parse_buffer(bufnr, start, old_end, new_end)
+
+      -- the buffer may have been reset after a panic, so try a full parse
+      if not did_parse then did_parse = parse_buffer(bufnr) end
 
       -- no longer parseable, detach
-      if not did_incremental_parse then
+      if not did_parse then
         watcher.watched_bufnrs[bufnr] = nil
         return true
       end
@@ -1,20 +1,73 @@
 use syn::parse::{Parse, ParseStream};
 use syn::token::{Colon, Comma, FatArrow};
 use syn::{braced, bracketed, Result};
-use syn::{Ident, LitStr};
+use syn::{Ident, LitInt, LitStr};
 
 use std::collections::{HashMap, HashSet};
 
+/// Condition on the text before a pattern, set with flags following any pattern, i.e.
+/// `"#" after_whitespace` or `"=begin" at_line_start => "=end" at_line_start`. The predicates
+/// apply wherever the pattern is used. Mirrors `Predicate` in the parser, which the generated
+/// code calls
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// `at_line_start`
+    AtLineStart,
+    /// `after_indent`, at the start of the line after any spaces and tabs
+    AfterIndent,
+    /// `after_whitespace`, at the start of the line or after whitespace
+    AfterWhitespace,
+    /// `not_after_identifier`
+    NotAfterIdentifier,
+    /// `not_after "$"`, not directly after any of the ASCII characters
+    NotAfter(String),
+}
+
+/// How characters are escaped inside a string, block string or block comment, set with
+/// `escape none`, `escape backslash`, `escape doubled` or `escape "`"` following the closing
+/// pattern. Mirrors `Escape` in the parser
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    None,
+    Backslash,
+    /// Doubling the closing pattern, i.e. `''` in SQL
+    Doubled,
+    Char(u8),
+}
+
+#[derive(Default)]
+struct Flags {
+    names: HashSet<String>,
+    escape: Option<Escape>,
+    max_chars: Option<usize>,
+}
+
 pub struct MatcherDef {
     pub name: Ident,
     pub delimiters: Vec<(String, String)>,
     pub line_comments: Vec<String>,
-    pub block_comments: Vec<(String, String)>,
+    /// Opening and closing pattern, and whether the comments may be nested
+    pub block_comments: Vec<(String, String, bool)>,
     pub strings: Vec<String>,
-    pub chars: Vec<String>,
+    /// Opening quote, and the maximum number of characters between the quotes
+    pub chars: Vec<(String, usize)>,
     pub block_strings: Vec<(String, String)>,
     pub inline_spans: HashMap<String, (String, String)>,
     pub block_spans: HashMap<String, (String, String)>,
+    /// Opening keyword, middle keywords and closing keyword
+    pub keyword_pairs: Vec<(String, Vec<String>, String)>,
+    /// Opening pattern of the string or block string, and the opening and closing pattern of
+    /// the interpolation
+    pub interpolations: Vec<(String, String, String)>,
+    /// Opening pattern, whether the terminator may be indented and whether whitespace is
+    /// allowed before the terminator
+    pub heredocs: Vec<(String, bool, bool)>,
+    /// Opening and closing pattern of regex literals, i.e. `/[(]/`
+    pub regexes: Vec<(String, String)>,
+    /// Conditions on the text before each pattern
+    pub predicates: HashMap<String, Vec<Predicate>>,
+    /// Escapes inside the strings, block strings and block comments, by their opening pattern
+    pub escapes: HashMap<String, Escape>,
 }
 
 // Parse the incoming macro definition into a MatcherDef struct
@@ -32,6 +85,12 @@ impl Parse for MatcherDef {
         let mut block_strings = Vec::new();
         let mut inline_spans = HashMap::new();
         let mut block_spans = HashMap::new();
+        let mut keyword_pairs = Vec::new();
+        let mut interpolations = Vec::new();
+        let mut heredocs = Vec::new();
+        let mut regexes = Vec::new();
+        let mut predicates = HashMap::new();
+        let mut escapes = HashMap::new();
 
         fn get_single_char(token: LitStr) -> Result<String> {
             let value = token.value();
@@ -45,6 +104,100 @@ impl Parse for MatcherDef {
             }
         }
 
+        fn get_non_empty(token: LitStr) -> Result<String> {
+            let value = token.value();
+            if value.is_empty() {
+                Err(syn::Error::new(token.span(), "Delimiter must not be empty"))
+            } else {
+                Ok(value)
+            }
+        }
+
+        fn get_keyword(token: LitStr) -> Result<String> {
+            let value = token.value();
+            if value.is_empty()
+                || !value
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+            {
+                Err(syn::Error::new(
+                    token.span(),
+                    "Keyword must only contain ASCII letters, digits and underscores",
+                ))
+            } else {
+                Ok(value)
+            }
+        }
+
+        // Parses the flags following a pattern, i.e. `nested` in `"/*" => "*/" nested`, adding
+        // predicates to the pattern for any of the `Predicate` flags, i.e. `"#" after_whitespace`
+        fn parse_flags(
+            input: ParseStream,
+            allowed: &[&str],
+            predicates: &mut HashMap<String, Vec<Predicate>>,
+            pattern: &str,
+        ) -> Result<Flags> {
+            let mut flags = Flags::default();
+            while input.peek(Ident) {
+                let flag = input.parse::<Ident>()?;
+                let predicate = match flag.to_string().as_str() {
+                    "at_line_start" => Predicate::AtLineStart,
+                    "after_indent" => Predicate::AfterIndent,
+                    "after_whitespace" => Predicate::AfterWhitespace,
+                    "not_after_identifier" => Predicate::NotAfterIdentifier,
+                    "not_after" => {
+                        let chars = input.parse::<LitStr>()?;
+                        if !chars.value().is_ascii() {
+                            return Err(syn::Error::new(chars.span(), "Expected ASCII characters"));
+                        }
+                        Predicate::NotAfter(chars.value())
+                    }
+                    // max_chars 4
+                    "max_chars" if allowed.contains(&"max_chars") => {
+                        let max_chars = input.parse::<LitInt>()?;
+                        flags.max_chars = Some(max_chars.base10_parse()?);
+                        continue;
+                    }
+                    // escape none, escape backslash, escape doubled or escape "`"
+                    "escape" if allowed.contains(&"escape") => {
+                        flags.escape = Some(parse_escape(input)?);
+                        continue;
+                    }
+                    name if allowed.contains(&name) => {
+                        flags.names.insert(name.to_string());
+                        continue;
+                    }
+                    _ => return Err(syn::Error::new(flag.span(), "Unknown flag")),
+                };
+                predicates
+                    .entry(pattern.to_string())
+                    .or_default()
+                    .push(predicate);
+            }
+            Ok(flags)
+        }
+
+        fn parse_escape(input: ParseStream) -> Result<Escape> {
+            if input.peek(LitStr) {
+                let char = input.parse::<LitStr>()?;
+                return match char.value().as_bytes() {
+                    [byte] => Ok(Escape::Char(*byte)),
+                    _ => Err(syn::Error::new(
+                        char.span(),
+                        "Escape must be a single ASCII character",
+                    )),
+                };
+            }
+
+            let escape = input.parse::<Ident>()?;
+            match escape.to_string().as_str() {
+                "none" => Ok(Escape::None),
+                "backslash" => Ok(Escape::Backslash),
+                "doubled" => Ok(Escape::Doubled),
+                _ => Err(syn::Error::new(escape.span(), "Unknown escape")),
+            }
+        }
+
         // Parse each section
         while !content.is_empty() {
             let section_name = content.parse::<Ident>()?;
@@ -53,7 +206,7 @@ impl Parse for MatcherDef {
             let section_content;
             match section_name.to_string().as_str() {
                 "delimiters" | "line_comment" | "block_comment" | "string" | "char"
-                | "block_string" => {
+                | "block_string" | "keyword_pairs" | "interpolation" | "heredoc" | "regex" => {
                     bracketed!(section_content in content);
                 }
                 "inline_span" | "block_span" => {
@@ -65,9 +218,11 @@ impl Parse for MatcherDef {
             match section_name.to_string().as_str() {
                 "delimiters" => {
                     while !section_content.is_empty() {
-                        let open = get_single_char(section_content.parse::<LitStr>()?)?;
+                        let open = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
                         section_content.parse::<FatArrow>()?;
-                        let close = get_single_char(section_content.parse::<LitStr>()?)?;
+                        let close = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &close)?;
                         delimiters.push((open, close));
 
                         if !section_content.is_empty() {
@@ -77,18 +232,32 @@ impl Parse for MatcherDef {
                 }
                 "line_comment" => {
                     while !section_content.is_empty() {
-                        line_comments.push(section_content.parse::<LitStr>()?.value());
+                        let comment = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &comment)?;
+                        line_comments.push(comment);
                         if !section_content.is_empty() {
                             section_content.parse::<Comma>()?;
                         }
                     }
                 }
                 "block_comment" => {
+                    // "/*" => "*/" or "/*" => "*/" nested
                     while !section_content.is_empty() {
                         let open = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
                         section_content.parse::<FatArrow>()?;
                         let close = section_content.parse::<LitStr>()?.value();
-                        block_comments.push((open, close));
+
+                        let flags = parse_flags(
+                            &section_content,
+                            &["nested", "escape"],
+                            &mut predicates,
+                            &close,
+                        )?;
+                        if let Some(escape) = flags.escape {
+                            escapes.insert(open.clone(), escape);
+                        }
+                        block_comments.push((open, close, flags.names.contains("nested")));
 
                         if !section_content.is_empty() {
                             section_content.parse::<Comma>()?;
@@ -97,7 +266,13 @@ impl Parse for MatcherDef {
                 }
                 "string" => {
                     while !section_content.is_empty() {
-                        strings.push(section_content.parse::<LitStr>()?.value());
+                        let delim = section_content.parse::<LitStr>()?.value();
+                        let flags =
+                            parse_flags(&section_content, &["escape"], &mut predicates, &delim)?;
+                        if let Some(escape) = flags.escape {
+                            escapes.insert(delim.clone(), escape);
+                        }
+                        strings.push(delim);
                         if !section_content.is_empty() {
                             section_content.parse::<Comma>()?;
                         }
@@ -106,7 +281,9 @@ impl Parse for MatcherDef {
                 "char" => {
                     while !section_content.is_empty() {
                         let delim = get_single_char(section_content.parse::<LitStr>()?)?;
-                        chars.push(delim);
+                        let flags =
+                            parse_flags(&section_content, &["max_chars"], &mut predicates, &delim)?;
+                        chars.push((delim, flags.max_chars.unwrap_or(1)));
                         if !section_content.is_empty() {
                             section_content.parse::<Comma>()?;
                         }
@@ -115,8 +292,14 @@ impl Parse for MatcherDef {
                 "block_string" => {
                     while !section_content.is_empty() {
                         let open = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
                         section_content.parse::<FatArrow>()?;
                         let close = section_content.parse::<LitStr>()?.value();
+                        let flags =
+                            parse_flags(&section_content, &["escape"], &mut predicates, &close)?;
+                        if let Some(escape) = flags.escape {
+                            escapes.insert(open.clone(), escape);
+                        }
                         block_strings.push((open, close));
 
                         if !section_content.is_empty() {
@@ -129,8 +312,10 @@ impl Parse for MatcherDef {
                         let name = section_content.parse::<Ident>()?.to_string();
                         section_content.parse::<Colon>()?;
                         let open = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
                         section_content.parse::<FatArrow>()?;
                         let close = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &close)?;
                         inline_spans.insert(name, (open, close));
 
                         if !section_content.is_empty() {
@@ -143,8 +328,10 @@ impl Parse for MatcherDef {
                         let name = section_content.parse::<Ident>()?.to_string();
                         section_content.parse::<Colon>()?;
                         let open = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
                         section_content.parse::<FatArrow>()?;
                         let close = section_content.parse::<LitStr>()?.value();
+                        parse_flags(&section_content, &[], &mut predicates, &close)?;
                         block_spans.insert(name, (open, close));
 
                         if !section_content.is_empty() {
@@ -152,6 +339,89 @@ impl Parse for MatcherDef {
                         }
                     }
                 }
+                "keyword_pairs" => {
+                    // "do" => "end" or "if" after_indent => ["elseif", "else"] => "end"
+                    while !section_content.is_empty() {
+                        let open = get_keyword(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
+                        section_content.parse::<FatArrow>()?;
+
+                        let mut middles = Vec::new();
+                        if section_content.peek(syn::token::Bracket) {
+                            let middles_content;
+                            bracketed!(middles_content in section_content);
+                            while !middles_content.is_empty() {
+                                let middle = get_keyword(middles_content.parse::<LitStr>()?)?;
+                                parse_flags(&middles_content, &[], &mut predicates, &middle)?;
+                                middles.push(middle);
+                                if !middles_content.is_empty() {
+                                    middles_content.parse::<Comma>()?;
+                                }
+                            }
+                            section_content.parse::<FatArrow>()?;
+                        }
+
+                        let close = get_keyword(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &close)?;
+                        keyword_pairs.push((open, middles, close));
+
+                        if !section_content.is_empty() {
+                            section_content.parse::<Comma>()?;
+                        }
+                    }
+                }
+                "interpolation" => {
+                    // "`" => "${" => "}"
+                    while !section_content.is_empty() {
+                        let string = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        section_content.parse::<FatArrow>()?;
+                        let open = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
+                        section_content.parse::<FatArrow>()?;
+                        let close = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        interpolations.push((string, open, close));
+
+                        if !section_content.is_empty() {
+                            section_content.parse::<Comma>()?;
+                        }
+                    }
+                }
+                "heredoc" => {
+                    // "<<" or "<<-" indented spaced
+                    while !section_content.is_empty() {
+                        let open = get_non_empty(section_content.parse::<LitStr>()?)?;
+
+                        let flags = parse_flags(
+                            &section_content,
+                            &["indented", "spaced"],
+                            &mut predicates,
+                            &open,
+                        )?;
+                        heredocs.push((
+                            open,
+                            flags.names.contains("indented"),
+                            flags.names.contains("spaced"),
+                        ));
+
+                        if !section_content.is_empty() {
+                            section_content.parse::<Comma>()?;
+                        }
+                    }
+                }
+                "regex" => {
+                    // "/" => "/" or "~/" => "/"
+                    while !section_content.is_empty() {
+                        let open = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        parse_flags(&section_content, &[], &mut predicates, &open)?;
+                        section_content.parse::<FatArrow>()?;
+                        let close = get_non_empty(section_content.parse::<LitStr>()?)?;
+                        regexes.push((open, close));
+
+                        if !section_content.is_empty() {
+                            section_content.parse::<Comma>()?;
+                        }
+                    }
+                }
                 _ => return Err(syn::Error::new(section_name.span(), "Unknown section name")),
             }
 
@@ -170,6 +440,12 @@ impl Parse for MatcherDef {
             block_strings,
             inline_spans,
             block_spans,
+            keyword_pairs,
+            interpolations,
+            heredocs,
+            regexes,
+            predicates,
+            escapes,
         })
     }
 }
@@ -194,7 +470,7 @@ pub fn collect_tokens(def: &MatcherDef) -> Vec<u8> {
         }
     }
 
-    for (open, close) in &def.block_comments {
+    for (open, close, _) in &def.block_comments {
         for c in open.bytes() {
             all_tokens.insert(c);
         }
@@ -218,7 +494,7 @@ pub fn collect_tokens(def: &MatcherDef) -> Vec<u8> {
         }
     }
 
-    for s in &def.chars {
+    for (s, _) in &def.chars {
         for c in s.bytes() {
             all_tokens.insert(c);
         }
@@ -242,8 +518,55 @@ pub fn collect_tokens(def: &MatcherDef) -> Vec<u8> {
         }
     }
 
+    for (_, open, close) in &def.interpolations {
+        for c in open.bytes() {
+            all_tokens.insert(c);
+        }
+        for c in close.bytes() {
+            all_tokens.insert(c);
+        }
+    }
+
+    // The terminator is found by the parser, see `parse`
+    for (open, _, _) in &def.heredocs {
+        for c in open.bytes() {
+            all_tokens.insert(c);
+        }
+    }
+
+    for (open, close) in &def.regexes {
+        for c in open.bytes().chain(close.bytes()) {
+            all_tokens.insert(c);
+        }
+    }
+
+    for escape in def.escapes.values() {
+        if let Escape::Char(c) = escape {
+            all_tokens.insert(*c);
+        }
+    }
+
+    // Keywords are found separately, see `collect_keyword_starts`
+
     // Convert to sorted vector
     let mut tokens_vec: Vec<u8> = all_tokens.into_iter().collect();
     tokens_vec.sort();
     tokens_vec
 }
+
+// Helper function to collect the unique first bytes of the keywords
+pub fn collect_keyword_starts(def: &MatcherDef) -> Vec<u8> {
+    let mut keyword_starts = def
+        .keyword_pairs
+        .iter()
+        .flat_map(|(open, middles, close)| {
+            std::iter::once(open)
+                .chain(middles)
+                .chain(std::iter::once(close))
+        })
+        .map(|keyword| keyword.as_bytes()[0])
+        .collect::<Vec<_>>();
+    keyword_starts.sort();
+    keyword_starts.dedup();
+    keyword_starts
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::parse_macro_input;
@@ -6,7 +8,7 @@ mod config;
 mod lookahead;
 mod matcher;
 
-use config::{collect_tokens, MatcherDef};
+use config::{collect_keyword_starts, collect_tokens, Escape, MatcherDef};
 use lookahead::{calculate_max_lookahead, generate_lookahead_extractors};
 use matcher::{create_match_header, MatchArm};
 
@@ -16,44 +18,116 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
     let max_lookahead = calculate_max_lookahead(&def);
     let all_tokens = collect_tokens(&def);
     let token_literals = all_tokens.iter().map(|&t| quote! { #t });
+    let keyword_starts = collect_keyword_starts(&def);
     let lookahead_extractors = generate_lookahead_extractors(max_lookahead);
 
     // Generate match arms for all patterns
     let mut match_arms = Vec::new();
+    let builder = |pattern: &str| {
+        MatchArm::builder(pattern.to_string(), max_lookahead)
+            .predicates(def.predicates.get(pattern).cloned().unwrap_or_default())
+    };
+
+    // Doubled closing patterns stay in the state, i.e. `""` in C# verbatim strings
+    let doubled_arm = |close: &str, arm: MatchArm, output_state| {
+        arm.ignore_escaped()
+            .if_condition(quote! { doubled_at(line, token.col, #close) })
+            .body(quote! {
+                skip_to(tokens, token.col + 2 * #close.len());
+                #output_state
+            })
+            .build()
+    };
+    let is_doubled = |open: &str| def.escapes.get(open) == Some(&Escape::Doubled);
 
     // Order matters, we want to prioritize:
     // - block strings and block comments
     // - line comments, strings, and chars
     // - finally, delimiters
 
-    // 1. Block comment patterns
-    for (open, close) in &def.block_comments {
-        let open_arm = MatchArm::builder(open.to_string(), max_lookahead).body(quote! {
+    // Interpolation patterns, which take priority over closing the string containing them.
+    // The parser handles the code inside and closing the interpolation
+    for (string, open, close) in &def.interpolations {
+        let body = quote! {
+            matches.push(Match::new_with_stack(Kind::Opening, Token::Delimiter(Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col, stack.len()));
+            // Skip tokens based on length of pattern
+            for _ in 1..#open.len() {
+                tokens.next();
+            }
+            State::InInterpolation(Box::new(Interpolation::new(state.clone(), #open, #close)))
+        };
+        if def.strings.contains(string) {
+            let arm = builder(open)
+                .ignore_escaped()
+                .input_state_with_str(quote! { State::InString }, string)
+                .body(body.clone());
+            match_arms.push(arm.build());
+        }
+        if def.block_strings.iter().any(|(o, _)| o == string) {
+            let arm = builder(open)
+                .ignore_escaped()
+                .input_state_with_str(quote! { State::InBlockString }, string)
+                .body(body);
+            match_arms.push(arm.build());
+        }
+    }
+
+    // 1. Block comment patterns, where the state holds the depth of nested comments
+    for (open, close, nested) in &def.block_comments {
+        let open_arm = builder(open).body(quote! {
             matches.push(Match::new(
                 Kind::Opening,
-                Token::BlockComment(#open, #close),
+                Token::BlockComment(Cow::Borrowed(#open), Cow::Borrowed(#close)),
                 token.col,
             ));
             // Skip tokens based on length of pattern
             for _ in 1..#open.len() {
                 tokens.next();
             }
-            State::InBlockComment(#open)
+            State::InBlockComment(Cow::Borrowed(#open), 1)
         });
         match_arms.push(open_arm.build());
 
-        let close_arm = MatchArm::builder(close.to_string(), max_lookahead)
-            .input_state(quote! { State::InBlockComment(#open) })
+        // Only the outermost comment gets matches
+        if *nested {
+            let nested_open_arm = builder(open)
+                .input_state_with_str_and_depth(quote! { State::InBlockComment }, open)
+                .body(quote! {
+                    // Skip tokens based on length of pattern
+                    for _ in 1..#open.len() {
+                        tokens.next();
+                    }
+                    State::InBlockComment(Cow::Borrowed(#open), depth + 1)
+                });
+            match_arms.push(nested_open_arm.build());
+        }
+
+        if is_doubled(open) {
+            match_arms.push(doubled_arm(
+                close,
+                builder(close)
+                    .input_state_with_str_and_depth(quote! { State::InBlockComment }, open),
+                quote! { State::InBlockComment(Cow::Borrowed(#open), *depth) },
+            ));
+        }
+
+        let close_arm = builder(close)
+            .ignore_escaped()
+            .input_state_with_str_and_depth(quote! { State::InBlockComment }, open)
             .body(quote! {
-                matches.push(Match::new(
-                    Kind::Closing,
-                    Token::BlockComment(#open, #close),
-                    token.col,
-                ));
                 // Skip tokens based on length of pattern
                 for _ in 1..#close.len() {
                     tokens.next();
                 }
+                if *depth > 1 {
+                    return State::InBlockComment(Cow::Borrowed(#open), depth - 1);
+                }
+
+                matches.push(Match::new(
+                    Kind::Closing,
+                    Token::BlockComment(Cow::Borrowed(#open), Cow::Borrowed(#close)),
+                    token.col,
+                ));
                 State::Normal
             });
         match_arms.push(close_arm.build());
@@ -61,27 +135,35 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
 
     // 2. Block string patterns
     for (open, close) in &def.block_strings {
-        let open_arm = MatchArm::builder(open.to_string(), max_lookahead).body(quote! {
+        let open_arm = builder(open).body(quote! {
             matches.push(Match::new(
                 Kind::Opening,
-                Token::BlockString(#open, #close),
+                Token::BlockString(Cow::Borrowed(#open), Cow::Borrowed(#close)),
                 token.col,
             ));
             // Skip tokens based on length of pattern
             for _ in 1..#open.len() {
                 tokens.next();
             }
-            State::InBlockString(#open)
+            State::InBlockString(Cow::Borrowed(#open))
         });
         match_arms.push(open_arm.build());
 
-        let close_arm = MatchArm::builder(close.to_string(), max_lookahead)
+        if is_doubled(open) {
+            match_arms.push(doubled_arm(
+                close,
+                builder(close).input_state_with_str(quote! { State::InBlockString }, open),
+                quote! { State::InBlockString(Cow::Borrowed(#open)) },
+            ));
+        }
+
+        let close_arm = builder(close)
             .ignore_escaped()
-            .input_state(quote! { State::InBlockString(#open) })
+            .input_state_with_str(quote! { State::InBlockString }, open)
             .body(quote! {
                 matches.push(Match::new(
                     Kind::Closing,
-                    Token::BlockString(#open, #close),
+                    Token::BlockString(Cow::Borrowed(#open), Cow::Borrowed(#close)),
                     token.col,
                 ));
                 // Skip tokens based on length of pattern
@@ -93,22 +175,39 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
         match_arms.push(close_arm.build());
     }
 
+    // Heredoc patterns, longest first so that i.e. `<<-` takes priority over `<<`. The body
+    // starts on the next line, where the parser looks for the captured terminator
+    let mut heredocs = def.heredocs.iter().collect::<Vec<_>>();
+    heredocs.sort_by_key(|(open, _, _)| std::cmp::Reverse(open.len()));
+    for (open, indented, spaced) in heredocs {
+        let arm = builder(open)
+            .if_condition(quote! { heredoc_at(line, token.col, #open, #spaced).is_some() })
+            .body(quote! {
+                let (terminator, end) = heredoc_at(line, token.col, #open, #spaced).unwrap();
+                let heredoc = Heredoc::new(#open, terminator.to_string(), #indented);
+                matches.push(Match::new(Kind::Opening, heredoc.token(), token.col));
+                skip_to(tokens, end);
+                State::InHeredoc(Box::new(heredoc))
+            });
+        match_arms.push(arm.build());
+    }
+
     // 3. Block span patterns
     for (name, (open, close)) in &def.block_spans {
-        let arm = MatchArm::builder(open.to_string(), max_lookahead).body(quote! {
-            matches.push(Match::new(Kind::Opening, Token::BlockSpan(#name, #open, #close), token.col));
+        let arm = builder(open).body(quote! {
+            matches.push(Match::new(Kind::Opening, Token::BlockSpan(Cow::Borrowed(#name), Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col));
             // Skip tokens based on length of pattern
             for _ in 1..#open.len() {
                 tokens.next();
             }
-            State::InBlockSpan(#name)
+            State::InBlockSpan(Cow::Borrowed(#name))
         });
         match_arms.push(arm.build());
 
-        let close_arm = MatchArm::builder(close.to_string(), max_lookahead)
-            .input_state(quote! { State::InBlockSpan(#name) })
+        let close_arm = builder(close)
+            .input_state_with_str(quote! { State::InBlockSpan }, name)
             .body(quote! {
-                matches.push(Match::new(Kind::Closing, Token::BlockSpan(#name, #open, #close), token.col));
+                matches.push(Match::new(Kind::Closing, Token::BlockSpan(Cow::Borrowed(#name), Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col));
                 // Skip tokens based on length of pattern
                 for _ in 1..#close.len() {
                     tokens.next();
@@ -120,16 +219,14 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
 
     // 4. Line comment patterns
     for comment in &def.line_comments {
-        let arm = MatchArm::builder(comment.to_string(), max_lookahead)
-            .ignore_escaped()
-            .body(quote! {
-                matches.push(Match::line_comment(#comment, token.col));
-                // Skip tokens based on length of pattern
-                for _ in 1..#comment.len() {
-                    tokens.next();
-                }
-                State::InLineComment
-            });
+        let arm = builder(comment).ignore_escaped().body(quote! {
+            matches.push(Match::line_comment(#comment, token.col));
+            // Skip tokens based on length of pattern
+            for _ in 1..#comment.len() {
+                tokens.next();
+            }
+            State::InLineComment
+        });
         // TODO: skip tokens based on length of pattern
         match_arms.push(arm.build());
     }
@@ -137,23 +234,31 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
     // 5. String patterns
     for delim in &def.strings {
         // Opening string
-        let open_arm = MatchArm::builder(delim.to_string(), max_lookahead).body(quote! {
-            matches.push(Match::new(Kind::Opening, Token::String(#delim), token.col));
+        let open_arm = builder(delim).body(quote! {
+            matches.push(Match::new(Kind::Opening, Token::String(Cow::Borrowed(#delim)), token.col));
             // Skip tokens based on length of pattern
             for _ in 1..#delim.len() {
                 tokens.next();
             }
-            State::InString(#delim)
+            State::InString(Cow::Borrowed(#delim))
         });
         // TODO: skip tokens based on length of pattern
         match_arms.push(open_arm.build());
 
+        if is_doubled(delim) {
+            match_arms.push(doubled_arm(
+                delim,
+                builder(delim).input_state_with_str(quote! { State::InString }, delim),
+                quote! { State::InString(Cow::Borrowed(#delim)) },
+            ));
+        }
+
         // Closing string
-        let close_arm = MatchArm::builder(delim.to_string(), max_lookahead)
+        let close_arm = builder(delim)
             .ignore_escaped()
-            .input_state(quote! { State::InString(#delim) })
+            .input_state_with_str(quote! { State::InString }, delim)
             .body(quote! {
-                matches.push(Match::new(Kind::Closing, Token::String(#delim), token.col));
+                matches.push(Match::new(Kind::Closing, Token::String(Cow::Borrowed(#delim)), token.col));
                 // Skip tokens based on length of pattern
                 for _ in 1..#delim.len() {
                     tokens.next();
@@ -165,28 +270,32 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
     }
 
     // 6. Character literal patterns
-    for delim in &def.chars {
-        // TODO: handle escaped
+    for (delim, max_chars) in &def.chars {
         let delim_byte = delim.as_bytes()[0];
-        let arm = MatchArm::builder(delim.to_string(), max_lookahead)
-            .non_adjacent()
-            .if_condition(quote! { token_1_byte == #delim_byte && (token_1_distance == 1 || token_1_distance == 2) })
+        let arm = builder(delim)
+            .if_condition(quote! { char_literal_at(line, token.col, #delim_byte, #max_chars).is_some() })
             .body(quote! {
-                matches.push(Match::new(Kind::Opening, Token::String(#delim), token.col));
-                matches.push(Match::new(Kind::Closing, Token::String(#delim), token.col + token_1_distance));
-                tokens.next(); // Skip next token
+                let close_col = char_literal_at(line, token.col, #delim_byte, #max_chars).unwrap();
+                matches.push(Match::new(Kind::Opening, Token::String(Cow::Borrowed(#delim)), token.col));
+                matches.push(Match::new(Kind::Closing, Token::String(Cow::Borrowed(#delim)), close_col));
+                // Skip the tokens inside the literal, i.e. `{` in `'{'`
+                skip_to(tokens, close_col + 1);
                 State::Normal
             });
         match_arms.push(arm.build());
+    }
 
-        let arm = MatchArm::builder(delim.to_string(), max_lookahead)
-            .non_adjacent()
-            .if_condition(quote! { token_2_byte == #delim_byte && token_2_distance == 2 })
+    // Regex literal patterns, which only open after operators and some keywords
+    for (open, close) in &def.regexes {
+        let arm = builder(open)
+            .if_condition(quote! { regex_at(line, token.col, #open, #close).is_some() })
             .body(quote! {
-                matches.push(Match::new(Kind::Opening, Token::String(#delim), token.col));
-                matches.push(Match::new(Kind::Closing, Token::String(#delim), token.col + token_2_distance));
-                tokens.next(); // Skip 2 tokens
-                tokens.next();
+                let close_col = regex_at(line, token.col, #open, #close).unwrap();
+                let token_ = Token::Regex(Cow::Borrowed(#open), Cow::Borrowed(#close));
+                matches.push(Match::new(Kind::Opening, token_.clone(), token.col));
+                matches.push(Match::new(Kind::Closing, token_, close_col));
+                // Skip the tokens inside the regex, i.e. `(` in `/[(]/`
+                skip_to(tokens, close_col + #close.len());
                 State::Normal
             });
         match_arms.push(arm.build());
@@ -194,20 +303,20 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
 
     // 7. Inline span patterns
     for (name, (open, close)) in &def.inline_spans {
-        let arm = MatchArm::builder(open.to_string(), max_lookahead).body(quote! {
-            matches.push(Match::new(Kind::Opening, Token::InlineSpan(#name, #open, #close), token.col));
+        let arm = builder(open).body(quote! {
+            matches.push(Match::new(Kind::Opening, Token::InlineSpan(Cow::Borrowed(#name), Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col));
             // Skip tokens based on length of pattern
             for _ in 1..#open.len() {
                 tokens.next();
             }
-            State::InInlineSpan(#name)
+            State::InInlineSpan(Cow::Borrowed(#name))
         });
         match_arms.push(arm.build());
 
-        let close_arm = MatchArm::builder(close.to_string(), max_lookahead)
-            .input_state(quote! { State::InInlineSpan(#name) })
+        let close_arm = builder(close)
+            .input_state_with_str(quote! { State::InInlineSpan }, name)
             .body(quote! {
-                matches.push(Match::new(Kind::Closing, Token::InlineSpan(#name, #open, #close), token.col));
+                matches.push(Match::new(Kind::Closing, Token::InlineSpan(Cow::Borrowed(#name), Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col));
                 // Skip tokens based on length of pattern
                 for _ in 1..#close.len() {
                     tokens.next();
@@ -217,29 +326,109 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
         match_arms.push(close_arm.build());
     }
 
-    // 8. Delimiter patterns
-    for (open, close) in &def.delimiters {
-        let close_byte = close.as_bytes()[0];
+    // 8. Delimiter patterns, where the stack holds the index of each open pair. Pairs may share
+    // a closing delimiter, in which case it closes whichever of them is on top of the stack
+    let pairs = def
+        .delimiters
+        .iter()
+        .map(|(open, close)| (open, close))
+        .chain(
+            def.keyword_pairs
+                .iter()
+                .map(|(open, _, close)| (open, close)),
+        )
+        .collect::<Vec<_>>();
+    let pair_literals = pairs.iter().map(|(open, close)| quote! { (#open, #close) });
+    let closing_body = |close: &str| {
+        let first_idx = pairs.iter().position(|(_, c)| *c == close).unwrap();
+        quote! {
+            let idx = match stack.last() {
+                Some(&idx) if PAIRS[idx].1 == #close => {
+                    stack.pop();
+                    idx
+                }
+                _ => #first_idx,
+            };
+            matches.push(Match::new_with_stack(Kind::Closing, Token::Delimiter(Cow::Borrowed(PAIRS[idx].0), Cow::Borrowed(#close)), token.col, stack.len()));
+        }
+    };
 
+    let mut delimiter_arms = Vec::new();
+    for (idx, (open, close)) in def.delimiters.iter().enumerate() {
         // Opening delimiter
-        let open_arm = MatchArm::builder(open.to_string(), max_lookahead).body(quote! {
-            matches.push(Match::new_with_stack(Kind::Opening, Token::Delimiter(#open, #close), token.col, stack.len()));
-            stack.push(#close_byte);
+        let open_arm = builder(open).body(quote! {
+            matches.push(Match::new_with_stack(Kind::Opening, Token::Delimiter(Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col, stack.len()));
+            stack.push(#idx);
+            // Skip tokens based on length of pattern
+            for _ in 1..#open.len() {
+                tokens.next();
+            }
             State::Normal
         });
-        match_arms.push(open_arm.build());
+        delimiter_arms.push((open.len(), open_arm.build()));
 
-        // Closing delimiter
-        let close_arm = MatchArm::builder(close.to_string(), max_lookahead).body(quote! {
-            if let Some(closing) = stack.last() {
-                if token.byte == *closing {
-                    stack.pop();
-                }
+        // Closing delimiter, only once per closing pattern
+        if def.delimiters[..idx].iter().any(|(_, c)| c == close) {
+            continue;
+        }
+        let body = closing_body(close);
+        let close_arm = builder(close).body(quote! {
+            #body
+            // Skip tokens based on length of pattern
+            for _ in 1..#close.len() {
+                tokens.next();
             }
-            matches.push(Match::new_with_stack(Kind::Closing, Token::Delimiter(#open, #close), token.col, stack.len()));
             State::Normal
         });
-        match_arms.push(close_arm.build());
+        delimiter_arms.push((close.len(), close_arm.build()));
+    }
+    // Longest patterns first, so that i.e. `{{` takes priority over `{`
+    delimiter_arms.sort_by_key(|(len, _)| std::cmp::Reverse(*len));
+    match_arms.extend(delimiter_arms.into_iter().map(|(_, arm)| arm));
+
+    // 9. Keyword pairs, which the tokenizer only emits at the start of identifiers
+    let keyword_arm = |keyword: &str, body: proc_macro2::TokenStream| {
+        MatchArm::builder(keyword[..1].to_string(), max_lookahead)
+            .predicates(def.predicates.get(keyword).cloned().unwrap_or_default())
+            .if_condition(quote! { keyword_at(line, token.col, #keyword) })
+            .body(quote! {
+                #body
+                skip_keyword(tokens, token, #keyword);
+                State::Normal
+            })
+            .build()
+    };
+    let mut keywords = HashSet::new();
+    for (idx, (open, middles, close)) in def.keyword_pairs.iter().enumerate() {
+        let idx = def.delimiters.len() + idx;
+        if keywords.insert(open) {
+            match_arms.push(keyword_arm(
+                open,
+                quote! {
+                    matches.push(Match::new_with_stack(Kind::Opening, Token::Delimiter(Cow::Borrowed(#open), Cow::Borrowed(#close)), token.col, stack.len()));
+                    stack.push(#idx);
+                },
+            ));
+        }
+
+        for middle in middles {
+            if keywords.insert(middle) {
+                match_arms.push(keyword_arm(
+                    middle,
+                    quote! {
+                        let stack_height = match stack.last() {
+                            Some(&idx) if PAIRS[idx].1 == #close => stack.len() - 1,
+                            _ => stack.len(),
+                        };
+                        matches.push(Match::new_with_stack(Kind::Middle, Token::Delimiter(Cow::Borrowed(#middle), Cow::Borrowed(#close)), token.col, stack_height));
+                    },
+                ));
+            }
+        }
+
+        if keywords.insert(close) {
+            match_arms.push(keyword_arm(close, closing_body(close)));
+        }
     }
 
     // Add fallback pattern
@@ -254,6 +443,71 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
         }
     };
 
+    // Escapes which differ from the defaults in `Matcher::escape`
+    let escape_literal = |escape: &Escape| match escape {
+        Escape::None => quote! { Escape::None },
+        Escape::Backslash => quote! { Escape::Backslash },
+        Escape::Doubled => quote! { Escape::Doubled },
+        Escape::Char(byte) => quote! { Escape::Char(#byte) },
+    };
+    let mut escape_arms = Vec::new();
+    for delim in &def.strings {
+        if let Some(escape) = def.escapes.get(delim).map(escape_literal) {
+            escape_arms
+                .push(quote! { State::InString(state_str) if state_str == #delim => #escape });
+        }
+    }
+    for (open, _) in &def.block_strings {
+        if let Some(escape) = def.escapes.get(open).map(escape_literal) {
+            escape_arms
+                .push(quote! { State::InBlockString(state_str) if state_str == #open => #escape });
+        }
+    }
+    for (open, _, _) in &def.block_comments {
+        if let Some(escape) = def.escapes.get(open).map(escape_literal) {
+            escape_arms.push(
+                quote! { State::InBlockComment(state_str, _) if state_str == #open => #escape },
+            );
+        }
+    }
+    let escape_fn = (!escape_arms.is_empty()).then(|| {
+        quote! {
+            fn escape(&self, state: &State) -> Escape {
+                match state {
+                    #(#escape_arms,)*
+                    State::InBlockComment(..) => Escape::None,
+                    _ => Escape::Backslash,
+                }
+            }
+        }
+    });
+
+    // Interpolations opening with a backslash, which the parser must check for before escaping
+    let mut interpolation_arms = Vec::new();
+    for (string, open, _) in def
+        .interpolations
+        .iter()
+        .filter(|(_, o, _)| o.starts_with('\\'))
+    {
+        let state = match def.strings.contains(string) {
+            true => quote! { State::InString(state_str) },
+            false => quote! { State::InBlockString(state_str) },
+        };
+        interpolation_arms.push(quote! {
+            #state if state_str == #string && line[col..].starts_with(#open) => true
+        });
+    }
+    let escape_opens_interpolation_fn = (!interpolation_arms.is_empty()).then(|| {
+        quote! {
+            fn escape_opens_interpolation(&self, state: &State, line: &str, col: usize) -> bool {
+                match state {
+                    #(#interpolation_arms,)*
+                    _ => false,
+                }
+            }
+        }
+    });
+
     let name = &def.name;
 
     // Generate the full implementation
@@ -262,11 +516,18 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
 
         impl Matcher for #name {
             const TOKENS: &[u8] = &[#(#token_literals),*];
+            const KEYWORD_STARTS: &[u8] = &[#(#keyword_starts),*];
+
+            #escape_fn
 
+            #escape_opens_interpolation_fn
+
+            #[allow(unused_variables)]
             fn call<I>(
                 &mut self,
                 matches: &mut Vec<Match>,
-                stack: &mut Vec<u8>,
+                stack: &mut Vec<usize>,
+                line: &str,
                 tokens: &mut MultiPeek<I>,
                 state: State,
                 token: CharPos,
@@ -275,6 +536,12 @@ pub fn define_matcher(input: TokenStream) -> TokenStream {
             where
                 I: Iterator<Item = CharPos>,
             {
+                use ::std::borrow::Cow;
+
+                /// Opening and closing pattern of each pair on the stack
+                #[allow(dead_code)]
+                const PAIRS: &[(&str, &str)] = &[#(#pair_literals),*];
+
                 // Generate lookahead tokens based on the calculated max lookahead
                 #lookahead_extractors
 
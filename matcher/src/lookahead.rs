@@ -17,7 +17,7 @@ pub fn calculate_max_lookahead(def: &MatcherDef) -> usize {
         max_len = max_len.max(comment.len());
     }
 
-    for (open, close) in &def.block_comments {
+    for (open, close, _) in &def.block_comments {
         max_len = max_len.max(open.len());
         max_len = max_len.max(close.len());
     }
@@ -26,10 +26,9 @@ pub fn calculate_max_lookahead(def: &MatcherDef) -> usize {
         max_len = max_len.max(s.len());
     }
 
-    for s in &def.chars {
-        // Always need to lookahead 2 extra bytes ahead for single-char strings
-        // So we can check for the second `'` in `'{'`
-        max_len = max_len.max(s.len() + 2);
+    // The closing quote is found by `char_literal_at`
+    for (s, _) in &def.chars {
+        max_len = max_len.max(s.len());
     }
 
     for (open, close) in &def.block_strings {
@@ -47,6 +46,20 @@ pub fn calculate_max_lookahead(def: &MatcherDef) -> usize {
         max_len = max_len.max(close.len());
     }
 
+    // The closing pattern is found by the parser, see `parse`
+    for (_, open, _) in &def.interpolations {
+        max_len = max_len.max(open.len());
+    }
+
+    for (open, _, _) in &def.heredocs {
+        max_len = max_len.max(open.len());
+    }
+
+    // The closing pattern is found by `regex_at`
+    for (open, _) in &def.regexes {
+        max_len = max_len.max(open.len());
+    }
+
     // Already have the first byte, so subtract 1
     max_len.saturating_sub(1)
 }
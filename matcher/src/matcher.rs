@@ -1,17 +1,19 @@
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 
+use crate::config::Predicate;
+
 /// Generates the match header for the given lookahead
 ///
 /// Examples:
 ///
 /// lookahead = 2
-/// Generates: (state, token.byte, token_1_byte, token_2_byte)
+/// Generates: (&state, token.byte, token_1_byte, token_2_byte, escaped)
 ///
 /// lookahead = 0
-/// Generates: (state, token.byte)
+/// Generates: (&state, token.byte, escaped)
 pub fn create_match_header(lookahead: usize) -> TokenStream2 {
-    let mut pattern_str = "(state, token.byte".to_string();
+    let mut pattern_str = "(&state, token.byte".to_string();
 
     for i in 0..lookahead {
         pattern_str.push_str(&format!(", token_{}_byte", i + 1));
@@ -42,7 +44,9 @@ pub struct MatchArm {
     pattern: String,
     lookahead: usize,
     adjacent: bool,
+    _predicates: Vec<Predicate>,
     _input_state: TokenStream2,
+    _input_state_condition: Option<TokenStream2>,
     _ignore_escaped: bool,
     _if_condition: Option<TokenStream2>,
     _body: Option<TokenStream2>,
@@ -55,20 +59,35 @@ impl MatchArm {
             pattern,
             lookahead,
             adjacent,
+            _predicates: vec![],
             _input_state: quote! { State::Normal },
+            _input_state_condition: None,
             _ignore_escaped: false,
             _if_condition: None,
             _body: None,
         }
     }
 
-    pub fn input_state(mut self, input_state: TokenStream2) -> Self {
-        self._input_state = input_state;
+    /// Matches a state variant holding a string, such as `State::InBlockComment("/*")`.
+    /// Since the state holds a `Cow`, we can't match against the string literal directly
+    /// so we bind it and compare in the if condition instead
+    pub fn input_state_with_str(mut self, variant: TokenStream2, value: &str) -> Self {
+        self._input_state = quote! { #variant(state_str) };
+        self._input_state_condition = Some(quote! { state_str == #value });
+        self
+    }
+
+    /// Same as `input_state_with_str`, also binding the depth of the state as `depth`, such as
+    /// `State::InBlockComment("/*", 2)`
+    pub fn input_state_with_str_and_depth(mut self, variant: TokenStream2, value: &str) -> Self {
+        self._input_state = quote! { #variant(state_str, depth) };
+        self._input_state_condition = Some(quote! { state_str == #value });
         self
     }
 
-    pub fn non_adjacent(mut self) -> Self {
-        self.adjacent = false;
+    /// Conditions on the text before the pattern, i.e. only matching at the start of the line
+    pub fn predicates(mut self, predicates: Vec<Predicate>) -> Self {
+        self._predicates = predicates;
         self
     }
 
@@ -122,14 +141,28 @@ impl MatchArm {
         let mut condition: TokenStream2 = condition.parse().unwrap();
 
         // Add if statement
-        if self.adjacent || self._if_condition.is_some() {
-            condition.extend(quote! { if });
-            if self.adjacent {
-                condition.extend(Self::adjacent_if_condition(&self.pattern));
-            }
-            if let Some(if_condition) = self._if_condition {
-                condition.extend(if_condition);
-            }
+        let mut if_conditions = vec![];
+        if let Some(input_state_condition) = self._input_state_condition {
+            if_conditions.push(input_state_condition);
+        }
+        if self.adjacent {
+            if_conditions.push(Self::adjacent_if_condition(&self.pattern));
+        }
+        for predicate in self._predicates {
+            let predicate = match predicate {
+                Predicate::AtLineStart => quote! { Predicate::AtLineStart },
+                Predicate::AfterIndent => quote! { Predicate::AfterIndent },
+                Predicate::AfterWhitespace => quote! { Predicate::AfterWhitespace },
+                Predicate::NotAfterIdentifier => quote! { Predicate::NotAfterIdentifier },
+                Predicate::NotAfter(chars) => quote! { Predicate::NotAfter(Cow::Borrowed(#chars)) },
+            };
+            if_conditions.push(quote! { #predicate.matches(line, token.col) });
+        }
+        if let Some(if_condition) = self._if_condition {
+            if_conditions.push(if_condition);
+        }
+        if !if_conditions.is_empty() {
+            condition.extend(quote! { if #((#if_conditions))&&* });
         }
 
         // Combine condition and body
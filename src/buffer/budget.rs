@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use mlua::prelude::*;
+
+/// Options kept with the buffer, applying to every parse of it
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ParseOptions {
+    /// Lines longer than this many bytes are skipped, as if they were empty, and reported by
+    /// `ParsedBuffer::unparsed_lines`, since minified files may have megabytes on a single line
+    pub max_line_length: Option<usize>,
+}
+
+impl ParseOptions {
+    /// Replaces the lines over the maximum length with empty lines, returning the indices of the
+    /// replaced lines
+    pub fn skip_long_lines<'a>(&self, lines: &[&'a str]) -> (Vec<&'a str>, Vec<usize>) {
+        let Some(max_line_length) = self.max_line_length else {
+            return (lines.to_vec(), vec![]);
+        };
+
+        let mut skipped = vec![];
+        let lines = lines
+            .iter()
+            .enumerate()
+            .map(|(idx, &line)| match line.len() > max_line_length {
+                true => {
+                    skipped.push(idx);
+                    ""
+                }
+                false => line,
+            })
+            .collect();
+        (lines, skipped)
+    }
+}
+
+/// Limits on how much of the buffer a single call to `ParsedBuffer::continue_parse` parses, so
+/// that huge buffers may be parsed across multiple event loop ticks
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ParseBudget {
+    pub max_time: Option<Duration>,
+    pub max_bytes: Option<usize>,
+}
+
+impl FromLua for ParseBudget {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        // { time_ms = 10, bytes = 1000000 }
+        let table = LuaTable::from_lua(value, lua)?;
+        Ok(Self {
+            max_time: table
+                .get::<Option<f64>>("time_ms")?
+                .map(|time_ms| Duration::from_secs_f64(time_ms.max(0.) / 1000.)),
+            max_bytes: table.get("bytes")?,
+        })
+    }
+}
+
+impl ParseBudget {
+    pub fn start(&self) -> BudgetTracker {
+        BudgetTracker {
+            budget: *self,
+            started_at: Instant::now(),
+            bytes: 0,
+        }
+    }
+}
+
+/// Time and bytes spent so far from a budget
+pub struct BudgetTracker {
+    budget: ParseBudget,
+    started_at: Instant,
+    bytes: usize,
+}
+
+impl BudgetTracker {
+    /// Number of the lines which fit in both the remaining bytes and `chunk_bytes`, parsing at
+    /// least one line so that each call makes progress
+    pub fn lines_within(&self, lines: &[String], chunk_bytes: usize) -> usize {
+        let max_bytes = match self.budget.max_bytes {
+            Some(max_bytes) => max_bytes.saturating_sub(self.bytes).min(chunk_bytes),
+            None => chunk_bytes,
+        };
+
+        let mut bytes = 0;
+        let count = lines
+            .iter()
+            .take_while(|line| {
+                bytes += line.len() + 1;
+                bytes <= max_bytes
+            })
+            .count();
+        count.max(1).min(lines.len())
+    }
+
+    pub fn spend(&mut self, lines: &[String]) {
+        self.bytes += lines.iter().map(|line| line.len() + 1).sum::<usize>();
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.budget
+            .max_bytes
+            .is_some_and(|max_bytes| self.bytes >= max_bytes)
+            || self
+                .budget
+                .max_time
+                .is_some_and(|max_time| self.started_at.elapsed() >= max_time)
+    }
+}
+
+/// Lines parsed by a call to `ParsedBuffer::continue_parse`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParseProgress {
+    pub start_line: usize,
+    /// Exclusive
+    pub end_line: usize,
+    /// Whether the whole buffer has been parsed
+    pub done: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ParseBudget, ParseOptions};
+    use crate::buffer::tests::assert_matches_full_parse;
+    use crate::buffer::ParsedBuffer;
+
+    #[test]
+    fn test_budgeted_parse() {
+        let mut lines = (0..3000)
+            .map(|idx| match idx % 3 {
+                0 => "int a() {",
+                1 => "  /* b(); */ c();",
+                _ => "}",
+            })
+            .collect::<Vec<_>>();
+        let get_lines = |lines: &[&str], start: usize, end: usize| {
+            lines[start.min(lines.len())..end.min(lines.len())]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        let budget = ParseBudget {
+            max_time: None,
+            max_bytes: Some(10_000),
+        };
+
+        // Parses up to the budget on each call
+        let mut buffer = ParsedBuffer::unparsed("c", ParseOptions::default()).unwrap();
+        let progress = buffer
+            .continue_parse("c", &budget, |start, end| get_lines(&lines, start, end))
+            .unwrap();
+        assert_eq!((progress.start_line, progress.done), (0, false));
+        assert!(progress.end_line < lines.len());
+        assert_eq!(buffer.line_matches(lines.len() - 1), None);
+
+        // Edits past the parsed lines drop the lines after the edit
+        let edit_line = progress.end_line - 1;
+        lines[edit_line] = "/*";
+        buffer.reparse_range(
+            "c",
+            &lines[edit_line..lines.len()],
+            Some(edit_line),
+            Some(lines.len()),
+            Some(lines.len()),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert!(!buffer.is_complete());
+
+        let mut end_line = edit_line;
+        while !buffer.is_complete() {
+            let progress = buffer
+                .continue_parse("c", &budget, |start, end| get_lines(&lines, start, end))
+                .unwrap();
+            assert_eq!(progress.start_line, end_line);
+            end_line = progress.end_line;
+        }
+        assert_eq!(end_line, lines.len());
+        assert_matches_full_parse(&buffer, &lines);
+    }
+
+    #[test]
+    fn test_long_lines() {
+        let mut lines = vec!["{", "  a(((((((((((", "}"];
+        let options = ParseOptions {
+            max_line_length: Some(10),
+        };
+        let mut buffer = ParsedBuffer::parse_with_options("c", &lines, options).unwrap();
+        assert_eq!(buffer.unparsed_lines(), &[1]);
+        assert_eq!(buffer.line_matches(1), Some(vec![]));
+        assert_eq!(buffer.match_pair(0, 0).unwrap().1.line, 2);
+
+        // Unparsed lines move along with the edits
+        lines.insert(0, "b();");
+        buffer.reparse_range("c", &lines[0..1], Some(0), Some(0), Some(1), |_, _| vec![]);
+        assert_eq!(buffer.unparsed_lines(), &[2]);
+        lines[2] = "  a();";
+        buffer.reparse_range("c", &lines[2..3], Some(2), Some(3), Some(3), |_, _| vec![]);
+        assert!(buffer.unparsed_lines().is_empty());
+        assert_matches_full_parse(&buffer, &lines);
+    }
+}
@@ -0,0 +1,194 @@
+use mlua::IntoLua;
+
+use crate::parser::{Kind, Match, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticReason {
+    /// Opening delimiter without a closing delimiter
+    UnmatchedOpening,
+    /// Closing delimiter without any opening delimiter
+    StrayClosing,
+    /// Closing delimiter that doesn't match the last opening delimiter, i.e. `(` closed by `]`
+    MismatchedClosing,
+    UnterminatedString,
+    UnterminatedBlockString,
+    UnterminatedBlockComment,
+    UnterminatedSpan,
+}
+
+impl DiagnosticReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticReason::UnmatchedOpening => "unmatched_opening",
+            DiagnosticReason::StrayClosing => "stray_closing",
+            DiagnosticReason::MismatchedClosing => "mismatched_closing",
+            DiagnosticReason::UnterminatedString => "unterminated_string",
+            DiagnosticReason::UnterminatedBlockString => "unterminated_block_string",
+            DiagnosticReason::UnterminatedBlockComment => "unterminated_block_comment",
+            DiagnosticReason::UnterminatedSpan => "unterminated_span",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub col: usize,
+    /// Text of the offending token, i.e. `(` or `/*`
+    pub token: String,
+    pub reason: DiagnosticReason,
+}
+
+impl Diagnostic {
+    fn new(line: usize, match_: &Match, reason: DiagnosticReason) -> Self {
+        let token = match match_.kind {
+            Kind::Closing => match_.token.closing().unwrap_or(match_.token.opening()),
+            _ => match_.token.opening(),
+        };
+        Self {
+            line,
+            col: match_.col,
+            token: token.to_string(),
+            reason,
+        }
+    }
+}
+
+impl IntoLua for Diagnostic {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+
+        table.set("line", self.line)?;
+        table.set("col", self.col)?;
+        table.set("token", self.token)?;
+        table.set("reason", self.reason.code())?;
+
+        (&table).into_lua(lua)
+    }
+}
+
+/// Walks all the matches in the buffer, reporting unbalanced delimiters and unterminated
+/// strings, comments and spans
+pub fn collect_diagnostics(matches_by_line: &[Vec<Match>]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let mut stack: Vec<(usize, &Match)> = vec![];
+    // Block comments, block strings and block spans may span multiple lines
+    let mut open_block: Option<(usize, &Match)> = None;
+
+    for (line_number, matches) in matches_by_line.iter().enumerate() {
+        // Strings and inline spans end at the end of the line
+        let mut open_inline: Option<&Match> = None;
+
+        for match_ in matches {
+            match (&match_.token, match_.kind) {
+                (Token::Delimiter(_, _), Kind::Opening) => stack.push((line_number, match_)),
+                (Token::Delimiter(_, _), Kind::Middle) => {}
+                (Token::Delimiter(_, _), _) => match stack.last() {
+                    Some((_, opening)) if match_.token.closes(&opening.token) => {
+                        stack.pop();
+                    }
+                    Some(_) => diagnostics.push(Diagnostic::new(
+                        line_number,
+                        match_,
+                        DiagnosticReason::MismatchedClosing,
+                    )),
+                    None => diagnostics.push(Diagnostic::new(
+                        line_number,
+                        match_,
+                        DiagnosticReason::StrayClosing,
+                    )),
+                },
+
+                (Token::String(_) | Token::InlineSpan(_, _, _), Kind::Opening) => {
+                    open_inline = Some(match_)
+                }
+                (Token::String(_) | Token::InlineSpan(_, _, _), Kind::Closing) => {
+                    open_inline = None
+                }
+
+                (
+                    Token::BlockString(_, _)
+                    | Token::BlockComment(_, _)
+                    | Token::BlockSpan(_, _, _),
+                    Kind::Opening,
+                ) => open_block = Some((line_number, match_)),
+                (
+                    Token::BlockString(_, _)
+                    | Token::BlockComment(_, _)
+                    | Token::BlockSpan(_, _, _),
+                    Kind::Closing,
+                ) => open_block = None,
+
+                _ => {}
+            }
+        }
+
+        if let Some(opening) = open_inline {
+            let reason = match opening.token {
+                Token::String(_) => DiagnosticReason::UnterminatedString,
+                _ => DiagnosticReason::UnterminatedSpan,
+            };
+            diagnostics.push(Diagnostic::new(line_number, opening, reason));
+        }
+    }
+
+    if let Some((line_number, opening)) = open_block {
+        let reason = match opening.token {
+            Token::BlockString(_, _) => DiagnosticReason::UnterminatedBlockString,
+            Token::BlockComment(_, _) => DiagnosticReason::UnterminatedBlockComment,
+            _ => DiagnosticReason::UnterminatedSpan,
+        };
+        diagnostics.push(Diagnostic::new(line_number, opening, reason));
+    }
+
+    for (line_number, opening) in stack {
+        diagnostics.push(Diagnostic::new(
+            line_number,
+            opening,
+            DiagnosticReason::UnmatchedOpening,
+        ));
+    }
+
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.col));
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiagnosticReason;
+    use crate::buffer::ParsedBuffer;
+
+    #[test]
+    fn test_diagnostics() {
+        let lines = [
+            ")",
+            "int a( {",
+            "  b(];",
+            "}",
+            "char *c = \"abc;",
+            "]",
+            "/* d",
+        ];
+        let buffer = ParsedBuffer::parse("c", &lines).unwrap();
+
+        assert_eq!(
+            buffer
+                .diagnostics()
+                .into_iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.col, diagnostic.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, 0, DiagnosticReason::StrayClosing),
+                (1, 5, DiagnosticReason::UnmatchedOpening),
+                (1, 7, DiagnosticReason::UnmatchedOpening),
+                (2, 3, DiagnosticReason::UnmatchedOpening),
+                (2, 4, DiagnosticReason::MismatchedClosing),
+                (3, 0, DiagnosticReason::MismatchedClosing),
+                (4, 10, DiagnosticReason::UnterminatedString),
+                (5, 0, DiagnosticReason::MismatchedClosing),
+                (6, 0, DiagnosticReason::UnterminatedBlockComment),
+            ]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use mlua::IntoLua;
+
+use crate::parser::MatchWithLine;
+
+/// Range with an exclusive end, for building text objects
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextRange {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+impl IntoLua for TextRange {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+
+        table.set("start_line", self.start_line)?;
+        table.set("start_col", self.start_col)?;
+        table.set("end_line", self.end_line)?;
+        table.set("end_col", self.end_col)?;
+
+        (&table).into_lua(lua)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnclosingPair {
+    pub opening: MatchWithLine,
+    pub closing: MatchWithLine,
+    /// Range including the delimiters, i.e. `a(`
+    pub outer: TextRange,
+    /// Range excluding the delimiters and the whitespace/newlines next to them, i.e. `i(`
+    pub inner: TextRange,
+}
+
+impl EnclosingPair {
+    /// Builds the pair, using `get_line` to get the text of a line for calculating the inner range.
+    /// Like vim's `i{`, when the delimiters are on their own lines, the inner range starts at the
+    /// beginning of the line after the opening delimiter and ends at the end of the line before
+    /// the closing delimiter, so that indentation is kept
+    pub fn new(
+        opening: MatchWithLine,
+        closing: MatchWithLine,
+        get_line: impl Fn(usize) -> Option<String>,
+    ) -> Self {
+        let outer = TextRange {
+            start_line: opening.line,
+            start_col: opening.col,
+            end_line: closing.line,
+            end_col: closing.col + closing.len(),
+        };
+
+        let opening_end_col = opening.col + opening.len();
+        let opening_line = get_line(opening.line).unwrap_or_default();
+        let after_opening = opening_line.get(opening_end_col..).unwrap_or_default();
+        let (start_line, start_col) =
+            if after_opening.trim().is_empty() && closing.line > opening.line {
+                (opening.line + 1, 0)
+            } else {
+                let whitespace = after_opening.len() - after_opening.trim_start().len();
+                (opening.line, opening_end_col + whitespace)
+            };
+
+        let closing_line = get_line(closing.line).unwrap_or_default();
+        let before_closing = closing_line.get(..closing.col).unwrap_or_default();
+        let (end_line, end_col) = if before_closing.trim().is_empty() && closing.line > start_line {
+            let previous_line = get_line(closing.line - 1).unwrap_or_default();
+            (closing.line - 1, previous_line.len())
+        } else {
+            (closing.line, before_closing.trim_end().len())
+        };
+
+        // Only whitespace between the delimiters
+        let inner = if (start_line, start_col) > (end_line, end_col) {
+            TextRange {
+                start_line: opening.line,
+                start_col: opening_end_col,
+                end_line: opening.line,
+                end_col: opening_end_col,
+            }
+        } else {
+            TextRange {
+                start_line,
+                start_col,
+                end_line,
+                end_col,
+            }
+        };
+
+        Self {
+            opening,
+            closing,
+            outer,
+            inner,
+        }
+    }
+}
+
+impl IntoLua for EnclosingPair {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+
+        table.set("opening", self.opening)?;
+        table.set("closing", self.closing)?;
+        table.set("outer", self.outer)?;
+        table.set("inner", self.inner)?;
+
+        (&table).into_lua(lua)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnclosingPair, TextRange};
+    use crate::buffer::ParsedBuffer;
+
+    #[test]
+    fn test_enclosing_pair() {
+        let lines = [
+            "int a(int b) {",
+            "  if (b) { c(\")\"); }",
+            "  /* ( */ d[ (e) ];",
+            "}",
+        ];
+        let buffer = ParsedBuffer::parse("c", &lines).unwrap();
+        let enclosing = |line_number, col, count, filter| {
+            let (opening, closing) = buffer.enclosing_pair(line_number, col, count, filter)?;
+            Some(EnclosingPair::new(opening, closing, |line_number| {
+                lines.get(line_number).map(|line| line.to_string())
+            }))
+        };
+        let range = |start_line, start_col, end_line, end_col| TextRange {
+            start_line,
+            start_col,
+            end_line,
+            end_col,
+        };
+
+        // Ignores the delimiters in the string
+        let pair = enclosing(1, 13, 1, None).unwrap();
+        assert_eq!(pair.outer, range(1, 12, 1, 17));
+        assert_eq!(pair.inner, range(1, 13, 1, 16));
+
+        // Counts outwards, with the inner range excluding whitespace and newlines
+        let pair = enclosing(1, 13, 2, None).unwrap();
+        assert_eq!(pair.outer, range(1, 9, 1, 20));
+        assert_eq!(pair.inner, range(1, 11, 1, 18));
+        let pair = enclosing(1, 13, 3, None).unwrap();
+        assert_eq!(pair.outer, range(0, 13, 3, 1));
+        assert_eq!(pair.inner, range(1, 0, 2, 19));
+        assert_eq!(enclosing(1, 13, 4, None), None);
+
+        // Filters by delimiter, ignoring the comment
+        let pair = enclosing(2, 14, 1, Some("{")).unwrap();
+        assert_eq!(pair.outer, range(0, 13, 3, 1));
+        let pair = enclosing(2, 14, 1, Some("[")).unwrap();
+        assert_eq!(pair.outer, range(2, 11, 2, 18));
+        assert_eq!(pair.inner, range(2, 13, 2, 16));
+        assert_eq!(enclosing(2, 4, 1, Some("(")), None);
+
+        // Delimiter at the position encloses it
+        assert_eq!(enclosing(0, 5, 1, None).unwrap().inner, range(0, 6, 0, 11));
+        assert_eq!(enclosing(0, 11, 1, None).unwrap().inner, range(0, 6, 0, 11));
+    }
+}
@@ -0,0 +1,800 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use mlua::prelude::*;
+
+use super::{
+    Diagnostic, EnclosingPair, ParseBudget, ParseOptions, ParseProgress, ParsedBuffer,
+    RangeMatches, CHUNK_LINES,
+};
+use crate::parser::matcher::TokenType;
+use crate::parser::{Match, MatchWithLine, PositionEncoding};
+
+/// Converts the optional token type passed from Lua, defaulting to delimiters
+pub fn token_type_from_lua(token_type: Option<u8>) -> TokenType {
+    token_type
+        // TODO: don't ignore the error
+        .and_then(|token_type| token_type.try_into().ok())
+        .unwrap_or(TokenType::Delimiter)
+}
+
+/// Shared handle to a parsed buffer, exposed to Lua as userdata. The buffer is freed once
+/// every handle has been dropped, i.e. when Lua garbage collects the userdata and the buffer
+/// isn't in the registry used by the bufnr-based functions.
+///
+/// Each buffer has its own lock, so queries on one buffer don't wait for parsing another, and
+/// queries on the same buffer run concurrently
+#[derive(Clone)]
+pub struct BufferHandle(Arc<Shared>);
+
+struct Shared {
+    buffer: RwLock<Option<ParsedBuffer>>,
+    /// Background parse in progress, see `BufferHandle::parse_in_background`
+    pending: Mutex<Option<PendingParse>>,
+    /// Incremented whenever the buffer is replaced by a full parse
+    generation: AtomicU64,
+    /// Id of the last background parse
+    last_request: AtomicU64,
+}
+
+struct PendingParse {
+    id: u64,
+    /// Edits made since the background parse started, replayed on its result
+    edits: Vec<Edit>,
+}
+
+/// Arguments of an incremental reparse, see [`ParsedBuffer::reparse_range`]
+struct Edit {
+    filetype: String,
+    lines: Vec<String>,
+    start_line: Option<usize>,
+    old_end_line: Option<usize>,
+    new_end_line: Option<usize>,
+}
+
+impl Edit {
+    /// Applies the edit to both the lines and the buffer parsed from them
+    fn replay(self, parsed_buffer: &mut ParsedBuffer, lines: &mut Vec<String>) {
+        let max_line = lines.len();
+        let start_line = self.start_line.unwrap_or(0).min(max_line);
+        let old_end_line = self.old_end_line.unwrap_or(max_line).min(max_line);
+        let new_end_line = self.new_end_line.unwrap_or(start_line + self.lines.len());
+        lines.splice(
+            start_line..old_end_line,
+            self.lines.iter().take(new_end_line - start_line).cloned(),
+        );
+
+        let edit_lines = self
+            .lines
+            .iter()
+            .map(|line| line.as_ref())
+            .collect::<Vec<_>>();
+        parsed_buffer.reparse_range(
+            &self.filetype,
+            &edit_lines,
+            self.start_line,
+            self.old_end_line,
+            self.new_end_line,
+            |start, end| lines[start.min(lines.len())..end.min(lines.len())].to_vec(),
+        );
+    }
+}
+
+impl BufferHandle {
+    pub fn new(parsed_buffer: ParsedBuffer) -> Self {
+        Self(Arc::new(Shared {
+            buffer: RwLock::new(Some(parsed_buffer)),
+            pending: Mutex::new(None),
+            generation: AtomicU64::new(0),
+            last_request: AtomicU64::new(0),
+        }))
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, Option<PendingParse>> {
+        // Only holds plain data, so poisoning can be ignored
+        self.0
+            .pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Incremented whenever the buffer is replaced by a full parse, so that Lua may poll for the
+    /// result of a background parse
+    pub fn generation(&self) -> u64 {
+        self.0.generation.load(Ordering::Acquire)
+    }
+
+    /// Whether a background parse is in progress
+    pub fn is_parsing(&self) -> bool {
+        self.pending().is_some()
+    }
+
+    /// Runs the function on the buffer, or returns `None` if the buffer was reset
+    pub fn read<T>(&self, f: impl FnOnce(&ParsedBuffer) -> T) -> Option<T> {
+        // The guard must be dropped before resetting, hence `ok()`
+        let result = self
+            .0
+            .buffer
+            .read()
+            .map(|parsed_buffer| parsed_buffer.as_ref().map(f))
+            .ok();
+        result.unwrap_or_else(|| {
+            self.reset();
+            None
+        })
+    }
+
+    /// Runs the function on the buffer, or returns `None` if the buffer was reset
+    pub fn write<T>(&self, f: impl FnOnce(&mut ParsedBuffer) -> T) -> Option<T> {
+        let result = self
+            .0
+            .buffer
+            .write()
+            .map(|mut parsed_buffer| parsed_buffer.as_mut().map(f))
+            .ok();
+        result.unwrap_or_else(|| {
+            self.reset();
+            None
+        })
+    }
+
+    /// A panic while reparsing may leave the buffer partially updated, so it's dropped until
+    /// the next full parse
+    fn reset(&self) {
+        let mut parsed_buffer = self
+            .0
+            .buffer
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *parsed_buffer = None;
+        self.0.buffer.clear_poison();
+    }
+
+    /// Replaces the buffer with a full parse of the lines, resetting it if it was poisoned.
+    /// Returns whether the filetype is supported, leaving the buffer untouched when it isn't
+    pub fn parse(&self, filetype: &str, lines: &[String]) -> bool {
+        self.parse_with_options(filetype, lines, ParseOptions::default())
+    }
+
+    pub fn parse_with_options(
+        &self,
+        filetype: &str,
+        lines: &[String],
+        options: ParseOptions,
+    ) -> bool {
+        let lines = lines.iter().map(|line| line.as_ref()).collect::<Vec<_>>();
+        self.replace(ParsedBuffer::parse_with_options(filetype, &lines, options))
+    }
+
+    /// Replaces the buffer with a buffer without any parsed lines, to be parsed via
+    /// `continue_parse`. Returns whether the filetype is supported
+    pub fn start_parse(&self, filetype: &str, options: ParseOptions) -> bool {
+        self.replace(ParsedBuffer::unparsed(filetype, options))
+    }
+
+    /// Replaces the buffer, cancelling any background parse, since it would be older
+    fn replace(&self, new_parsed_buffer: Option<ParsedBuffer>) -> bool {
+        let Some(new_parsed_buffer) = new_parsed_buffer else {
+            return false;
+        };
+
+        let mut parsed_buffer = self
+            .0
+            .buffer
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        *parsed_buffer = Some(new_parsed_buffer);
+        self.0.buffer.clear_poison();
+        *self.pending() = None;
+        self.0.generation.fetch_add(1, Ordering::Release);
+        true
+    }
+
+    /// Parses the lines on a background thread, while queries keep using the current buffer.
+    /// Once parsed, replays the edits made in the meantime on the result, then swaps it in and
+    /// increments the generation. Starting another full parse cancels the background parse.
+    ///
+    /// Returns whether the filetype is supported
+    pub fn parse_in_background(
+        &self,
+        filetype: &str,
+        lines: Vec<String>,
+        options: ParseOptions,
+    ) -> bool {
+        if ParsedBuffer::unparsed(filetype, options).is_none() {
+            return false;
+        }
+
+        let id = self.0.last_request.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.pending() = Some(PendingParse { id, edits: vec![] });
+
+        let handle = self.clone();
+        let filetype = filetype.to_string();
+        std::thread::spawn(move || handle.finish_background_parse(id, &filetype, lines, options));
+        true
+    }
+
+    fn finish_background_parse(
+        &self,
+        id: u64,
+        filetype: &str,
+        mut lines: Vec<String>,
+        options: ParseOptions,
+    ) {
+        let new_parsed_buffer = catch_unwind(AssertUnwindSafe(|| {
+            let lines = lines.iter().map(|line| line.as_ref()).collect::<Vec<_>>();
+            ParsedBuffer::parse_with_options(filetype, &lines, options)
+        }));
+
+        // Holding the buffer lock while swapping, so that no edits are made in between
+        let mut parsed_buffer = self
+            .0
+            .buffer
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut pending = self.pending();
+        // Superseded by another parse
+        if pending.as_ref().is_none_or(|pending| pending.id != id) {
+            return;
+        }
+        let edits = pending.take().unwrap().edits;
+        let Ok(Some(mut new_parsed_buffer)) = new_parsed_buffer else {
+            return;
+        };
+
+        for edit in edits {
+            edit.replay(&mut new_parsed_buffer, &mut lines);
+        }
+        if let Some(old_parsed_buffer) = parsed_buffer.as_mut() {
+            new_parsed_buffer.take_regions(old_parsed_buffer);
+        }
+        *parsed_buffer = Some(new_parsed_buffer);
+        self.0.buffer.clear_poison();
+        self.0.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// See [`ParsedBuffer::continue_parse`], with `get_lines` being a Lua function whose errors
+    /// are returned. Also returns `None` when the buffer was reset
+    pub fn continue_parse(
+        &self,
+        filetype: &str,
+        budget: &ParseBudget,
+        get_lines: &LuaFunction,
+    ) -> LuaResult<Option<ParseProgress>> {
+        self.try_continue_parse(filetype, budget, |start, end| get_lines.call((start, end)))
+    }
+
+    /// See [`ParsedBuffer::continue_parse`], returning the errors of `get_lines`. The lines are
+    /// fetched without holding the buffer lock, since `get_lines` may call into Lua, which may
+    /// query this buffer. Also returns `None` when the buffer was reset
+    pub fn try_continue_parse<E>(
+        &self,
+        filetype: &str,
+        budget: &ParseBudget,
+        mut get_lines: impl FnMut(usize, usize) -> Result<Vec<String>, E>,
+    ) -> Result<Option<ParseProgress>, E> {
+        let Some(mut progress) = self.read(|parsed_buffer| ParseProgress {
+            start_line: parsed_buffer.matches_by_line.len(),
+            end_line: parsed_buffer.matches_by_line.len(),
+            done: parsed_buffer.is_complete(),
+        }) else {
+            return Ok(None);
+        };
+
+        let mut tracker = budget.start();
+        while !progress.done && !tracker.is_exhausted() {
+            let lines = get_lines(progress.end_line, progress.end_line + CHUNK_LINES)?;
+            let parsed = self.write(|parsed_buffer| {
+                // Skips the lines when the buffer was replaced while fetching them
+                if parsed_buffer.matches_by_line.len() == progress.end_line {
+                    parsed_buffer.parse_chunk(filetype, &lines, &mut tracker)?;
+                }
+                Some((
+                    parsed_buffer.matches_by_line.len(),
+                    parsed_buffer.is_complete(),
+                ))
+            });
+            let Some(Some((end_line, done))) = parsed else {
+                return Ok(None);
+            };
+            progress.end_line = end_line;
+            progress.done = done;
+        }
+        Ok(Some(progress))
+    }
+
+    /// See [`ParsedBuffer::reparse_range`], with `get_lines` being an optional Lua function
+    /// whose errors are returned. Also returns `None` when the buffer was reset, in which case
+    /// it must be fully parsed again
+    pub fn reparse(
+        &self,
+        filetype: &str,
+        lines: &[String],
+        start_line: Option<usize>,
+        old_end_line: Option<usize>,
+        new_end_line: Option<usize>,
+        get_lines: Option<&LuaFunction>,
+    ) -> LuaResult<Option<usize>> {
+        let end_line = self.try_reparse_range(
+            filetype,
+            lines,
+            start_line,
+            old_end_line,
+            new_end_line,
+            |start, end| match get_lines {
+                Some(get_lines) => get_lines.call((start, end)),
+                None => Ok(vec![]),
+            },
+        )?;
+        Ok(end_line.flatten())
+    }
+
+    /// See [`ParsedBuffer::reparse_range`], returning the errors of `get_lines`. The edit is also
+    /// recorded for the background parse, if any. The lines following the edit are fetched
+    /// without holding the buffer lock, since `get_lines` may call into Lua, which may query this
+    /// buffer. Returns `None` when the buffer was reset
+    pub fn try_reparse_range<E>(
+        &self,
+        filetype: &str,
+        lines: &[String],
+        start_line: Option<usize>,
+        old_end_line: Option<usize>,
+        new_end_line: Option<usize>,
+        mut get_lines: impl FnMut(usize, usize) -> Result<Vec<String>, E>,
+    ) -> Result<Option<Option<usize>>, E> {
+        let reparse = self.write(|parsed_buffer| {
+            // Replayed on the result of the background parse, while holding the buffer lock
+            if let Some(pending) = self.pending().as_mut() {
+                pending.edits.push(Edit {
+                    filetype: filetype.to_string(),
+                    lines: lines.to_vec(),
+                    start_line,
+                    old_end_line,
+                    new_end_line,
+                });
+            }
+
+            let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+            parsed_buffer.start_reparse(filetype, &lines, start_line, old_end_line, new_end_line)
+        });
+        let Some(Some(mut reparse)) = reparse else {
+            return Ok(reparse.map(|_| None));
+        };
+
+        while let Some((start, end)) = reparse.next_lines() {
+            let lines = get_lines(start, end)?;
+            let Some(continued) = self.write(|parsed_buffer| {
+                parsed_buffer.continue_reparse(filetype, &mut reparse, &lines)
+            }) else {
+                return Ok(None);
+            };
+            if continued.is_none() {
+                return Ok(Some(None));
+            }
+        }
+        for region in std::mem::take(&mut reparse.dirty_regions) {
+            let lines = get_lines(region.start_row, region.end_row + 1)?;
+            if self
+                .write(|parsed_buffer| parsed_buffer.reparse_region(region, &lines))
+                .is_none()
+            {
+                return Ok(None);
+            }
+        }
+        Ok(Some(Some(reparse.end_line)))
+    }
+}
+
+/// Queries with the columns converted to and from the encoding, using the text of the parsed
+/// lines. Buffers which were reset return no results
+impl BufferHandle {
+    pub fn line_matches(
+        &self,
+        line_number: usize,
+        token_type: TokenType,
+        encoding: PositionEncoding,
+    ) -> Vec<Match> {
+        self.read(|parsed_buffer| {
+            let mut matches = parsed_buffer.line_matches(line_number)?;
+            matches.retain(|match_| token_type.matches(&match_.token));
+            for match_ in matches.iter_mut() {
+                match_.col = parsed_buffer.encode_col(encoding, line_number, match_.col);
+            }
+            Some(matches)
+        })
+        .flatten()
+        .unwrap_or_default()
+    }
+
+    pub fn range_matches(
+        &self,
+        start_line: usize,
+        end_line: usize,
+        token_type: TokenType,
+        encoding: PositionEncoding,
+    ) -> RangeMatches {
+        self.read(|parsed_buffer| {
+            let mut range_matches = parsed_buffer.range_matches(start_line, end_line, token_type);
+            for (line_number, col) in range_matches.line.iter().zip(range_matches.col.iter_mut()) {
+                *col = parsed_buffer.encode_col(encoding, *line_number, *col);
+            }
+            range_matches
+        })
+        .unwrap_or_default()
+    }
+
+    pub fn span_at(
+        &self,
+        line_number: usize,
+        col: usize,
+        encoding: PositionEncoding,
+    ) -> Option<String> {
+        self.read(|parsed_buffer| {
+            let col = parsed_buffer.decode_col(encoding, line_number, col);
+            parsed_buffer.span_at(line_number, col)
+        })
+        .flatten()
+    }
+
+    pub fn match_at(
+        &self,
+        line_number: usize,
+        col: usize,
+        encoding: PositionEncoding,
+    ) -> Option<Match> {
+        self.read(|parsed_buffer| {
+            let col = parsed_buffer.decode_col(encoding, line_number, col);
+            let mut match_ = parsed_buffer.match_at(line_number, col)?;
+            match_.col = parsed_buffer.encode_col(encoding, line_number, match_.col);
+            Some(match_)
+        })
+        .flatten()
+    }
+
+    pub fn match_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+        encoding: PositionEncoding,
+    ) -> Option<(MatchWithLine, MatchWithLine)> {
+        self.read(|parsed_buffer| {
+            let col = parsed_buffer.decode_col(encoding, line_number, col);
+            let (mut open, mut close) = parsed_buffer.match_pair(line_number, col)?;
+            for match_ in [&mut open, &mut close] {
+                match_.col = parsed_buffer.encode_col(encoding, match_.line, match_.col);
+            }
+            Some((open, close))
+        })
+        .flatten()
+    }
+
+    /// See [`ParsedBuffer::enclosing_pair`] and [`EnclosingPair::new`]. The lines are fetched
+    /// without holding the buffer lock, since `get_line` may call into Lua, which may query
+    /// this buffer or wait on a background parse swapping it
+    pub fn enclosing_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+        count: usize,
+        filter: Option<&str>,
+        encoding: PositionEncoding,
+        get_line: impl Fn(usize) -> Option<String>,
+    ) -> Option<EnclosingPair> {
+        let (opening, closing) = self
+            .read(|parsed_buffer| {
+                let col = parsed_buffer.decode_col(encoding, line_number, col);
+                parsed_buffer.enclosing_pair(line_number, col, count, filter)
+            })
+            .flatten()?;
+        let mut pair = EnclosingPair::new(opening, closing, get_line);
+
+        self.read(|parsed_buffer| {
+            let encode = |line_number, col| parsed_buffer.encode_col(encoding, line_number, col);
+            pair.opening.col = encode(pair.opening.line, pair.opening.col);
+            pair.closing.col = encode(pair.closing.line, pair.closing.col);
+            for range in [&mut pair.outer, &mut pair.inner] {
+                range.start_col = encode(range.start_line, range.start_col);
+                range.end_col = encode(range.end_line, range.end_col);
+            }
+            pair
+        })
+    }
+
+    pub fn diagnostics(&self, encoding: PositionEncoding) -> Vec<Diagnostic> {
+        self.read(|parsed_buffer| {
+            let mut diagnostics = parsed_buffer.diagnostics();
+            for diagnostic in diagnostics.iter_mut() {
+                diagnostic.col =
+                    parsed_buffer.encode_col(encoding, diagnostic.line, diagnostic.col);
+            }
+            diagnostics
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl LuaUserData for BufferHandle {
+    #[allow(clippy::type_complexity)]
+    fn add_methods<M: LuaUserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method(
+            "parse",
+            |_, this, (filetype, lines): (String, Vec<String>)| Ok(this.parse(&filetype, &lines)),
+        );
+        methods.add_method(
+            "reparse",
+            |_,
+             this,
+             (filetype, lines, start_line, old_end_line, new_end_line, get_lines): (
+                String,
+                Vec<String>,
+                Option<usize>,
+                Option<usize>,
+                Option<usize>,
+                Option<LuaFunction>,
+            )| {
+                this.reparse(
+                    &filetype,
+                    &lines,
+                    start_line,
+                    old_end_line,
+                    new_end_line,
+                    get_lines.as_ref(),
+                )
+            },
+        );
+
+        methods.add_method(
+            "line_matches",
+            |_,
+             this,
+             (line_number, token_type, encoding): (
+                usize,
+                Option<u8>,
+                Option<PositionEncoding>,
+            )| {
+                Ok(this.line_matches(
+                    line_number,
+                    token_type_from_lua(token_type),
+                    encoding.unwrap_or_default(),
+                ))
+            },
+        );
+        methods.add_method(
+            "range_matches",
+            |_,
+             this,
+             (start_line, end_line, token_type, encoding): (
+                usize,
+                usize,
+                Option<u8>,
+                Option<PositionEncoding>,
+            )| {
+                Ok(this.range_matches(
+                    start_line,
+                    end_line,
+                    token_type_from_lua(token_type),
+                    encoding.unwrap_or_default(),
+                ))
+            },
+        );
+        methods.add_method(
+            "span_at",
+            |_, this, (line_number, col, encoding): (usize, usize, Option<PositionEncoding>)| {
+                Ok(this.span_at(line_number, col, encoding.unwrap_or_default()))
+            },
+        );
+        methods.add_method(
+            "match_at",
+            |_, this, (line_number, col, encoding): (usize, usize, Option<PositionEncoding>)| {
+                Ok(this.match_at(line_number, col, encoding.unwrap_or_default()))
+            },
+        );
+        methods.add_method(
+            "match_pair",
+            |_, this, (line_number, col, encoding): (usize, usize, Option<PositionEncoding>)| {
+                Ok(this
+                    .match_pair(line_number, col, encoding.unwrap_or_default())
+                    .map(|(open, close)| vec![open, close]))
+            },
+        );
+        methods.add_method(
+            "diagnostics",
+            |_, this, encoding: Option<PositionEncoding>| {
+                Ok(this.diagnostics(encoding.unwrap_or_default()))
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::tests::assert_matches_full_parse;
+
+    #[test]
+    fn test_enclosing_pair_get_line() {
+        // Lines are fetched without holding the buffer lock, so fetching may access the buffer
+        let lines = ["f(", "  a", ")"];
+        let handle = BufferHandle::new(ParsedBuffer::parse("c", &lines).unwrap());
+        let pair = handle.enclosing_pair(1, 2, 1, None, PositionEncoding::default(), |line| {
+            handle.write(|_| ())?;
+            lines.get(line).map(|line| line.to_string())
+        });
+        assert_eq!(pair.map(|pair| pair.inner.start_line), Some(1));
+    }
+
+    #[test]
+    fn test_get_lines_without_lock() {
+        // Lines are fetched without holding the buffer lock, so fetching may access the buffer
+        let mut lines = vec!["int a() {", "  b();", "}"];
+        let handle =
+            BufferHandle::new(ParsedBuffer::unparsed("c", ParseOptions::default()).unwrap());
+        let get_lines = |lines: &[&str], start: usize, end: usize| {
+            handle.write(|_| ());
+            Ok::<_, ()>(
+                lines[start.min(lines.len())..end.min(lines.len())]
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let progress = handle
+            .try_continue_parse("c", &ParseBudget::default(), |start, end| {
+                get_lines(&lines, start, end)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!((progress.end_line, progress.done), (3, true));
+
+        // Opens a block comment, so the following lines are fetched
+        lines[0] = "/* int a() {";
+        let end_line = handle.try_reparse_range(
+            "c",
+            &[lines[0].to_string()],
+            Some(0),
+            Some(1),
+            Some(1),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert_eq!(end_line, Ok(Some(Some(3))));
+        let expected = ParsedBuffer::parse("c", &lines).unwrap();
+        for line_number in 0..lines.len() {
+            assert_eq!(
+                handle.read(|buffer| buffer.line_matches(line_number)),
+                Some(expected.line_matches(line_number))
+            );
+        }
+    }
+
+    #[test]
+    fn test_background_parse() {
+        let mut lines = (0..3000)
+            .map(|idx| match idx % 3 {
+                0 => "int a() {",
+                1 => "  /* b(); */ c();",
+                _ => "}",
+            })
+            .collect::<Vec<_>>();
+        let owned_lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect();
+        let mut old_lines = lines.clone();
+        old_lines[0] = "";
+        let handle = BufferHandle::new(ParsedBuffer::parse("c", &old_lines).unwrap());
+        assert_eq!(handle.generation(), 0);
+
+        // Queries use the previous buffer until the result is swapped in, which waits for them
+        handle.read(|buffer| {
+            let options = ParseOptions::default();
+            assert!(handle.parse_in_background("c", owned_lines(&lines), options));
+            assert_eq!(buffer.line_matches(0), Some(vec![]));
+        });
+        assert!(!handle.parse_in_background("", vec![], ParseOptions::default()));
+
+        // Edits while parsing are replayed on the result
+        lines[1] = "  b[0] = c();";
+        handle
+            .try_reparse_range(
+                "c",
+                &[lines[1].to_string()],
+                Some(1),
+                Some(2),
+                Some(2),
+                |_, _| Ok::<_, ()>(vec![]),
+            )
+            .unwrap()
+            .unwrap();
+
+        while handle.is_parsing() {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(handle.generation(), 1);
+        handle.read(|buffer| assert_matches_full_parse(buffer, &lines));
+
+        // Full parses cancel the background parse
+        assert!(handle.parse_in_background("c", owned_lines(&lines), ParseOptions::default()));
+        handle.parse("c", &owned_lines(&old_lines));
+        assert!(!handle.is_parsing());
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.read(|buffer| assert_matches_full_parse(buffer, &old_lines));
+    }
+
+    #[test]
+    fn test_position_encoding() {
+        let lines = ["a(é, \"😀\")", "\t[b]"];
+        let handle = BufferHandle::new(ParsedBuffer::parse("c", &lines).unwrap());
+        let cols = |line_number, encoding| {
+            handle
+                .line_matches(line_number, TokenType::Delimiter, encoding)
+                .iter()
+                .map(|match_| match_.col)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(cols(0, PositionEncoding::Byte), vec![1, 12]);
+        assert_eq!(cols(0, PositionEncoding::Codepoint), vec![1, 8]);
+        assert_eq!(cols(0, PositionEncoding::Utf16), vec![1, 9]);
+        assert_eq!(
+            cols(1, PositionEncoding::Display { tabstop: 4 }),
+            vec![4, 6]
+        );
+
+        // Positions passed in are converted using the same text
+        let match_ = handle.match_at(0, 9, PositionEncoding::Utf16).unwrap();
+        assert_eq!(match_.col, 9);
+        let (open, close) = handle
+            .match_pair(1, 6, PositionEncoding::Display { tabstop: 4 })
+            .unwrap();
+        assert_eq!((open.col, close.col), (4, 6));
+    }
+
+    #[test]
+    fn test_buffer_handle() {
+        let lines = ["{", "}"].map(String::from);
+        let handle = BufferHandle::new(ParsedBuffer::parse("c", &["("]).unwrap());
+        let cloned = handle.clone();
+
+        assert!(!handle.parse("unknown", &lines));
+        assert_eq!(cloned.read(|buffer| buffer.match_pair(0, 0)), Some(None));
+
+        // Clones share the same buffer
+        assert!(handle.parse("c", &lines));
+        assert!(cloned
+            .read(|buffer| buffer.match_pair(0, 0))
+            .flatten()
+            .is_some());
+
+        let lines = ["[", "]"];
+        let end_line = cloned.write(|buffer| {
+            buffer.reparse_range("c", &lines, Some(0), Some(2), Some(2), |_, _| vec![])
+        });
+        assert_eq!(end_line, Some(Some(2)));
+        assert_eq!(
+            handle.read(|buffer| buffer.line_matches(1)),
+            Some(ParsedBuffer::parse("c", &lines).unwrap().line_matches(1))
+        );
+    }
+
+    #[test]
+    fn test_buffer_handle_poisoned() {
+        let lines = ["{", "}"].map(String::from);
+        let handle = BufferHandle::new(ParsedBuffer::parse("c", &["{", "}"]).unwrap());
+        let other = BufferHandle::new(ParsedBuffer::parse("c", &["{", "}"]).unwrap());
+
+        let poisoned = handle.clone();
+        std::thread::spawn(move || poisoned.write(|_| panic!("panic while parsing")))
+            .join()
+            .unwrap_err();
+
+        // Only the poisoned buffer is reset, until the next full parse
+        assert_eq!(handle.read(|buffer| buffer.match_at(0, 0)), None);
+        assert_eq!(handle.write(|buffer| buffer.match_at(0, 0)), None);
+        assert!(other.read(|buffer| buffer.match_at(0, 0)).is_some());
+
+        assert!(handle.parse("c", &lines));
+        assert!(handle
+            .read(|buffer| buffer.match_at(0, 0))
+            .flatten()
+            .is_some());
+    }
+}
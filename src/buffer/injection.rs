@@ -0,0 +1,236 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::parser::{parse_filetype, Checkpoint, Injection, Kind, Match, Parsed, State, Token};
+
+/// Block spans whose contents are parsed with the language named after the opening pattern,
+/// i.e. ```` ```rust ```` in Markdown
+const INJECTED_SPANS: &[&str] = &["code"];
+
+/// Parses the lines with the filetype, then parses the contents of each fenced code block with
+/// the language from its info string, replacing the matches and checkpoints of those lines. The
+/// checkpoints record the injected language, so that reparsing may resume inside a code block
+pub fn parse_with_injections(
+    filetype: &str,
+    lines: &[&str],
+    initial: Arc<Checkpoint>,
+) -> Option<Parsed> {
+    let (initial, mut injection) = match &initial.state {
+        State::InInjection(injection) => (
+            Checkpoint {
+                state: State::InBlockSpan(injection.span.clone()),
+                ..Checkpoint::clone(&initial)
+            },
+            Some(((**injection).clone(), 0)),
+        ),
+        _ => (Checkpoint::clone(&initial), None),
+    };
+    let (mut matches_by_line, mut checkpoints) = parse_filetype(filetype, lines, initial)?;
+
+    for line_number in 0..lines.len() {
+        if let Some((current, start_line)) = &injection {
+            if closes_span(&matches_by_line[line_number], &current.span) {
+                let range = *start_line..line_number;
+                let (current, _) = injection.take().unwrap();
+                inject(
+                    current,
+                    lines,
+                    range,
+                    &mut matches_by_line,
+                    &mut checkpoints,
+                );
+            }
+            continue;
+        }
+
+        let checkpoint = &checkpoints[line_number];
+        if let Some(current) = open_injection(
+            lines[line_number],
+            &matches_by_line[line_number],
+            &checkpoint.state,
+        ) {
+            checkpoints[line_number] = Arc::new(Checkpoint {
+                state: State::InInjection(Box::new(current.clone())),
+                ..Checkpoint::clone(checkpoint)
+            });
+            injection = Some((current, line_number + 1));
+        }
+    }
+
+    // The code block continues past the end of the lines
+    if let Some((current, start_line)) = injection {
+        let range = start_line..lines.len();
+        inject(
+            current,
+            lines,
+            range,
+            &mut matches_by_line,
+            &mut checkpoints,
+        );
+    }
+
+    Some((matches_by_line, checkpoints))
+}
+
+fn closes_span(matches: &[Match], span: &str) -> bool {
+    matches.iter().any(|match_| {
+        match_.kind == Kind::Closing
+            && matches!(&match_.token, Token::BlockSpan(name, _, _) if name == span)
+    })
+}
+
+/// Reads the language of a code block opened on the line, which continues onto the next line
+fn open_injection(line: &str, matches: &[Match], state: &State) -> Option<Injection> {
+    let State::InBlockSpan(span) = state else {
+        return None;
+    };
+    if !INJECTED_SPANS.contains(&span.as_ref()) {
+        return None;
+    }
+
+    let (col, open) =
+        matches
+            .iter()
+            .rev()
+            .find_map(|match_| match (&match_.token, match_.kind) {
+                (Token::BlockSpan(name, open, _), Kind::Opening) if name == span => {
+                    Some((match_.col, open))
+                }
+                _ => None,
+            })?;
+    let language = info_string_language(&line[col + open.len()..])?;
+
+    // Only inject supported languages, so that the state doesn't change otherwise
+    parse_filetype(&language, &[], State::Normal)?;
+    Some(Injection::new(span.clone(), language))
+}
+
+/// Parses the lines in the range with the injected language, starting from its checkpoint
+fn inject(
+    injection: Injection,
+    lines: &[&str],
+    range: Range<usize>,
+    matches_by_line: &mut [Vec<Match>],
+    checkpoints: &mut [Arc<Checkpoint>],
+) {
+    let Some((injected_matches, injected_checkpoints)) = parse_filetype(
+        &injection.language,
+        &lines[range.clone()],
+        injection.checkpoint.clone(),
+    ) else {
+        return;
+    };
+
+    for ((line_number, matches), injected) in range.zip(injected_matches).zip(injected_checkpoints)
+    {
+        matches_by_line[line_number] = matches;
+
+        // The host's delimiter stack is unchanged inside the span
+        let checkpoint = Checkpoint {
+            state: State::InInjection(Box::new(Injection {
+                checkpoint: Checkpoint::clone(&injected),
+                ..injection.clone()
+            })),
+            ..Checkpoint::clone(&checkpoints[line_number])
+        };
+        checkpoints[line_number] = match line_number.checked_sub(1) {
+            Some(previous) if *checkpoints[previous] == checkpoint => checkpoints[previous].clone(),
+            _ => Arc::new(checkpoint),
+        };
+    }
+}
+
+/// Filetype from the first word of the info string, i.e. `rust` in ```` ```rust ignore ````
+/// or `js` in ```` ```{.js} ````, resolving common aliases
+fn info_string_language(info: &str) -> Option<String> {
+    let info = info.trim_start().trim_start_matches(['{', '.']);
+    let word = info
+        .split(|char: char| !(char.is_alphanumeric() || matches!(char, '_' | '-' | '+' | '#')))
+        .next()?
+        .to_ascii_lowercase();
+
+    let language = match word.as_str() {
+        "" => return None,
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "sh" | "bash" | "zsh" => "shell",
+        "c++" | "cc" | "cxx" | "hpp" => "cpp",
+        "c#" | "cs" => "csharp",
+        "f#" | "fs" => "fsharp",
+        "ex" | "exs" => "elixir",
+        "erl" => "erlang",
+        "golang" => "go",
+        "hs" => "haskell",
+        "kt" | "kts" => "kotlin",
+        "md" => "markdown",
+        "ml" => "ocaml",
+        "objective-c" => "objc",
+        "pl" => "perl",
+        "py" => "python",
+        "rb" => "ruby",
+        "rs" => "rust",
+        "tf" => "terraform",
+        _ => return Some(word),
+    };
+    Some(language.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::ParsedBuffer;
+
+    #[test]
+    fn test_injection() {
+        let mut lines = vec![
+            "Some (text",
+            "```rust",
+            "fn a() {",
+            "    b(')', \"}\");",
+            "}",
+            "```",
+            "```unknown",
+            "{",
+            "```",
+        ];
+        let mut buffer = ParsedBuffer::parse("markdown", &lines).unwrap();
+
+        // Code blocks are parsed with the language from the info string
+        let pair = buffer.match_pair(2, 7).unwrap();
+        assert_eq!((pair.1.line, pair.1.col), (4, 0));
+        assert_eq!(buffer.line_matches(3).unwrap().len(), 6);
+        assert_eq!(buffer.span_at(3, 4), Some("code".to_string()));
+        assert!(buffer.line_matches(7).unwrap().is_empty());
+
+        // Resumes inside the code block, where the block comment continues until the fence
+        lines[3] = "    b(/* ')', \"}\");";
+        let end_line = buffer.reparse_range(
+            "markdown",
+            &lines[3..4],
+            Some(3),
+            Some(4),
+            Some(4),
+            |start, end| lines[start..end].iter().map(|s| s.to_string()).collect(),
+        );
+        assert_eq!(end_line, Some(6));
+        let expected = ParsedBuffer::parse("markdown", &lines).unwrap();
+        for line_number in 0..lines.len() {
+            assert_eq!(
+                buffer.line_matches(line_number),
+                expected.line_matches(line_number),
+                "line {}",
+                line_number
+            );
+        }
+        assert_eq!(
+            buffer.checkpoint_by_line.len(),
+            expected.checkpoint_by_line.len()
+        );
+        for (checkpoint, expected_checkpoint) in buffer
+            .checkpoint_by_line
+            .iter()
+            .zip(&expected.checkpoint_by_line)
+        {
+            assert!(checkpoint.converges_with(expected_checkpoint));
+        }
+        assert!(buffer.line_matches(4).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+use std::mem::size_of;
+use std::sync::Arc;
+
+use crate::parser::{Checkpoint, Heredoc, Injection, Interpolation, Match, State, Token};
+
+/// Heap allocations of a string, which only exist for languages registered at runtime
+#[allow(clippy::ptr_arg)]
+fn cow_size(str: &Cow<'static, str>) -> usize {
+    match str {
+        Cow::Borrowed(_) => 0,
+        Cow::Owned(str) => str.capacity(),
+    }
+}
+
+pub fn token_heap_size(token: &Token) -> usize {
+    match token {
+        Token::String(open) | Token::LineComment(open) => cow_size(open),
+        Token::Delimiter(open, close)
+        | Token::BlockString(open, close)
+        | Token::BlockComment(open, close)
+        | Token::Regex(open, close) => cow_size(open) + cow_size(close),
+        Token::InlineSpan(span, open, close) | Token::BlockSpan(span, open, close) => {
+            cow_size(span) + cow_size(open) + cow_size(close)
+        }
+    }
+}
+
+pub fn matches_heap_size(matches_by_line: &Vec<Vec<Match>>) -> usize {
+    matches_by_line.capacity() * size_of::<Vec<Match>>()
+        + matches_by_line
+            .iter()
+            .map(|matches| {
+                matches.capacity() * size_of::<Match>()
+                    + matches
+                        .iter()
+                        .map(|match_| token_heap_size(&match_.token))
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+}
+
+pub fn line_text_heap_size(line_text: &Vec<Option<Box<str>>>) -> usize {
+    line_text.capacity() * size_of::<Option<Box<str>>>()
+        + line_text
+            .iter()
+            .flatten()
+            .map(|line| line.len())
+            .sum::<usize>()
+}
+
+/// Checkpoints shared between consecutive lines are only counted once
+pub fn checkpoints_heap_size(checkpoint_by_line: &Vec<Arc<Checkpoint>>) -> usize {
+    checkpoint_by_line.capacity() * size_of::<Arc<Checkpoint>>()
+        + checkpoint_by_line
+            .iter()
+            .enumerate()
+            .filter(|&(idx, checkpoint)| {
+                idx == 0 || !Arc::ptr_eq(checkpoint, &checkpoint_by_line[idx - 1])
+            })
+            .map(|(_, checkpoint)| size_of::<Checkpoint>() + checkpoint_heap_size(checkpoint))
+            .sum::<usize>()
+}
+
+fn checkpoint_heap_size(checkpoint: &Checkpoint) -> usize {
+    state_heap_size(&checkpoint.state)
+        + checkpoint.stack.capacity() * size_of::<usize>()
+        + checkpoint.outer_stacks.capacity() * size_of::<Vec<usize>>()
+        + checkpoint
+            .outer_stacks
+            .iter()
+            .map(|stack| stack.capacity() * size_of::<usize>())
+            .sum::<usize>()
+}
+
+fn heredoc_heap_size(heredoc: &Heredoc) -> usize {
+    cow_size(&heredoc.open)
+        + cow_size(&heredoc.terminator)
+        + heredoc.queued.capacity() * size_of::<Heredoc>()
+        + heredoc.queued.iter().map(heredoc_heap_size).sum::<usize>()
+}
+
+fn state_heap_size(state: &State) -> usize {
+    match state {
+        State::Normal | State::InLineComment => 0,
+        State::InString(str)
+        | State::InBlockString(str)
+        | State::InBlockComment(str, _)
+        | State::InInlineSpan(str)
+        | State::InBlockSpan(str) => cow_size(str),
+        State::InInterpolation(interpolation) => {
+            size_of::<Interpolation>()
+                + state_heap_size(&interpolation.string)
+                + cow_size(&interpolation.open)
+                + cow_size(&interpolation.close)
+                + state_heap_size(&interpolation.state)
+        }
+        State::InHeredoc(heredoc) => size_of::<Heredoc>() + heredoc_heap_size(heredoc),
+        State::InInjection(injection) => {
+            size_of::<Injection>()
+                + cow_size(&injection.span)
+                + injection.language.capacity()
+                + checkpoint_heap_size(&injection.checkpoint)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+    use std::sync::Arc;
+
+    use super::checkpoints_heap_size;
+    use crate::buffer::ParsedBuffer;
+    use crate::parser::Checkpoint;
+
+    #[test]
+    fn test_memory_usage() {
+        let lines = ["int a() {", "  b();", "}"];
+        let buffer = ParsedBuffer::parse("c", &lines[..1]).unwrap();
+        assert!(ParsedBuffer::parse("c", &lines).unwrap().memory_usage() > buffer.memory_usage());
+
+        // Checkpoints shared between lines are only counted once
+        let checkpoint = Arc::new(Checkpoint::default());
+        let shared = (0..3).map(|_| checkpoint.clone()).collect::<Vec<_>>();
+        let separate = (0..3)
+            .map(|_| Arc::new(Checkpoint::default()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            checkpoints_heap_size(&separate) - checkpoints_heap_size(&shared),
+            2 * size_of::<Checkpoint>()
+        );
+    }
+}
@@ -0,0 +1,936 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::parser::matcher::TokenType;
+use crate::parser::{Checkpoint, Kind, Match, MatchWithLine, PositionEncoding, State, Token};
+
+mod budget;
+mod diagnostics;
+mod enclosing;
+mod handle;
+mod injection;
+mod memory;
+mod range;
+mod region;
+mod registry;
+mod stack;
+
+use budget::BudgetTracker;
+pub use budget::{ParseBudget, ParseOptions, ParseProgress};
+pub use diagnostics::{Diagnostic, DiagnosticReason};
+pub use enclosing::{EnclosingPair, TextRange};
+pub use handle::{token_type_from_lua, BufferHandle};
+use injection::parse_with_injections;
+pub use range::RangeMatches;
+pub use region::Region;
+use region::RegionBuffer;
+pub use registry::{BufferLookup, BufferRegistry, MemoryUsage};
+use stack::{apply_stack_heights, StackEffect, StackTree};
+
+/// Number of lines fetched at a time by `continue_parse`
+const CHUNK_LINES: usize = 1024;
+/// The time budget may only be checked between parses, so the fetched lines are parsed in chunks
+/// of at most this many bytes
+const CHUNK_BYTES: usize = 64 * 1024;
+
+pub struct ParsedBuffer {
+    /// Stack heights are only valid after a full parse, use `line_matches` to get
+    /// the matches with up-to-date stack heights
+    matches_by_line: Vec<Vec<Match>>,
+    checkpoint_by_line: Vec<Arc<Checkpoint>>,
+    stack_tree: StackTree,
+    /// Regions in other languages, parsed separately from the rest of the buffer
+    regions: Vec<RegionBuffer>,
+    options: ParseOptions,
+    /// Sorted lines which were skipped for being too long
+    unparsed_lines: Vec<usize>,
+    /// Text of the lines whose columns differ between position encodings, for converting
+    /// columns without fetching the lines again. `None` for printable ASCII and skipped lines
+    line_text: Vec<Option<Box<str>>>,
+    /// Whether all the lines of the buffer have been parsed, which is only false while a budgeted
+    /// parse is in progress, in which case only the first lines of the buffer are stored
+    complete: bool,
+}
+
+/// Lines parsed by `ParsedBuffer::parse_lines`, to be spliced into the buffer
+#[derive(Default)]
+struct ParsedLines {
+    matches_by_line: Vec<Vec<Match>>,
+    checkpoint_by_line: Vec<Arc<Checkpoint>>,
+    /// Indices of the lines which were skipped, which may include lines past the end of the lines
+    /// spliced into the buffer
+    unparsed_lines: Vec<usize>,
+    line_text: Vec<Option<Box<str>>>,
+}
+
+/// Incremental reparse started by `ParsedBuffer::start_reparse`, which continues with the lines
+/// following the edit until the checkpoints converge. Split into steps so that the lines may be
+/// fetched without holding a lock on the buffer
+pub struct Reparse {
+    /// Line (exclusive) at which parsing stopped
+    pub end_line: usize,
+    /// Number of lines stored in the buffer, which bounds the lines to continue with
+    max_line: usize,
+    converged: bool,
+    /// Number of lines to continue with, doubled on each step
+    chunk_size: usize,
+    /// Regions overlapping the edit, which must be parsed again via `reparse_region`
+    pub dirty_regions: Vec<Region>,
+}
+
+impl Reparse {
+    /// Range of the lines to continue parsing with, or `None` once done
+    pub fn next_lines(&self) -> Option<(usize, usize)> {
+        (!self.converged && self.end_line < self.max_line).then(|| {
+            (
+                self.end_line,
+                (self.end_line + self.chunk_size).min(self.max_line),
+            )
+        })
+    }
+}
+
+impl ParsedBuffer {
+    pub fn parse(filetype: &str, lines: &[&str]) -> Option<Self> {
+        Self::parse_with_options(filetype, lines, ParseOptions::default())
+    }
+
+    pub fn parse_with_options(
+        filetype: &str,
+        lines: &[&str],
+        options: ParseOptions,
+    ) -> Option<Self> {
+        let parsed = Self::parse_lines(filetype, lines, Arc::default(), &options)?;
+        let stack_tree = StackTree::new(
+            parsed
+                .matches_by_line
+                .iter()
+                .map(|matches| StackEffect::from_matches(matches)),
+        );
+
+        Some(Self {
+            matches_by_line: parsed.matches_by_line,
+            checkpoint_by_line: parsed.checkpoint_by_line,
+            stack_tree,
+            regions: vec![],
+            options,
+            unparsed_lines: parsed.unparsed_lines,
+            line_text: parsed.line_text,
+            complete: true,
+        })
+    }
+
+    /// Creates a buffer without any parsed lines, to be parsed across multiple calls to
+    /// `continue_parse`. Returns `None` if the filetype isn't supported
+    pub fn unparsed(filetype: &str, options: ParseOptions) -> Option<Self> {
+        let mut buffer = Self::parse_with_options(filetype, &[], options)?;
+        buffer.splice(0..buffer.matches_by_line.len(), &ParsedLines::default(), 0);
+        buffer.complete = false;
+        Some(buffer)
+    }
+
+    /// Parses the lines following the parsed lines, fetched via `get_lines(start, end)`, until
+    /// the budget runs out or `get_lines` returns fewer lines than requested, which marks the
+    /// end of the buffer. Returns `None` if the filetype isn't supported
+    pub fn continue_parse(
+        &mut self,
+        filetype: &str,
+        budget: &ParseBudget,
+        mut get_lines: impl FnMut(usize, usize) -> Vec<String>,
+    ) -> Option<ParseProgress> {
+        let start_line = self.matches_by_line.len();
+        let mut tracker = budget.start();
+        while !self.complete && !tracker.is_exhausted() {
+            let end_line = self.matches_by_line.len();
+            let lines = get_lines(end_line, end_line + CHUNK_LINES);
+            self.parse_chunk(filetype, &lines, &mut tracker)?;
+        }
+
+        Some(ParseProgress {
+            start_line,
+            end_line: self.matches_by_line.len(),
+            done: self.complete,
+        })
+    }
+
+    /// Parses the lines following the parsed lines, fetched as the next `CHUNK_LINES` lines, until
+    /// the budget runs out. Fewer lines than requested marks the end of the buffer. Returns `None`
+    /// if the filetype isn't supported
+    fn parse_chunk(
+        &mut self,
+        filetype: &str,
+        lines: &[String],
+        tracker: &mut BudgetTracker,
+    ) -> Option<()> {
+        let mut parsed_count = 0;
+        while parsed_count < lines.len() && !tracker.is_exhausted() {
+            let length = tracker.lines_within(&lines[parsed_count..], CHUNK_BYTES);
+            let chunk = &lines[parsed_count..parsed_count + length];
+            tracker.spend(chunk);
+
+            let end_line = self.matches_by_line.len();
+            let chunk_ref = chunk.iter().map(|str| str.as_ref()).collect::<Vec<_>>();
+            let initial = self.checkpoint_before_line(end_line);
+            let parsed = Self::parse_lines(filetype, &chunk_ref, initial, &self.options)?;
+            self.splice(end_line..end_line, &parsed, chunk_ref.len());
+            parsed_count += length;
+        }
+        // Reached the end of the buffer
+        self.complete = lines.len() < CHUNK_LINES && parsed_count == lines.len();
+        Some(())
+    }
+
+    /// Parses the lines starting from the checkpoint, skipping the lines over the maximum line
+    /// length
+    fn parse_lines(
+        filetype: &str,
+        lines: &[&str],
+        initial: Arc<Checkpoint>,
+        options: &ParseOptions,
+    ) -> Option<ParsedLines> {
+        let (lines, unparsed_lines) = options.skip_long_lines(lines);
+        let (matches_by_line, checkpoint_by_line) =
+            parse_with_injections(filetype, &lines, initial)?;
+        // Parsing no lines still yields a single empty line
+        let line_text = (0..matches_by_line.len())
+            .map(|idx| {
+                let line = lines.get(idx).copied().unwrap_or_default();
+                (!PositionEncoding::is_plain(line)).then(|| Box::from(line))
+            })
+            .collect();
+
+        Some(ParsedLines {
+            matches_by_line,
+            checkpoint_by_line,
+            unparsed_lines,
+            line_text,
+        })
+    }
+
+    /// Whether all the lines of the buffer have been parsed, see `continue_parse`
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Lines skipped for being longer than `ParseOptions::max_line_length`, which have no matches
+    pub fn unparsed_lines(&self) -> &[usize] {
+        &self.unparsed_lines
+    }
+
+    /// Replaces the regions parsed with their own filetype, given the lines from the first to the
+    /// last row of each region. Regions with unsupported filetypes are ignored
+    pub fn set_regions(&mut self, regions: Vec<(Region, Vec<String>)>) {
+        self.regions = regions
+            .into_iter()
+            .filter_map(|(region, lines)| RegionBuffer::parse(region, &lines))
+            .collect();
+        self.rebuild_stack_tree();
+    }
+
+    /// Moves the regions of the other buffer, with the same lines, to this buffer
+    pub fn take_regions(&mut self, other: &mut ParsedBuffer) {
+        self.regions = std::mem::take(&mut other.regions);
+        if !self.regions.is_empty() {
+            self.rebuild_stack_tree();
+        }
+    }
+
+    /// Rebuilds the stack effects of every line, after the regions changed
+    fn rebuild_stack_tree(&mut self) {
+        let effects = self
+            .matches_by_line
+            .iter()
+            .enumerate()
+            .map(|(line_number, matches)| self.stack_effect(line_number, matches))
+            .collect::<Vec<_>>();
+        self.stack_tree = StackTree::new(effects);
+    }
+
+    fn region_at(&self, line_number: usize, col: usize) -> Option<&RegionBuffer> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(line_number, col))
+    }
+
+    /// Drops the matches inside regions, which belong to the region's own delimiter stack
+    fn without_regions(&self, line_number: usize, matches: &mut Vec<Match>) {
+        if self.regions.is_empty() {
+            return;
+        }
+        matches.retain(|match_| self.region_at(line_number, match_.col).is_none());
+    }
+
+    fn stack_effect(&self, line_number: usize, matches: &[Match]) -> StackEffect {
+        if self.regions.is_empty() {
+            return StackEffect::from_matches(matches);
+        }
+        let mut matches = matches.to_vec();
+        self.without_regions(line_number, &mut matches);
+        StackEffect::from_matches(&matches)
+    }
+
+    /// Moves the regions following the edit, and resizes the regions containing it, given the new
+    /// lines between `start_line` and `new_end_line`. Regions whose first or last row was edited
+    /// have their columns shifted when the rows were edited in place, and are otherwise dropped
+    /// until the regions are set again. Returns the indices of the regions overlapping the edit,
+    /// which must be reparsed
+    fn shift_regions(
+        &mut self,
+        lines: &[&str],
+        start_line: usize,
+        old_end_line: usize,
+        new_end_line: usize,
+    ) -> Vec<usize> {
+        let shift = |row: usize| row - old_end_line + new_end_line;
+        let edited_rows = start_line..old_end_line;
+        self.regions.retain_mut(|region_buffer| {
+            let region = &mut region_buffer.region;
+            if region.end_row < start_line {
+                return true;
+            }
+            if region.start_row >= old_end_line {
+                region.start_row = shift(region.start_row);
+                region.end_row = shift(region.end_row);
+                return true;
+            }
+
+            let (start_row, end_row) = (region.start_row, region.end_row);
+            if !edited_rows.contains(&start_row) && !edited_rows.contains(&end_row) {
+                region.end_row = shift(end_row);
+                return true;
+            }
+            if old_end_line != new_end_line {
+                return false;
+            }
+            std::iter::once(start_row)
+                .chain((end_row != start_row).then_some(end_row))
+                .filter(|row| edited_rows.contains(row))
+                .all(|row| {
+                    let new_line = lines.get(row - start_line).copied().unwrap_or_default();
+                    region_buffer.shift_cols(row, new_line)
+                })
+        });
+
+        (0..self.regions.len())
+            .filter(|&idx| {
+                let region = &self.regions[idx].region;
+                region.end_row >= start_line && region.start_row < new_end_line.max(start_line + 1)
+            })
+            .collect()
+    }
+
+    /// Reparses the lines between `start_line` and `new_end_line`, replacing the lines previously
+    /// between `start_line` and `old_end_line`, resuming from the checkpoint before `start_line`.
+    /// When the checkpoint at the end of the range differs from the previously stored checkpoint
+    /// (i.e. a block comment was opened), continues parsing the following lines, fetched via
+    /// `get_lines(start, end)`, until the checkpoints converge again.
+    ///
+    /// While a budgeted parse is in progress, edits reaching past the parsed lines drop the parsed
+    /// lines from `start_line` onwards, which are then parsed by `continue_parse`.
+    ///
+    /// Returns the line (exclusive) at which parsing stopped, or `None` if the filetype
+    /// isn't supported
+    pub fn reparse_range(
+        &mut self,
+        filetype: &str,
+        lines: &[&str],
+        start_line: Option<usize>,
+        old_end_line: Option<usize>,
+        new_end_line: Option<usize>,
+        mut get_lines: impl FnMut(usize, usize) -> Vec<String>,
+    ) -> Option<usize> {
+        let mut reparse =
+            self.start_reparse(filetype, lines, start_line, old_end_line, new_end_line)?;
+        while let Some((start, end)) = reparse.next_lines() {
+            let lines = get_lines(start, end);
+            self.continue_reparse(filetype, &mut reparse, &lines)?;
+        }
+        for region in std::mem::take(&mut reparse.dirty_regions) {
+            let lines = get_lines(region.start_row, region.end_row + 1);
+            self.reparse_region(region, &lines);
+        }
+        Some(reparse.end_line)
+    }
+
+    /// Reparses the lines of the edit, see `reparse_range`, returning the reparse to continue
+    /// with the lines following the edit. Returns `None` if the filetype isn't supported
+    pub fn start_reparse(
+        &mut self,
+        filetype: &str,
+        lines: &[&str],
+        start_line: Option<usize>,
+        old_end_line: Option<usize>,
+        new_end_line: Option<usize>,
+    ) -> Option<Reparse> {
+        let max_line = self.matches_by_line.len();
+        let start_line = start_line.unwrap_or(0).min(max_line);
+        let old_end_line = old_end_line.unwrap_or(max_line).min(max_line);
+
+        let initial = self.checkpoint_before_line(start_line);
+        let old_end_checkpoint = self.checkpoint_before_line(old_end_line);
+
+        let parsed = Self::parse_lines(filetype, lines, initial, &self.options)?;
+
+        let new_end_line = new_end_line.unwrap_or(start_line + parsed.matches_by_line.len());
+        let length = new_end_line - start_line;
+        let dirty_regions = self
+            .shift_regions(lines, start_line, old_end_line, new_end_line)
+            .into_iter()
+            .map(|idx| self.regions[idx].region.clone())
+            .collect();
+
+        // The lines following the edit haven't been parsed yet
+        if !self.complete && old_end_line == max_line {
+            self.splice(start_line..max_line, &ParsedLines::default(), 0);
+            return Some(Reparse {
+                end_line: new_end_line,
+                max_line: self.checkpoint_by_line.len(),
+                converged: true,
+                chunk_size: 0,
+                dirty_regions,
+            });
+        }
+
+        self.splice(start_line..old_end_line, &parsed, length);
+
+        // Keep parsing until the checkpoint at the end of a line converges with the previously
+        // stored checkpoint, at which point all the following lines would parse to the same tokens
+        let checkpoint = self.checkpoint_before_line(new_end_line);
+        Some(Reparse {
+            end_line: new_end_line,
+            max_line: self.checkpoint_by_line.len(),
+            converged: checkpoint.converges_with(&old_end_checkpoint),
+            chunk_size: 64,
+            dirty_regions,
+        })
+    }
+
+    /// Continues the reparse with the lines requested by `Reparse::next_lines`. The lines are
+    /// bounded by the lines currently stored, in case the buffer was replaced in the meantime.
+    /// Returns `None` if the filetype isn't supported
+    pub fn continue_reparse(
+        &mut self,
+        filetype: &str,
+        reparse: &mut Reparse,
+        lines: &[String],
+    ) -> Option<()> {
+        let end_line = reparse.end_line;
+        let max_line = self.checkpoint_by_line.len();
+        let lines_ref = lines
+            .iter()
+            .take(reparse.chunk_size.min(max_line.saturating_sub(end_line)))
+            .map(|str| str.as_ref())
+            .collect::<Vec<_>>();
+        if lines_ref.is_empty() {
+            reparse.converged = true;
+            return Some(());
+        }
+
+        let checkpoint = self.checkpoint_before_line(end_line);
+        let parsed = Self::parse_lines(filetype, &lines_ref, checkpoint, &self.options)?;
+
+        let old_checkpoint_by_line = &self.checkpoint_by_line[end_line..end_line + lines_ref.len()];
+        let length = match parsed
+            .checkpoint_by_line
+            .iter()
+            .zip(old_checkpoint_by_line)
+            .position(|(new_checkpoint, old_checkpoint)| {
+                new_checkpoint.converges_with(old_checkpoint)
+            }) {
+            Some(idx) => {
+                reparse.converged = true;
+                idx + 1
+            }
+            None => lines_ref.len(),
+        };
+
+        self.splice(end_line..end_line + length, &parsed, length);
+
+        reparse.end_line += length;
+        reparse.max_line = self.checkpoint_by_line.len();
+        reparse.chunk_size *= 2;
+        Some(())
+    }
+
+    /// Parses the region again with its lines, from its first to its last row, unless the region
+    /// was replaced in the meantime
+    pub fn reparse_region(&mut self, region: Region, lines: &[String]) {
+        let Some(idx) = self
+            .regions
+            .iter()
+            .position(|region_buffer| region_buffer.region == region)
+        else {
+            return;
+        };
+        if let Some(region_buffer) = RegionBuffer::parse(region, lines) {
+            self.regions[idx] = region_buffer;
+        }
+    }
+
+    /// Replaces the lines in the range with the first `length` parsed lines
+    fn splice(&mut self, range: Range<usize>, parsed: &ParsedLines, length: usize) {
+        let matches_by_line = &parsed.matches_by_line[..length];
+        let unparsed_lines = &parsed.unparsed_lines;
+        if !self.unparsed_lines.is_empty() || !unparsed_lines.is_empty() {
+            let old_unparsed_lines = std::mem::take(&mut self.unparsed_lines);
+            self.unparsed_lines = old_unparsed_lines
+                .iter()
+                .copied()
+                .filter(|&line_number| line_number < range.start)
+                .chain(
+                    unparsed_lines
+                        .iter()
+                        .filter(|&&idx| idx < length)
+                        .map(|idx| range.start + idx),
+                )
+                .chain(
+                    old_unparsed_lines
+                        .iter()
+                        .filter(|&&line_number| line_number >= range.end)
+                        .map(|line_number| line_number - range.end + range.start + length),
+                )
+                .collect();
+        }
+
+        let effects = matches_by_line
+            .iter()
+            .enumerate()
+            .map(|(idx, matches)| self.stack_effect(range.start + idx, matches))
+            .collect::<Vec<_>>();
+        self.stack_tree.splice(range.clone(), effects);
+        self.matches_by_line
+            .splice(range.clone(), matches_by_line.iter().cloned());
+        self.checkpoint_by_line.splice(
+            range.clone(),
+            parsed.checkpoint_by_line[..length].iter().cloned(),
+        );
+        self.line_text
+            .splice(range, parsed.line_text[..length].iter().cloned());
+    }
+
+    /// Converts a byte column on the line to the encoding
+    pub fn encode_col(&self, encoding: PositionEncoding, line_number: usize, col: usize) -> usize {
+        match self.line_text.get(line_number) {
+            Some(Some(line)) => encoding.from_byte(line, col),
+            _ => col,
+        }
+    }
+
+    /// Converts a column on the line in the encoding to a byte column
+    pub fn decode_col(&self, encoding: PositionEncoding, line_number: usize, col: usize) -> usize {
+        match self.line_text.get(line_number) {
+            Some(Some(line)) => encoding.to_byte(line, col),
+            _ => col,
+        }
+    }
+
+    pub fn line_matches(&self, line_number: usize) -> Option<Vec<Match>> {
+        let mut stack = self.stack_tree.stack_before(line_number);
+        self.line_matches_with_stack(line_number, &mut stack)
+    }
+
+    /// Gets the matches of the given type in the lines `start_line..end_line`, only querying the
+    /// stack once for the whole range
+    pub fn range_matches(
+        &self,
+        start_line: usize,
+        end_line: usize,
+        token_type: TokenType,
+    ) -> RangeMatches {
+        let mut range_matches = RangeMatches::default();
+        let mut stack = self.stack_tree.stack_before(start_line);
+        for line_number in start_line..end_line.min(self.matches_by_line.len()) {
+            let Some(line_matches) = self.line_matches_with_stack(line_number, &mut stack) else {
+                break;
+            };
+            for match_ in line_matches
+                .iter()
+                .filter(|match_| token_type.matches(&match_.token))
+            {
+                range_matches.push(line_number, match_);
+            }
+        }
+        range_matches
+    }
+
+    /// Gets the matches on the line with their stack heights, given the stack at the start
+    /// of the line, and updates the stack to the state at the end of the line. Includes the
+    /// matches of regions, with the stack heights of the region
+    fn line_matches_with_stack(
+        &self,
+        line_number: usize,
+        stack: &mut Vec<Token>,
+    ) -> Option<Vec<Match>> {
+        let mut matches = self.host_line_matches_with_stack(line_number, stack)?;
+        let mut regions = self
+            .regions
+            .iter()
+            .filter(|region| region.covers_line(line_number))
+            .peekable();
+        if regions.peek().is_some() {
+            matches.extend(regions.flat_map(|region| region.line_matches(line_number)));
+            matches.sort_by_key(|match_| match_.col);
+        }
+        Some(matches)
+    }
+
+    /// Same as `line_matches_with_stack` but without the matches of regions
+    fn host_line_matches_with_stack(
+        &self,
+        line_number: usize,
+        stack: &mut Vec<Token>,
+    ) -> Option<Vec<Match>> {
+        let mut matches = self.matches_by_line.get(line_number)?.clone();
+        self.without_regions(line_number, &mut matches);
+        apply_stack_heights(&mut matches, stack);
+        Some(matches)
+    }
+
+    fn host_line_matches(&self, line_number: usize) -> Option<Vec<Match>> {
+        let mut stack = self.stack_tree.stack_before(line_number);
+        self.host_line_matches_with_stack(line_number, &mut stack)
+    }
+
+    pub fn span_at(&self, line_number: usize, col: usize) -> Option<String> {
+        let line_matches = self.matches_by_line.get(line_number)?;
+        let line_state = &self.checkpoint_by_line.get(line_number)?.state;
+
+        // Look for spans starting in the current line before the desired column
+
+        let matching_span = line_matches
+            .iter()
+            .rev()
+            // Get all opening matches before the cursor on the current line
+            .filter(|match_| match_.kind == Kind::Opening && match_.col <= col)
+            // Find closing match on the same line or no match (overflows to next line)
+            .find_map(|opening| {
+                match &opening.token {
+                    Token::InlineSpan(span, _, _) | Token::BlockSpan(span, _, _) => {
+                        let closing = line_matches.iter().find(|closing| {
+                            closing.kind == Kind::Closing
+                                && closing.col > opening.col
+                                && closing.token == opening.token
+                                && closing.stack_height == opening.stack_height
+                        });
+
+                        match closing {
+                            // Ends before desired column
+                            Some(closing) if closing.col < col => None,
+                            // Extends to end of line or found closing after desired column
+                            _ => Some(span),
+                        }
+                    }
+                    _ => None,
+                }
+            });
+
+        if let Some(span) = matching_span {
+            return Some(span.to_string());
+        }
+
+        // Look for spans that started before the current line
+        match line_state {
+            // TODO: check that the span doesn't end before the cursor
+            State::InInlineSpan(span) | State::InBlockSpan(span) => Some(span.to_string()),
+            State::InInjection(injection) => Some(injection.span.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn match_at(&self, line_number: usize, col: usize) -> Option<Match> {
+        self.line_matches(line_number)?
+            .into_iter()
+            .find(|match_| (match_.col..(match_.col + match_.len())).contains(&col))
+    }
+
+    pub fn match_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+    ) -> Option<(MatchWithLine, MatchWithLine)> {
+        if let Some(region) = self.region_at(line_number, col) {
+            return region.match_pair(line_number, col);
+        }
+        let match_at_pos = self.match_at(line_number, col)?.with_line(line_number);
+
+        // Opening match
+        if match_at_pos.kind == Kind::Opening {
+            let mut stack = self.stack_tree.stack_before(line_number);
+            let closing_match =
+                (line_number..self.matches_by_line.len()).find_map(|matches_line_number| {
+                    self.host_line_matches_with_stack(matches_line_number, &mut stack)?
+                        .into_iter()
+                        .find(|match_| {
+                            (line_number != matches_line_number || match_.col > col)
+                                && match_at_pos.token == match_.token
+                                && match_at_pos.stack_height == match_.stack_height
+                        })
+                        .map(|match_| match_.with_line(matches_line_number))
+                })?;
+
+            Some((match_at_pos, closing_match))
+        }
+        // Closing match
+        else if match_at_pos.kind == Kind::Closing {
+            let opening_match = (0..=line_number).rev().find_map(|matches_line_number| {
+                self.host_line_matches(matches_line_number)?
+                    .into_iter()
+                    .rev()
+                    .find(|match_| {
+                        (line_number != matches_line_number || match_.col < col)
+                            && match_at_pos.token == match_.token
+                            && match_at_pos.stack_height == match_.stack_height
+                    })
+                    .map(|match_| match_.with_line(matches_line_number))
+            })?;
+
+            Some((opening_match, match_at_pos))
+        } else {
+            None
+        }
+    }
+
+    /// Gets the `count`th pair of delimiters enclosing the position, optionally only considering
+    /// delimiters with the given opening text (i.e. `(`). A delimiter at the position is
+    /// considered to enclose it
+    pub fn enclosing_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+        count: usize,
+        filter: Option<&str>,
+    ) -> Option<(MatchWithLine, MatchWithLine)> {
+        if let Some(pair) = self
+            .region_at(line_number, col)
+            .and_then(|region| region.enclosing_pair(line_number, col, count, filter))
+        {
+            return Some(pair);
+        }
+
+        let is_delimiter = |match_: &Match| {
+            matches!(match_.token, Token::Delimiter(_, _)) && match_.kind != Kind::Middle
+        };
+        let is_before = |match_: &Match| match match_.kind {
+            Kind::Opening => match_.col <= col,
+            _ => match_.col + match_.len() <= col,
+        };
+
+        let line_matches = self.host_line_matches(line_number)?;
+        let (before, after): (Vec<_>, Vec<_>) = line_matches
+            .into_iter()
+            .filter(is_delimiter)
+            .partition(is_before);
+
+        // Stack height at the position
+        let mut height = match before.last() {
+            Some(match_) if match_.kind == Kind::Opening => match_.stack_height? + 1,
+            Some(match_) => match_.stack_height?,
+            None => self.stack_tree.stack_before(line_number).len(),
+        };
+
+        // Opening delimiters before the position, from nearest to furthest
+        let mut openings = before
+            .into_iter()
+            .rev()
+            .map(|match_| match_.with_line(line_number))
+            .chain((0..line_number).rev().flat_map(|matches_line_number| {
+                self.host_line_matches(matches_line_number)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .rev()
+                    .map(move |match_| match_.with_line(matches_line_number))
+            }))
+            .filter(|match_| match_.kind == Kind::Opening);
+
+        // Closing delimiters after the position, from nearest to furthest
+        let mut stack = self.stack_tree.stack_before(line_number + 1);
+        let mut closings = after
+            .into_iter()
+            .map(|match_| match_.with_line(line_number))
+            .chain((line_number + 1..self.matches_by_line.len()).flat_map(
+                move |matches_line_number| {
+                    self.host_line_matches_with_stack(matches_line_number, &mut stack)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(move |match_| match_.with_line(matches_line_number))
+                },
+            ))
+            .filter(|match_| match_.kind == Kind::Closing);
+
+        // The opening delimiter at each height of the stack was the most recent one pushed at that
+        // height, and its closing delimiter is the first one to pop back to that height
+        let mut remaining = count.max(1);
+        while height > 0 {
+            height -= 1;
+            let opening = openings.find(|match_| match_.stack_height == Some(height))?;
+            // When the closing delimiter is missing, all the outer delimiters will be too
+            let closing = closings.find(|match_| match_.stack_height == Some(height))?;
+
+            if filter.is_none_or(|filter| opening.token.opening() == filter) {
+                remaining -= 1;
+                if remaining == 0 {
+                    return Some((opening, closing));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Approximate number of bytes used by the buffer, including heap allocations
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + memory::matches_heap_size(&self.matches_by_line)
+            + memory::checkpoints_heap_size(&self.checkpoint_by_line)
+            + self.unparsed_lines.capacity() * std::mem::size_of::<usize>()
+            + memory::line_text_heap_size(&self.line_text)
+            + self.stack_tree.heap_size()
+            + self
+                .regions
+                .iter()
+                .map(RegionBuffer::memory_usage)
+                .sum::<usize>()
+    }
+
+    /// Unmatched and mismatched delimiters, and unterminated strings, comments and spans
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        if self.regions.is_empty() {
+            return diagnostics::collect_diagnostics(&self.matches_by_line);
+        }
+
+        let mut matches_by_line = self.matches_by_line.clone();
+        for (line_number, matches) in matches_by_line.iter_mut().enumerate() {
+            self.without_regions(line_number, matches);
+        }
+        let mut diagnostics = diagnostics::collect_diagnostics(&matches_by_line);
+        diagnostics.extend(self.regions.iter().flat_map(RegionBuffer::diagnostics));
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.col));
+        diagnostics
+    }
+
+    /// Checkpoint at the start of the given line, which is the checkpoint at the end of the
+    /// previous line
+    fn checkpoint_before_line(&self, line_number: usize) -> Arc<Checkpoint> {
+        line_number
+            .checked_sub(1)
+            .and_then(|line_number| self.checkpoint_by_line.get(line_number))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn get_state_at_line(&self, line_number: usize) -> Option<&State> {
+        self.checkpoint_by_line
+            .get(line_number)
+            .map(|checkpoint| &checkpoint.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kind, ParsedBuffer, Token};
+
+    pub(super) fn reparse(
+        buffer: &mut ParsedBuffer,
+        lines: &[&str],
+        start_line: usize,
+        old_end_line: usize,
+        new_end_line: usize,
+    ) -> Option<usize> {
+        buffer.reparse_range(
+            "c",
+            &lines[start_line..new_end_line],
+            Some(start_line),
+            Some(old_end_line),
+            Some(new_end_line),
+            |start, end| lines[start..end].iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    pub(super) fn assert_matches_full_parse(buffer: &ParsedBuffer, lines: &[&str]) {
+        let expected = ParsedBuffer::parse("c", lines).unwrap();
+        for line_number in 0..lines.len() {
+            assert_eq!(
+                buffer.line_matches(line_number),
+                expected.line_matches(line_number),
+                "line {}",
+                line_number
+            );
+        }
+        assert_eq!(
+            buffer.checkpoint_by_line.len(),
+            expected.checkpoint_by_line.len()
+        );
+        for (checkpoint, expected_checkpoint) in buffer
+            .checkpoint_by_line
+            .iter()
+            .zip(&expected.checkpoint_by_line)
+        {
+            assert!(checkpoint.converges_with(expected_checkpoint));
+        }
+    }
+
+    #[test]
+    fn test_reparse_converges() {
+        let mut lines = vec!["int a() {", "  b();", "}", "int c() {", "  d();", "}"];
+        let mut buffer = ParsedBuffer::parse("c", &lines).unwrap();
+
+        // Doesn't change the state, so stops at the end of the edited range
+        lines[1] = "  b(1);";
+        assert_eq!(reparse(&mut buffer, &lines, 1, 2, 2), Some(2));
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Opens a block comment, so continues until the end of the buffer
+        lines[1] = "  /* b(1);";
+        assert_eq!(reparse(&mut buffer, &lines, 1, 2, 2), Some(6));
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Closes the block comment, so continues until the end of the buffer
+        lines.insert(3, "*/");
+        assert_eq!(reparse(&mut buffer, &lines, 3, 3, 4), Some(7));
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Removes the opening of the block comment, so continues until the state converges
+        lines[1] = "  b(1);";
+        assert_eq!(reparse(&mut buffer, &lines, 1, 2, 2), Some(4));
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Opens and closes a block comment, so the checkpoint converges immediately
+        lines[4] = "int c() { /* { */";
+        assert_eq!(reparse(&mut buffer, &lines, 4, 5, 5), Some(5));
+        assert_matches_full_parse(&buffer, &lines);
+    }
+
+    #[test]
+    fn test_keyword_pairs() {
+        let mut lines = vec!["function a()", "  if b then", "    c()", "  end", "end"];
+        let mut buffer = ParsedBuffer::parse("lua", &lines).unwrap();
+
+        // The closing keyword only knows which pair it closes from the stack before the range
+        lines[3] = "  else d() end";
+        buffer.reparse_range(
+            "lua",
+            &lines[3..4],
+            Some(3),
+            Some(4),
+            Some(4),
+            |_, _| vec![],
+        );
+        let expected = ParsedBuffer::parse("lua", &lines).unwrap();
+        for line_number in 0..lines.len() {
+            assert_eq!(
+                buffer.line_matches(line_number),
+                expected.line_matches(line_number),
+                "line {}",
+                line_number
+            );
+        }
+
+        let end = buffer.match_at(3, 11).unwrap();
+        assert_eq!(end.kind, Kind::Closing);
+        assert_eq!(end.token, Token::Delimiter("if".into(), "end".into()));
+        let pair = buffer.match_pair(4, 0).unwrap();
+        assert_eq!((pair.0.line, pair.0.col), (0, 0));
+        assert_eq!(buffer.match_at(3, 3).unwrap().stack_height, Some(1));
+        assert!(buffer.diagnostics().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+use mlua::IntoLua;
+
+use crate::parser::{Kind, Match};
+
+/// Matches in a range of lines, stored as parallel arrays so that converting to Lua only
+/// creates a table per field, rather than a table per match
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RangeMatches {
+    pub line: Vec<usize>,
+    pub col: Vec<usize>,
+    pub len: Vec<usize>,
+    /// Stack height of the delimiter, or -1 for other tokens
+    pub depth: Vec<isize>,
+    /// 0 for opening, 1 for closing, 2 for non-pair tokens and 3 for middle keywords, i.e. `else`
+    pub kind: Vec<u8>,
+}
+
+impl RangeMatches {
+    pub fn push(&mut self, line_number: usize, match_: &Match) {
+        self.line.push(line_number);
+        self.col.push(match_.col);
+        self.len.push(match_.len());
+        self.depth.push(
+            match_
+                .stack_height
+                .map(|stack_height| stack_height as isize)
+                .unwrap_or(-1),
+        );
+        self.kind.push(match match_.kind {
+            Kind::Opening => 0,
+            Kind::Closing => 1,
+            Kind::NonPair => 2,
+            Kind::Middle => 3,
+        });
+    }
+}
+
+impl IntoLua for RangeMatches {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+
+        table.set("line", lua.create_sequence_from(self.line)?)?;
+        table.set("col", lua.create_sequence_from(self.col)?)?;
+        table.set("len", lua.create_sequence_from(self.len)?)?;
+        table.set("depth", lua.create_sequence_from(self.depth)?)?;
+        table.set("kind", lua.create_sequence_from(self.kind)?)?;
+
+        (&table).into_lua(lua)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::ParsedBuffer;
+    use crate::parser::matcher::TokenType;
+    use crate::parser::Token;
+
+    #[test]
+    fn test_range_matches() {
+        let lines = ["{", "  a(\"b\");", "  /* ( */ [c]", "}"];
+        let buffer = ParsedBuffer::parse("c", &lines).unwrap();
+
+        let range_matches = buffer.range_matches(1, 3, TokenType::Delimiter);
+        assert_eq!(range_matches.line, vec![1, 1, 2, 2]);
+        assert_eq!(range_matches.col, vec![3, 7, 10, 12]);
+        assert_eq!(range_matches.len, vec![1, 1, 1, 1]);
+        assert_eq!(range_matches.depth, vec![1, 1, 1, 1]);
+        assert_eq!(range_matches.kind, vec![0, 1, 0, 1]);
+
+        let range_matches = buffer.range_matches(1, 3, TokenType::String);
+        assert_eq!(range_matches.line, vec![1, 1]);
+        assert_eq!(range_matches.col, vec![4, 6]);
+        assert_eq!(range_matches.depth, vec![-1, -1]);
+
+        // Same as querying each line
+        let range_matches = buffer.range_matches(0, 10, TokenType::Delimiter);
+        let line_matches = (0..lines.len())
+            .flat_map(|line_number| {
+                buffer
+                    .line_matches(line_number)
+                    .unwrap()
+                    .into_iter()
+                    .filter(|match_| matches!(match_.token, Token::Delimiter(_, _)))
+                    .map(move |match_| (line_number, match_.col, match_.stack_height))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            (0..range_matches.line.len())
+                .map(|idx| (
+                    range_matches.line[idx],
+                    range_matches.col[idx],
+                    Some(range_matches.depth[idx] as usize)
+                ))
+                .collect::<Vec<_>>(),
+            line_matches
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::mem::size_of;
+
+use mlua::prelude::*;
+
+use super::{Diagnostic, ParsedBuffer};
+use crate::parser::{Match, MatchWithLine};
+
+/// Range of the buffer in another language, i.e. SQL in a Python string from a tree-sitter
+/// injection. Rows are 0-indexed and columns are in bytes, with the end column exclusive.
+/// Columns inside a character are moved outwards to include the character
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub start_row: usize,
+    pub start_col: usize,
+    pub end_row: usize,
+    pub end_col: usize,
+    pub filetype: String,
+}
+
+impl FromLua for Region {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        // { start_row, start_col, end_row, end_col, filetype }
+        let table = LuaTable::from_lua(value, lua)?;
+        let region = Region {
+            start_row: table.get(1)?,
+            start_col: table.get(2)?,
+            end_row: table.get(3)?,
+            end_col: table.get(4)?,
+            filetype: table.get(5)?,
+        };
+        if (region.end_row, region.end_col) < (region.start_row, region.start_col) {
+            return Err(LuaError::RuntimeError(format!(
+                "Region must not end before it starts: {:?}",
+                region
+            )));
+        }
+        Ok(region)
+    }
+}
+
+/// Moves the column down to the start of the character it's in
+fn floor_char_boundary(line: &str, col: usize) -> usize {
+    let mut col = col.min(line.len());
+    while !line.is_char_boundary(col) {
+        col -= 1;
+    }
+    col
+}
+
+/// Moves the column up to the end of the character it's in
+fn ceil_char_boundary(line: &str, col: usize) -> usize {
+    let mut col = col.min(line.len());
+    while !line.is_char_boundary(col) {
+        col += 1;
+    }
+    col
+}
+
+/// Maps a column on a line to the same text on the edited line, comparing the unchanged start
+/// and end of the lines. Columns on the boundary of the changed text stay before the change,
+/// unless `after_change`. Returns `None` for columns inside the changed text
+fn shift_col(old_line: &str, new_line: &str, col: usize, after_change: bool) -> Option<usize> {
+    let (old, new) = (old_line.as_bytes(), new_line.as_bytes());
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let is_after_change = col >= old.len() - suffix;
+    if col <= prefix && !(after_change && is_after_change) {
+        Some(col)
+    } else if is_after_change {
+        Some(col + new.len() - old.len())
+    } else {
+        None
+    }
+}
+
+impl Region {
+    /// Moves the columns to character boundaries on the given lines, which are the rows of the
+    /// region, so that the region includes any character it splits
+    fn snap_to_char_boundaries(&mut self, lines: &[String]) {
+        if let Some(line) = lines.first() {
+            self.start_col = floor_char_boundary(line, self.start_col);
+        }
+        if let Some(line) = lines.get(self.end_row - self.start_row) {
+            self.end_col = ceil_char_boundary(line, self.end_col);
+        }
+    }
+
+    /// Text of the region on each of the given lines, which are the rows of the region
+    fn slice<'a>(&self, lines: &'a [String]) -> Vec<&'a str> {
+        lines
+            .iter()
+            .take(self.end_row - self.start_row + 1)
+            .enumerate()
+            .map(|(idx, line)| {
+                let row = self.start_row + idx;
+                let end = match row == self.end_row {
+                    true => self.end_col.min(line.len()),
+                    false => line.len(),
+                };
+                let start = match row == self.start_row {
+                    true => self.start_col.min(end),
+                    false => 0,
+                };
+                line.get(start..end).unwrap_or_default()
+            })
+            .collect()
+    }
+}
+
+/// Region parsed with its own matcher and delimiter stack, where the first line of the parsed
+/// buffer starts at the start of the region
+pub struct RegionBuffer {
+    pub region: Region,
+    buffer: ParsedBuffer,
+    /// Text of the first and last rows of the region, for shifting its columns on edits
+    start_line: String,
+    end_line: String,
+}
+
+impl RegionBuffer {
+    /// Parses the region, given the lines of its rows. Returns `None` if the filetype isn't
+    /// supported
+    pub fn parse(mut region: Region, lines: &[String]) -> Option<Self> {
+        region.snap_to_char_boundaries(lines);
+        let buffer = ParsedBuffer::parse(&region.filetype, &region.slice(lines))?;
+        let line = |row: usize| {
+            lines
+                .get(row - region.start_row)
+                .cloned()
+                .unwrap_or_default()
+        };
+        Some(Self {
+            start_line: line(region.start_row),
+            end_line: line(region.end_row),
+            region,
+            buffer,
+        })
+    }
+
+    /// Shifts the columns of the region on the row edited in place to the new text of the row,
+    /// without reparsing. Returns false when the start or end of the region was edited, in which
+    /// case the region no longer matches the text
+    pub fn shift_cols(&mut self, row: usize, new_line: &str) -> bool {
+        let region = &mut self.region;
+        if row == region.start_row {
+            match shift_col(&self.start_line, new_line, region.start_col, true) {
+                Some(col) => region.start_col = col,
+                None => return false,
+            }
+        }
+        if row == region.end_row {
+            match shift_col(&self.end_line, new_line, region.end_col, false) {
+                Some(col) => region.end_col = col,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, line_number: usize, col: usize) -> bool {
+        let region = &self.region;
+        (region.start_row, region.start_col) <= (line_number, col)
+            && (line_number, col) < (region.end_row, region.end_col)
+    }
+
+    pub fn covers_line(&self, line_number: usize) -> bool {
+        (self.region.start_row..=self.region.end_row).contains(&line_number)
+    }
+
+    /// Column in the buffer of the start of the line in the region
+    fn col_offset(&self, line_number: usize) -> usize {
+        match line_number == self.region.start_row {
+            true => self.region.start_col,
+            false => 0,
+        }
+    }
+
+    fn to_buffer(&self, mut match_: MatchWithLine) -> MatchWithLine {
+        match_.line += self.region.start_row;
+        match_.col += self.col_offset(match_.line);
+        match_
+    }
+
+    pub fn line_matches(&self, line_number: usize) -> Vec<Match> {
+        let col_offset = self.col_offset(line_number);
+        let mut matches = self
+            .buffer
+            .line_matches(line_number - self.region.start_row)
+            .unwrap_or_default();
+        for match_ in matches.iter_mut() {
+            match_.col += col_offset;
+        }
+        matches
+    }
+
+    pub fn match_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+    ) -> Option<(MatchWithLine, MatchWithLine)> {
+        let (opening, closing) = self.buffer.match_pair(
+            line_number - self.region.start_row,
+            col - self.col_offset(line_number),
+        )?;
+        Some((self.to_buffer(opening), self.to_buffer(closing)))
+    }
+
+    pub fn enclosing_pair(
+        &self,
+        line_number: usize,
+        col: usize,
+        count: usize,
+        filter: Option<&str>,
+    ) -> Option<(MatchWithLine, MatchWithLine)> {
+        let (opening, closing) = self.buffer.enclosing_pair(
+            line_number - self.region.start_row,
+            col - self.col_offset(line_number),
+            count,
+            filter,
+        )?;
+        Some((self.to_buffer(opening), self.to_buffer(closing)))
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.buffer.diagnostics();
+        for diagnostic in diagnostics.iter_mut() {
+            diagnostic.line += self.region.start_row;
+            diagnostic.col += self.col_offset(diagnostic.line);
+        }
+        diagnostics
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self.region.filetype.capacity()
+            + self.start_line.capacity()
+            + self.end_line.capacity()
+            + self.buffer.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::{DiagnosticReason, ParsedBuffer, Region};
+
+    #[test]
+    fn test_regions() {
+        let mut lines = vec!["f(", "  (x", ")"];
+        let mut buffer = ParsedBuffer::parse("python", &lines).unwrap();
+        let get_lines = |lines: &[&str], start: usize, end: usize| {
+            lines[start..end.min(lines.len())]
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        };
+        let pair_positions = |buffer: &ParsedBuffer, line_number, col| {
+            buffer
+                .match_pair(line_number, col)
+                .map(|(open, close)| ((open.line, open.col), (close.line, close.col)))
+        };
+
+        // The region has its own delimiter stack
+        let region = Region {
+            start_row: 1,
+            start_col: 2,
+            end_row: 1,
+            end_col: 4,
+            filetype: "c".to_string(),
+        };
+        buffer.set_regions(vec![(region, get_lines(&lines, 1, 2))]);
+        assert_eq!(pair_positions(&buffer, 0, 1), Some(((0, 1), (2, 0))));
+        assert_eq!(pair_positions(&buffer, 1, 2), None);
+        let region_match = buffer.match_at(1, 2).unwrap();
+        assert_eq!(region_match.stack_height, Some(0));
+        assert_eq!(
+            buffer
+                .diagnostics()
+                .into_iter()
+                .map(|diagnostic| (diagnostic.line, diagnostic.col, diagnostic.reason))
+                .collect::<Vec<_>>(),
+            vec![(1, 2, DiagnosticReason::UnmatchedOpening)]
+        );
+
+        // Inserting a line before the region shifts it
+        lines.insert(0, "");
+        buffer.reparse_range(
+            "python",
+            &lines[0..1],
+            Some(0),
+            Some(0),
+            Some(1),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert_eq!(pair_positions(&buffer, 1, 1), Some(((1, 1), (3, 0))));
+        assert_eq!(buffer.match_at(2, 2).unwrap().stack_height, Some(0));
+
+        // Editing the region reparses it
+        lines[2] = "  ()";
+        buffer.reparse_range(
+            "python",
+            &lines[2..3],
+            Some(2),
+            Some(3),
+            Some(3),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert_eq!(pair_positions(&buffer, 2, 2), Some(((2, 2), (2, 3))));
+        assert_eq!(pair_positions(&buffer, 1, 1), Some(((1, 1), (3, 0))));
+        assert!(buffer.diagnostics().is_empty());
+
+        // Inserting text before the region on the same row shifts its columns
+        lines[2] = " ( ()";
+        buffer.reparse_range(
+            "python",
+            &lines[2..3],
+            Some(2),
+            Some(3),
+            Some(3),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert_eq!(pair_positions(&buffer, 2, 3), Some(((2, 3), (2, 4))));
+        assert_eq!(pair_positions(&buffer, 2, 1), Some(((2, 1), (3, 0))));
+
+        // Editing across the start of the region drops it, until the regions are set again
+        lines[2] = " (x)";
+        buffer.reparse_range(
+            "python",
+            &lines[2..3],
+            Some(2),
+            Some(3),
+            Some(3),
+            |start, end| get_lines(&lines, start, end),
+        );
+        assert_eq!(pair_positions(&buffer, 2, 3), Some(((2, 1), (2, 3))));
+        assert_eq!(pair_positions(&buffer, 1, 1), Some(((1, 1), (3, 0))));
+    }
+
+    #[test]
+    fn test_region_char_boundaries() {
+        let lines = ["é(é)é"];
+        let mut buffer = ParsedBuffer::parse("python", &lines).unwrap();
+
+        // Columns inside a character include the character, rather than dropping the text
+        let region = Region {
+            start_row: 0,
+            start_col: 1,
+            end_row: 0,
+            end_col: 7,
+            filetype: "c".to_string(),
+        };
+        buffer.set_regions(vec![(region, vec![lines[0].to_string()])]);
+        let region = &buffer.regions[0].region;
+        assert_eq!((region.start_col, region.end_col), (0, 8));
+        assert_eq!(buffer.match_at(0, 2).unwrap().stack_height, Some(0));
+        assert_eq!(buffer.match_pair(0, 2).unwrap().1.col, 5);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use mlua::IntoLua;
+
+use super::BufferHandle;
+
+struct Entry {
+    /// `None` when the buffer was evicted
+    handle: Option<BufferHandle>,
+    /// Filetype of the last full parse, for reparsing after eviction
+    filetype: String,
+    last_access: AtomicU64,
+}
+
+pub enum BufferLookup {
+    Parsed(BufferHandle),
+    /// Evicted to stay under the cap, and must be reparsed with the given filetype
+    Evicted(String),
+    Missing,
+}
+
+/// Handles of the buffers parsed via the bufnr-based functions. The map is only locked while
+/// getting or inserting a handle, and each buffer has its own lock.
+///
+/// When a cap is set, the least recently accessed buffers are evicted when parsing a new buffer.
+/// Lua may still hold handles to evicted buffers, in which case they're only freed once
+/// garbage collected
+#[derive(Default)]
+pub struct BufferRegistry {
+    entries: RwLock<HashMap<usize, Entry>>,
+    clock: AtomicU64,
+    /// `None` when there's no cap
+    max_buffers: RwLock<Option<usize>>,
+}
+
+impl BufferRegistry {
+    // Inserting and removing can't leave the map in an invalid state, so poisoning can be ignored
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<usize, Entry>> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<usize, Entry>> {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn get(&self, bufnr: usize) -> BufferLookup {
+        let entries = self.read();
+        let Some(entry) = entries.get(&bufnr) else {
+            return BufferLookup::Missing;
+        };
+
+        entry.last_access.store(self.tick(), Ordering::Relaxed);
+        match &entry.handle {
+            Some(handle) => BufferLookup::Parsed(handle.clone()),
+            None => BufferLookup::Evicted(entry.filetype.clone()),
+        }
+    }
+
+    /// Inserts or replaces the buffer, evicting other buffers if over the cap
+    pub fn insert(&self, bufnr: usize, filetype: &str, handle: BufferHandle) {
+        let mut entries = self.write();
+        entries.insert(
+            bufnr,
+            Entry {
+                handle: Some(handle),
+                filetype: filetype.to_string(),
+                last_access: AtomicU64::new(self.tick()),
+            },
+        );
+        self.evict(&mut entries);
+    }
+
+    /// Returns whether the buffer existed
+    pub fn remove(&self, bufnr: usize) -> bool {
+        self.write().remove(&bufnr).is_some()
+    }
+
+    /// Sets the maximum number of parsed buffers, or removes the cap when `None`
+    pub fn set_max_buffers(&self, max_buffers: Option<usize>) {
+        *self
+            .max_buffers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = max_buffers;
+        self.evict(&mut self.write());
+    }
+
+    fn evict(&self, entries: &mut HashMap<usize, Entry>) {
+        let max_buffers = *self
+            .max_buffers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(max_buffers) = max_buffers else {
+            return;
+        };
+
+        let mut parsed = entries
+            .iter_mut()
+            .filter(|(_, entry)| entry.handle.is_some())
+            .map(|(_, entry)| entry)
+            .collect::<Vec<_>>();
+        if parsed.len() <= max_buffers {
+            return;
+        }
+
+        let evict_count = parsed.len() - max_buffers;
+        parsed.sort_by_key(|entry| entry.last_access.load(Ordering::Relaxed));
+        for entry in parsed.into_iter().take(evict_count) {
+            entry.handle = None;
+        }
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let buffers = self
+            .read()
+            .iter()
+            .filter_map(|(bufnr, entry)| {
+                let handle = entry.handle.as_ref()?;
+                Some((*bufnr, handle.read(|buffer| buffer.memory_usage())?))
+            })
+            .collect::<HashMap<_, _>>();
+        let total = buffers.values().sum();
+
+        MemoryUsage { buffers, total }
+    }
+}
+
+/// Approximate bytes used by each parsed buffer, excluding evicted buffers
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryUsage {
+    pub buffers: HashMap<usize, usize>,
+    pub total: usize,
+}
+
+impl IntoLua for MemoryUsage {
+    fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
+        let table = lua.create_table()?;
+
+        table.set("buffers", self.buffers)?;
+        table.set("total", self.total)?;
+
+        (&table).into_lua(lua)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferLookup, BufferRegistry};
+    use crate::buffer::{BufferHandle, ParsedBuffer};
+
+    #[test]
+    fn test_buffer_registry() {
+        let registry = BufferRegistry::default();
+        let handle = || BufferHandle::new(ParsedBuffer::parse("c", &["{", "}"]).unwrap());
+        let is_parsed = |bufnr| matches!(registry.get(bufnr), BufferLookup::Parsed(_));
+
+        registry.insert(1, "c", handle());
+        registry.insert(2, "c", handle());
+        registry.insert(3, "c", handle());
+        assert!(is_parsed(1));
+
+        // Evicts the least recently used buffer
+        registry.set_max_buffers(Some(2));
+        assert!(matches!(registry.get(2), BufferLookup::Evicted(filetype) if filetype == "c"));
+        assert!(is_parsed(1));
+        assert!(is_parsed(3));
+
+        registry.insert(2, "c", handle());
+        assert!(!is_parsed(1));
+        assert!(is_parsed(2));
+
+        let memory_usage = registry.memory_usage();
+        assert_eq!(memory_usage.buffers.len(), 2);
+        assert!(memory_usage.buffers[&2] > 0);
+        assert_eq!(
+            memory_usage.total,
+            memory_usage.buffers[&2] + memory_usage.buffers[&3]
+        );
+
+        assert!(registry.remove(3));
+        assert!(!registry.remove(3));
+        assert!(matches!(registry.get(3), BufferLookup::Missing));
+
+        // Removing the cap keeps the evicted buffers evicted
+        registry.set_max_buffers(None);
+        registry.insert(4, "c", handle());
+        assert!(is_parsed(2));
+        assert!(is_parsed(4));
+        assert!(!is_parsed(1));
+
+        // A cap of zero evicts every buffer, rather than removing the cap
+        registry.set_max_buffers(Some(0));
+        assert!(!is_parsed(2));
+        assert!(!is_parsed(4));
+    }
+}
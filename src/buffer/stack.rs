@@ -0,0 +1,340 @@
+use std::mem::size_of;
+use std::ops::Range;
+
+use super::memory::token_heap_size;
+use crate::parser::{Kind, Match, Token};
+
+/// Effect of a range of lines on the delimiter stack. Closing delimiters only pop from the stack
+/// when they close the top of the stack, so the effect depends on the incoming stack. However,
+/// closing delimiters that match within the range don't, which leaves:
+/// - closing delimiters that reached the bottom of the range's own stack, and will be applied to
+///   the incoming stack in order
+/// - opening delimiters left unclosed at the end of the range, pushed afterwards
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StackEffect {
+    closing: Vec<Token>,
+    opening: Vec<Token>,
+}
+
+impl StackEffect {
+    fn heap_size(&self) -> usize {
+        (self.closing.capacity() + self.opening.capacity()) * size_of::<Token>()
+            + self
+                .closing
+                .iter()
+                .chain(&self.opening)
+                .map(token_heap_size)
+                .sum::<usize>()
+    }
+
+    pub fn from_matches(matches: &[Match]) -> Self {
+        let mut effect = Self::default();
+        for match_ in matches {
+            if matches!(match_.token, Token::Delimiter(_, _)) {
+                effect.push(match_.kind, &match_.token);
+            }
+        }
+        effect
+    }
+
+    fn push(&mut self, kind: Kind, token: &Token) {
+        match kind {
+            Kind::Opening => self.opening.push(token.clone()),
+            // Middle keywords don't affect the stack
+            Kind::Middle => {}
+            _ => match self.opening.last() {
+                Some(opening) if token.closes(opening) => {
+                    self.opening.pop();
+                }
+                // Mismatched closing delimiter, ignored
+                Some(_) => {}
+                None => self.closing.push(token.clone()),
+            },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.closing.is_empty() && self.opening.is_empty()
+    }
+
+    /// Combines with the effect of the range directly following this one
+    fn then(&self, next: &Self) -> Self {
+        if next.is_empty() {
+            return self.clone();
+        }
+
+        let mut effect = self.clone();
+        for closing in &next.closing {
+            effect.push(Kind::Closing, closing);
+        }
+        effect.opening.extend(next.opening.iter().cloned());
+        effect
+    }
+
+    /// Applies the effect to the incoming stack
+    pub fn apply(&self, stack: &mut Vec<Token>) {
+        for closing in &self.closing {
+            if stack.last().is_some_and(|opening| closing.closes(opening)) {
+                stack.pop();
+            }
+        }
+        stack.extend(self.opening.iter().cloned());
+    }
+}
+
+/// Sets the stack heights of the delimiters on a line, given the stack at the start of the line,
+/// and updates the stack to the state at the end of the line. Closing delimiters take the token
+/// of the opening delimiter they close, since pairs may share a closing delimiter
+pub fn apply_stack_heights(matches: &mut [Match], stack: &mut Vec<Token>) {
+    for match_ in matches
+        .iter_mut()
+        .filter(|match_| matches!(match_.token, Token::Delimiter(_, _)))
+    {
+        match match_.kind {
+            Kind::Opening => {
+                match_.stack_height = Some(stack.len());
+                stack.push(match_.token.clone());
+            }
+            // Takes the height of the pair it belongs to
+            Kind::Middle => {
+                match_.stack_height = match stack.last() {
+                    Some(opening) if match_.token.closes(opening) => Some(stack.len() - 1),
+                    _ => Some(stack.len()),
+                };
+            }
+            _ => {
+                if stack
+                    .last()
+                    .is_some_and(|opening| match_.token.closes(opening))
+                {
+                    match_.token = stack.pop().unwrap();
+                }
+                match_.stack_height = Some(stack.len());
+            }
+        }
+    }
+}
+
+struct Node {
+    priority: u64,
+    size: usize,
+    /// Effect of this line
+    effect: StackEffect,
+    /// Effect of all the lines in this subtree, in order
+    summary: StackEffect,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+}
+
+impl Node {
+    fn update(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+
+        let mut summary = match &self.left {
+            Some(left) => left.summary.then(&self.effect),
+            None => self.effect.clone(),
+        };
+        if let Some(right) = &self.right {
+            summary = summary.then(&right.summary);
+        }
+        self.summary = summary;
+    }
+}
+
+fn heap_size(node: &Option<Box<Node>>) -> usize {
+    node.as_ref()
+        .map(|node| {
+            size_of::<Node>()
+                + node.effect.heap_size()
+                + node.summary.heap_size()
+                + heap_size(&node.left)
+                + heap_size(&node.right)
+        })
+        .unwrap_or(0)
+}
+
+fn size(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map(|node| node.size).unwrap_or(0)
+}
+
+/// Splits the tree such that the left side contains the first `idx` lines
+fn split(node: Option<Box<Node>>, idx: usize) -> (Option<Box<Node>>, Option<Box<Node>>) {
+    let Some(mut node) = node else {
+        return (None, None);
+    };
+
+    let left_size = size(&node.left);
+    if idx <= left_size {
+        let (left, right) = split(node.left.take(), idx);
+        node.left = right;
+        node.update();
+        (left, Some(node))
+    } else {
+        let (left, right) = split(node.right.take(), idx - left_size - 1);
+        node.right = left;
+        node.update();
+        (Some(node), right)
+    }
+}
+
+fn merge(left: Option<Box<Node>>, right: Option<Box<Node>>) -> Option<Box<Node>> {
+    match (left, right) {
+        (None, node) | (node, None) => node,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// Builds a balanced tree from the effects, where `priorities` must be sorted in descending order
+/// so that each node has a higher priority than its children
+fn build(effects: &mut [Option<StackEffect>], priorities: &[u64]) -> Option<Box<Node>> {
+    if effects.is_empty() {
+        return None;
+    }
+
+    let mid = effects.len() / 2;
+    let (left_effects, rest) = effects.split_at_mut(mid);
+    let (effect, right_effects) = rest.split_first_mut().unwrap();
+    let (left_priorities, right_priorities) = priorities[1..].split_at(mid);
+
+    let mut node = Box::new(Node {
+        priority: priorities[0],
+        size: 0,
+        effect: effect.take().unwrap(),
+        summary: StackEffect::default(),
+        left: build(left_effects, left_priorities),
+        right: build(right_effects, right_priorities),
+    });
+    node.update();
+    Some(node)
+}
+
+/// Per-line stack effects stored in an implicit treap, such that replacing lines and
+/// getting the stack at the start of a line only touches O(log n) nodes
+pub struct StackTree {
+    root: Option<Box<Node>>,
+    seed: u64,
+}
+
+impl StackTree {
+    pub fn new(effects: impl IntoIterator<Item = StackEffect>) -> Self {
+        let mut tree = Self {
+            root: None,
+            seed: 0x9E37_79B9_7F4A_7C15,
+        };
+        tree.root = tree.build(effects);
+        tree
+    }
+
+    /// Replaces the effects of the lines in the range with the given effects
+    pub fn splice(&mut self, range: Range<usize>, effects: impl IntoIterator<Item = StackEffect>) {
+        let middle = self.build(effects);
+
+        let (left, rest) = split(self.root.take(), range.start);
+        let (_, right) = split(rest, range.end - range.start);
+        self.root = merge(merge(left, middle), right);
+    }
+
+    /// Gets the delimiter stack at the start of the given line
+    pub fn stack_before(&self, line_number: usize) -> Vec<Token> {
+        let mut stack = vec![];
+        let mut remaining = line_number;
+        let mut node = self.root.as_deref();
+
+        while let Some(current) = node {
+            if remaining == 0 {
+                break;
+            }
+
+            let left_size = size(&current.left);
+            if remaining < left_size {
+                node = current.left.as_deref();
+                continue;
+            }
+
+            if let Some(left) = &current.left {
+                left.summary.apply(&mut stack);
+            }
+            remaining -= left_size;
+            if remaining == 0 {
+                break;
+            }
+
+            current.effect.apply(&mut stack);
+            remaining -= 1;
+            node = current.right.as_deref();
+        }
+
+        stack
+    }
+
+    /// Bytes allocated on the heap by the tree
+    pub fn heap_size(&self) -> usize {
+        heap_size(&self.root)
+    }
+
+    fn build(&mut self, effects: impl IntoIterator<Item = StackEffect>) -> Option<Box<Node>> {
+        let mut effects = effects.into_iter().map(Some).collect::<Vec<_>>();
+        let mut priorities = (0..effects.len())
+            .map(|_| self.next_priority())
+            .collect::<Vec<_>>();
+        priorities.sort_unstable_by(|a, b| b.cmp(a));
+        build(&mut effects, &priorities)
+    }
+
+    /// Xorshift, since we only need the priorities to be well distributed
+    fn next_priority(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buffer::tests::{assert_matches_full_parse, reparse};
+    use crate::buffer::ParsedBuffer;
+
+    #[test]
+    fn test_reparse_stack_heights() {
+        let mut lines = vec!["{", "  (", "  [", "  )", "  ]", "}", "{", "}"];
+        let mut buffer = ParsedBuffer::parse("c", &lines).unwrap();
+
+        // Mismatched closing delimiter
+        lines[3] = "  ]";
+        reparse(&mut buffer, &lines, 3, 4, 4);
+        assert_matches_full_parse(&buffer, &lines);
+        let pair_lines = |buffer: &ParsedBuffer, line_number, col| {
+            buffer
+                .match_pair(line_number, col)
+                .map(|(open, close)| (open.line, close.line))
+        };
+        assert_eq!(pair_lines(&buffer, 2, 2), Some((2, 3)));
+        assert_eq!(pair_lines(&buffer, 7, 0), Some((6, 7)));
+
+        // Inserting lines
+        lines.splice(1..1, ["  {", "  ((", "  }"]);
+        reparse(&mut buffer, &lines, 1, 1, 4);
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Removing lines
+        lines.drain(5..8);
+        reparse(&mut buffer, &lines, 5, 8, 5);
+        assert_matches_full_parse(&buffer, &lines);
+
+        // Closing delimiter with an empty stack
+        lines.splice(0..0, ["}", ")"]);
+        reparse(&mut buffer, &lines, 0, 0, 2);
+        assert_matches_full_parse(&buffer, &lines);
+    }
+}
@@ -1,120 +1,413 @@
 #![feature(portable_simd)]
 
 use mlua::prelude::*;
-use parser::matcher::TokenType;
-use std::collections::HashMap;
-use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::sync::{LazyLock, PoisonError, RwLock};
 
-use buffer::ParsedBuffer;
-use parser::{Match, MatchWithLine};
+use buffer::{
+    token_type_from_lua, BufferHandle, BufferLookup, BufferRegistry, Diagnostic, EnclosingPair,
+    MemoryUsage, ParseBudget, ParseOptions, ParsedBuffer, RangeMatches, Region,
+};
+use parser::{DynamicMatcher, LanguageDef, Match, MatchWithLine, PositionEncoding};
 
 pub mod buffer;
 pub mod parser;
 
-static PARSED_BUFFERS: LazyLock<Mutex<HashMap<usize, ParsedBuffer>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
-
-fn get_parsed_buffers<'a>() -> MutexGuard<'a, HashMap<usize, ParsedBuffer>> {
-    match PARSED_BUFFERS.lock() {
-        Ok(lock) => lock,
-        Err(_) => {
-            // Reset the mutex
-            PARSED_BUFFERS.clear_poison();
-            let mut parsed_buffers = PARSED_BUFFERS.lock().unwrap();
-            *parsed_buffers = HashMap::new();
-            parsed_buffers
+static PARSED_BUFFERS: LazyLock<BufferRegistry> = LazyLock::new(BufferRegistry::default);
+/// Lines longer than this many bytes are skipped when parsing buffers, `None` when there's no
+/// limit
+static MAX_LINE_LENGTH: RwLock<Option<usize>> = RwLock::new(None);
+
+fn parse_options() -> ParseOptions {
+    ParseOptions {
+        max_line_length: *MAX_LINE_LENGTH
+            .read()
+            .unwrap_or_else(PoisonError::into_inner),
+    }
+}
+
+fn nvim_buf_get_lines(lua: &Lua, bufnr: usize, start: i64, end: i64) -> LuaResult<Vec<String>> {
+    lua.globals()
+        .get::<LuaTable>("vim")?
+        .get::<LuaTable>("api")?
+        .get::<LuaFunction>("nvim_buf_get_lines")?
+        .call((bufnr, start, end, false))
+}
+
+/// Gets the handle of a buffer parsed via `parse_buffer`, transparently reparsing it if it was
+/// evicted
+fn get_parsed_buffer(lua: &Lua, bufnr: usize) -> LuaResult<Option<BufferHandle>> {
+    match PARSED_BUFFERS.get(bufnr) {
+        BufferLookup::Parsed(parsed_buffer) => Ok(Some(parsed_buffer)),
+        BufferLookup::Evicted(filetype) => {
+            let lines = nvim_buf_get_lines(lua, bufnr, 0, -1)?;
+            let lines_ref = lines.iter().map(|str| str.as_ref()).collect::<Vec<_>>();
+            let Some(parsed_buffer) =
+                ParsedBuffer::parse_with_options(&filetype, &lines_ref, parse_options())
+            else {
+                return Ok(None);
+            };
+
+            let parsed_buffer = BufferHandle::new(parsed_buffer);
+            PARSED_BUFFERS.insert(bufnr, &filetype, parsed_buffer.clone());
+            Ok(Some(parsed_buffer))
         }
+        BufferLookup::Missing => Ok(None),
     }
 }
 
+/// Parses the lines into a new buffer handle, independent of any neovim buffer
+fn parse(_lua: &Lua, (filetype, lines): (String, Vec<String>)) -> LuaResult<Option<BufferHandle>> {
+    let lines_ref = lines.iter().map(|str| str.as_ref()).collect::<Vec<_>>();
+    Ok(ParsedBuffer::parse(&filetype, &lines_ref).map(BufferHandle::new))
+}
+
+/// Returns whether the buffer is parseable, the line (exclusive) at which parsing stopped, and
+/// whether the whole buffer has been parsed. For incremental parses, `get_lines(start, end)` may
+/// be called to fetch the lines after `new_end_line` when the edit changed the state of the
+/// following lines.
+///
+/// When a budget is given, full parses ignore `lines` and instead fetch the lines via
+/// `get_lines`, stopping once the budget runs out. The rest of the buffer must then be parsed
+/// via `continue_parse`
+#[allow(clippy::type_complexity)]
 fn parse_buffer(
     _lua: &Lua,
-    (bufnr, filetype, lines, start_line, old_end_line, new_end_line): (
+    (bufnr, filetype, lines, start_line, old_end_line, new_end_line, get_lines, budget): (
         usize,
         String,
         Vec<String>,
         Option<usize>,
         Option<usize>,
         Option<usize>,
+        Option<LuaFunction>,
+        Option<ParseBudget>,
     ),
-) -> LuaResult<bool> {
-    let lines_ref = lines.iter().map(|str| str.as_ref()).collect::<Vec<_>>();
-
-    let mut parsed_buffers = get_parsed_buffers();
+) -> LuaResult<(bool, Option<usize>, bool)> {
+    // Budgeted full parse
+    if let (None, Some(budget), Some(get_lines)) = (start_line, budget, &get_lines) {
+        let parsed_buffer = match PARSED_BUFFERS.get(bufnr) {
+            BufferLookup::Parsed(parsed_buffer) => parsed_buffer
+                .start_parse(&filetype, parse_options())
+                .then_some(parsed_buffer),
+            _ => ParsedBuffer::unparsed(&filetype, parse_options()).map(BufferHandle::new),
+        };
+        let Some(parsed_buffer) = parsed_buffer else {
+            return Ok((false, None, false));
+        };
+        PARSED_BUFFERS.insert(bufnr, &filetype, parsed_buffer.clone());
 
-    // Incremental parse
-    if let Some(parsed_buffer) = parsed_buffers.get_mut(&bufnr) {
-        Ok(parsed_buffer.reparse_range(
+        let progress = parsed_buffer.continue_parse(&filetype, &budget, get_lines)?;
+        Ok(match progress {
+            Some(progress) => (true, Some(progress.end_line), progress.done),
+            None => (false, None, false),
+        })
+    }
+    // Full parse
+    else if start_line.is_none() {
+        let parsed_buffer = match PARSED_BUFFERS.get(bufnr) {
+            BufferLookup::Parsed(parsed_buffer) => parsed_buffer
+                .parse_with_options(&filetype, &lines, parse_options())
+                .then_some(parsed_buffer),
+            _ => {
+                let lines_ref = lines.iter().map(|str| str.as_ref()).collect::<Vec<_>>();
+                ParsedBuffer::parse_with_options(&filetype, &lines_ref, parse_options())
+                    .map(BufferHandle::new)
+            }
+        };
+        let did_parse = parsed_buffer.is_some();
+        if let Some(parsed_buffer) = parsed_buffer {
+            PARSED_BUFFERS.insert(bufnr, &filetype, parsed_buffer);
+        }
+        Ok((did_parse, did_parse.then_some(lines.len()), did_parse))
+    }
+    // Incremental parse, which fails when the buffer hasn't been parsed, was evicted or was reset
+    // after a panic, in which case the caller should run a full parse
+    else {
+        let BufferLookup::Parsed(parsed_buffer) = PARSED_BUFFERS.get(bufnr) else {
+            return Ok((false, None, false));
+        };
+        let end_line = parsed_buffer.reparse(
             &filetype,
-            &lines_ref,
+            &lines,
             start_line,
             old_end_line,
             new_end_line,
-        ))
-    }
-    // Full parse
-    else if let Some(parsed_buffer) = ParsedBuffer::parse(&filetype, &lines_ref) {
-        parsed_buffers.insert(bufnr, parsed_buffer);
-        Ok(true)
-    } else {
-        Ok(false)
+            get_lines.as_ref(),
+        )?;
+        let done = parsed_buffer
+            .read(|parsed_buffer| parsed_buffer.is_complete())
+            .unwrap_or(false);
+        Ok((end_line.is_some(), end_line, done))
     }
 }
 
-fn get_line_matches(
+/// Starts a full parse of the buffer on a background thread, see
+/// [`BufferHandle::parse_in_background`]. Until the result is ready, the previous parse of the
+/// buffer is used, or no matches if it hasn't been parsed before. Poll `get_generation` for when
+/// the result has been swapped in. Returns whether the buffer is parseable
+fn parse_buffer_async(
     _lua: &Lua,
-    (bufnr, line_number, token_type): (usize, usize, Option<u8>),
-) -> LuaResult<Vec<Match>> {
-    let parsed_buffers = get_parsed_buffers();
-    let token_type = token_type
-        // TODO: don't ignore the error
-        .and_then(|token_type| token_type.try_into().ok())
-        .unwrap_or(TokenType::Delimiter);
-
-    if let Some(parsed_buffer) = parsed_buffers.get(&bufnr) {
-        if let Some(line_matches) = parsed_buffer.line_matches(line_number) {
-            return Ok(line_matches
-                .iter()
-                .filter(|m| token_type.matches(&m.token))
-                .cloned()
-                .collect());
+    (bufnr, filetype, lines): (usize, String, Vec<String>),
+) -> LuaResult<bool> {
+    let parsed_buffer = match PARSED_BUFFERS.get(bufnr) {
+        BufferLookup::Parsed(parsed_buffer) => parsed_buffer,
+        _ => {
+            let Some(parsed_buffer) = ParsedBuffer::unparsed(&filetype, parse_options()) else {
+                return Ok(false);
+            };
+            BufferHandle::new(parsed_buffer)
         }
+    };
+    if !parsed_buffer.parse_in_background(&filetype, lines, parse_options()) {
+        return Ok(false);
     }
+    PARSED_BUFFERS.insert(bufnr, &filetype, parsed_buffer);
+    Ok(true)
+}
 
-    Ok(Vec::new())
+/// Returns the generation of the buffer, which is incremented whenever it's replaced by a full
+/// parse, and whether a background parse is in progress. The generation is `nil` when the buffer
+/// isn't parsed
+fn get_generation(_lua: &Lua, bufnr: usize) -> LuaResult<(Option<u64>, bool)> {
+    Ok(match PARSED_BUFFERS.get(bufnr) {
+        BufferLookup::Parsed(parsed_buffer) => {
+            (Some(parsed_buffer.generation()), parsed_buffer.is_parsing())
+        }
+        _ => (None, false),
+    })
 }
 
-fn get_span_at(_lua: &Lua, (bufnr, row, col): (usize, usize, usize)) -> LuaResult<Option<String>> {
-    Ok(get_parsed_buffers()
-        .get(&bufnr)
-        .and_then(|parsed_buffer| parsed_buffer.span_at(row, col)))
+/// Continues a budgeted full parse started by `parse_buffer`, fetching the lines via
+/// `get_lines(start, end)`. Returns whether the buffer is parsed, the range of lines parsed by
+/// this call, and whether the whole buffer has been parsed
+fn continue_parse(
+    _lua: &Lua,
+    (bufnr, filetype, get_lines, budget): (usize, String, LuaFunction, Option<ParseBudget>),
+) -> LuaResult<(bool, Option<usize>, Option<usize>, bool)> {
+    let BufferLookup::Parsed(parsed_buffer) = PARSED_BUFFERS.get(bufnr) else {
+        return Ok((false, None, None, false));
+    };
+    let budget = budget.unwrap_or_default();
+    Ok(
+        match parsed_buffer.continue_parse(&filetype, &budget, &get_lines)? {
+            Some(progress) => (
+                true,
+                Some(progress.start_line),
+                Some(progress.end_line),
+                progress.done,
+            ),
+            None => (false, None, None, false),
+        },
+    )
 }
 
-fn get_match_at(_lua: &Lua, (bufnr, row, col): (usize, usize, usize)) -> LuaResult<Option<Match>> {
-    Ok(get_parsed_buffers()
-        .get(&bufnr)
-        .and_then(|parsed_buffer| parsed_buffer.match_at(row, col)))
+fn register_language(_lua: &Lua, (filetype, def): (String, LanguageDef)) -> LuaResult<()> {
+    let matcher = DynamicMatcher::new(def).map_err(LuaError::RuntimeError)?;
+    parser::register_language(&filetype, matcher);
+    Ok(())
+}
+
+fn get_line_matches(
+    lua: &Lua,
+    (bufnr, line_number, token_type, encoding): (
+        usize,
+        usize,
+        Option<u8>,
+        Option<PositionEncoding>,
+    ),
+) -> LuaResult<Vec<Match>> {
+    let token_type = token_type_from_lua(token_type);
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .map(|parsed_buffer| {
+            parsed_buffer.line_matches(line_number, token_type, encoding.unwrap_or_default())
+        })
+        .unwrap_or_default())
+}
+
+/// Gets the matches in the lines `start_line..end_line`, as parallel arrays
+fn get_range_matches(
+    lua: &Lua,
+    (bufnr, start_line, end_line, token_type, encoding): (
+        usize,
+        usize,
+        usize,
+        Option<u8>,
+        Option<PositionEncoding>,
+    ),
+) -> LuaResult<RangeMatches> {
+    let token_type = token_type_from_lua(token_type);
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .map(|parsed_buffer| {
+            parsed_buffer.range_matches(
+                start_line,
+                end_line,
+                token_type,
+                encoding.unwrap_or_default(),
+            )
+        })
+        .unwrap_or_default())
+}
+
+fn get_span_at(
+    lua: &Lua,
+    (bufnr, row, col, encoding): (usize, usize, usize, Option<PositionEncoding>),
+) -> LuaResult<Option<String>> {
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .and_then(|parsed_buffer| parsed_buffer.span_at(row, col, encoding.unwrap_or_default())))
+}
+
+fn get_match_at(
+    lua: &Lua,
+    (bufnr, row, col, encoding): (usize, usize, usize, Option<PositionEncoding>),
+) -> LuaResult<Option<Match>> {
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .and_then(|parsed_buffer| parsed_buffer.match_at(row, col, encoding.unwrap_or_default())))
 }
 
 fn get_match_pair(
-    _lua: &Lua,
-    (bufnr, row, col): (usize, usize, usize),
+    lua: &Lua,
+    (bufnr, row, col, encoding): (usize, usize, usize, Option<PositionEncoding>),
 ) -> LuaResult<Option<Vec<MatchWithLine>>> {
-    Ok(get_parsed_buffers()
-        .get(&bufnr)
-        .and_then(|parsed_buffer| parsed_buffer.match_pair(row, col))
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .and_then(|parsed_buffer| parsed_buffer.match_pair(row, col, encoding.unwrap_or_default()))
         .map(|(open, close)| vec![open, close]))
 }
 
+#[allow(clippy::type_complexity)]
+fn get_enclosing_pair(
+    lua: &Lua,
+    (bufnr, row, col, count, filter, encoding): (
+        usize,
+        usize,
+        usize,
+        Option<usize>,
+        Option<String>,
+        Option<PositionEncoding>,
+    ),
+) -> LuaResult<Option<EnclosingPair>> {
+    let Some(parsed_buffer) = get_parsed_buffer(lua, bufnr)? else {
+        return Ok(None);
+    };
+
+    // The parsed buffer doesn't keep the text of every line, so get the lines for calculating
+    // the inner range
+    let get_line = |line: usize| {
+        nvim_buf_get_lines(lua, bufnr, line as i64, line as i64 + 1)
+            .ok()?
+            .into_iter()
+            .next()
+    };
+
+    Ok(parsed_buffer.enclosing_pair(
+        row,
+        col,
+        count.unwrap_or(1),
+        filter.as_deref(),
+        encoding.unwrap_or_default(),
+        get_line,
+    ))
+}
+
+/// Replaces the regions of the buffer parsed with their own filetype, i.e. from tree-sitter
+/// injections, returning whether the buffer is parsed. Each region is a
+/// `{ start_row, start_col, end_row, end_col, filetype }` tuple with an exclusive end column
+fn set_regions(lua: &Lua, (bufnr, regions): (usize, Vec<Region>)) -> LuaResult<bool> {
+    let Some(parsed_buffer) = get_parsed_buffer(lua, bufnr)? else {
+        return Ok(false);
+    };
+
+    // Fetched before locking the buffer, since Lua code reached from the call may query it
+    let regions = regions
+        .into_iter()
+        .map(|region| {
+            let (start, end) = (region.start_row as i64, region.end_row as i64 + 1);
+            Ok((region, nvim_buf_get_lines(lua, bufnr, start, end)?))
+        })
+        .collect::<LuaResult<Vec<_>>>()?;
+    Ok(parsed_buffer
+        .write(|parsed_buffer| parsed_buffer.set_regions(regions))
+        .is_some())
+}
+
+/// Frees the buffer, returning whether it had been parsed
+fn free_buffer(_lua: &Lua, bufnr: usize) -> LuaResult<bool> {
+    Ok(PARSED_BUFFERS.remove(bufnr))
+}
+
+/// Caps the number of parsed buffers, evicting the least recently used buffers, which are
+/// transparently reparsed when next accessed. Removes the cap when `None`
+fn set_max_buffers(_lua: &Lua, max_buffers: Option<usize>) -> LuaResult<()> {
+    PARSED_BUFFERS.set_max_buffers(max_buffers);
+    Ok(())
+}
+
+/// Skips lines longer than the given number of bytes in buffers parsed afterwards, since
+/// minified files may have megabytes on a single line. Removes the limit when `None`
+fn set_max_line_length(_lua: &Lua, max_line_length: Option<usize>) -> LuaResult<()> {
+    *MAX_LINE_LENGTH
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = max_line_length;
+    Ok(())
+}
+
+/// Lines skipped in the buffer for being longer than the maximum line length
+fn get_unparsed_lines(lua: &Lua, bufnr: usize) -> LuaResult<Vec<usize>> {
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .and_then(|parsed_buffer| {
+            parsed_buffer.read(|parsed_buffer| parsed_buffer.unparsed_lines().to_vec())
+        })
+        .unwrap_or_default())
+}
+
+fn memory_usage(_lua: &Lua, _: ()) -> LuaResult<MemoryUsage> {
+    Ok(PARSED_BUFFERS.memory_usage())
+}
+
+fn get_diagnostics(
+    lua: &Lua,
+    (bufnr, encoding): (usize, Option<PositionEncoding>),
+) -> LuaResult<Vec<Diagnostic>> {
+    Ok(get_parsed_buffer(lua, bufnr)?
+        .map(|parsed_buffer| parsed_buffer.diagnostics(encoding.unwrap_or_default()))
+        .unwrap_or_default())
+}
+
 // NOTE: skip_memory_check greatly improves performance
 // https://github.com/mlua-rs/mlua/issues/318
 #[mlua::lua_module(skip_memory_check)]
 fn blink_pairs(lua: &Lua) -> LuaResult<LuaTable> {
     let exports = lua.create_table()?;
+    exports.set("parse", lua.create_function(parse)?)?;
+    exports.set("get_buffer", lua.create_function(get_parsed_buffer)?)?;
     exports.set("parse_buffer", lua.create_function(parse_buffer)?)?;
+    exports.set("continue_parse", lua.create_function(continue_parse)?)?;
+    exports.set(
+        "parse_buffer_async",
+        lua.create_function(parse_buffer_async)?,
+    )?;
+    exports.set("get_generation", lua.create_function(get_generation)?)?;
+    exports.set("register_language", lua.create_function(register_language)?)?;
     exports.set("get_line_matches", lua.create_function(get_line_matches)?)?;
+    exports.set("get_range_matches", lua.create_function(get_range_matches)?)?;
     exports.set("get_span_at", lua.create_function(get_span_at)?)?;
     exports.set("get_match_at", lua.create_function(get_match_at)?)?;
     exports.set("get_match_pair", lua.create_function(get_match_pair)?)?;
+    exports.set(
+        "get_enclosing_pair",
+        lua.create_function(get_enclosing_pair)?,
+    )?;
+    exports.set("get_diagnostics", lua.create_function(get_diagnostics)?)?;
+    exports.set("set_regions", lua.create_function(set_regions)?)?;
+    exports.set("free_buffer", lua.create_function(free_buffer)?)?;
+    exports.set("set_max_buffers", lua.create_function(set_max_buffers)?)?;
+    exports.set(
+        "set_max_line_length",
+        lua.create_function(set_max_line_length)?,
+    )?;
+    exports.set(
+        "get_unparsed_lines",
+        lua.create_function(get_unparsed_lines)?,
+    )?;
+    exports.set("memory_usage", lua.create_function(memory_usage)?)?;
     Ok(exports)
 }
@@ -0,0 +1,131 @@
+use mlua::prelude::*;
+use unicode_width::UnicodeWidthChar;
+
+/// Unit of the columns passed to and returned from the Lua API. The parser always works with
+/// byte columns, which are converted given the text of the line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PositionEncoding {
+    /// UTF-8 code units, as used by extmarks
+    #[default]
+    Byte,
+    /// Unicode scalar values, i.e. UTF-32 in LSP
+    Codepoint,
+    /// UTF-16 code units, the default in LSP
+    Utf16,
+    /// Screen cells, where tabs expand to the next multiple of `tabstop`, wide characters take
+    /// two cells and control characters are displayed as `^X`
+    Display { tabstop: usize },
+}
+
+impl PositionEncoding {
+    fn char_width(&self, char: char, col: usize) -> usize {
+        match self {
+            PositionEncoding::Byte => char.len_utf8(),
+            PositionEncoding::Codepoint => 1,
+            PositionEncoding::Utf16 => char.len_utf16(),
+            PositionEncoding::Display { tabstop } if char == '\t' => {
+                let tabstop = (*tabstop).max(1);
+                tabstop - col % tabstop
+            }
+            PositionEncoding::Display { .. } => char.width().unwrap_or(2),
+        }
+    }
+
+    /// Whether the columns of the line are the same in every encoding, i.e. printable ASCII
+    pub fn is_plain(line: &str) -> bool {
+        line.bytes().all(|byte| (b' '..=b'~').contains(&byte))
+    }
+
+    /// Converts a byte column in the line to this encoding. Columns past the end of the line
+    /// are kept relative to the end of the line
+    pub fn from_byte(&self, line: &str, byte_col: usize) -> usize {
+        if *self == PositionEncoding::Byte {
+            return byte_col;
+        }
+
+        let mut col = 0;
+        for (byte_idx, char) in line.char_indices() {
+            if byte_idx >= byte_col {
+                return col;
+            }
+            col += self.char_width(char, col);
+        }
+        col + byte_col.saturating_sub(line.len())
+    }
+
+    /// Converts a column in this encoding to a byte column in the line. Columns in the middle
+    /// of a character, i.e. the second cell of a wide character, resolve to the start of it
+    pub fn to_byte(&self, line: &str, col: usize) -> usize {
+        if *self == PositionEncoding::Byte {
+            return col;
+        }
+
+        let mut current_col = 0;
+        for (byte_idx, char) in line.char_indices() {
+            let next_col = current_col + self.char_width(char, current_col);
+            if next_col > col {
+                return byte_idx;
+            }
+            current_col = next_col;
+        }
+        line.len() + col.saturating_sub(current_col)
+    }
+}
+
+/// Accepts `'byte'` (or `'utf-8'`), `'codepoint'` (or `'utf-32'`), `'utf-16'`, `'display'`
+/// or `{ 'display', tabstop = 4 }`, where the tabstop defaults to 8
+impl FromLua for PositionEncoding {
+    fn from_lua(value: LuaValue, lua: &Lua) -> LuaResult<Self> {
+        let (name, tabstop) = match &value {
+            LuaValue::Table(table) => (table.get::<String>(1)?, table.get("tabstop")?),
+            _ => (String::from_lua(value.clone(), lua)?, None),
+        };
+
+        match name.as_str() {
+            "byte" | "utf-8" => Ok(PositionEncoding::Byte),
+            "codepoint" | "utf-32" => Ok(PositionEncoding::Codepoint),
+            "utf-16" => Ok(PositionEncoding::Utf16),
+            "display" => Ok(PositionEncoding::Display {
+                tabstop: tabstop.unwrap_or(8),
+            }),
+            _ => Err(LuaError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "PositionEncoding".to_string(),
+                message: Some(format!("unknown position encoding: {name}")),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PositionEncoding;
+
+    #[test]
+    fn test_position_encoding() {
+        // 'é' is 2 bytes, '𝄞' is 4 bytes and 2 UTF-16 code units, '世' is 3 bytes and 2 cells wide
+        let line = "é𝄞\t世(";
+        let paren = line.find('(').unwrap();
+        assert_eq!(paren, 10);
+
+        let display = PositionEncoding::Display { tabstop: 4 };
+        let cases = [
+            (PositionEncoding::Byte, 10),
+            (PositionEncoding::Codepoint, 4),
+            (PositionEncoding::Utf16, 5),
+            (display, 6),
+        ];
+        for (encoding, col) in cases {
+            assert_eq!(encoding.from_byte(line, paren), col, "{encoding:?}");
+            assert_eq!(encoding.to_byte(line, col), paren, "{encoding:?}");
+        }
+
+        // Tab expands to the next tabstop
+        assert_eq!(display.from_byte(line, 7), 4);
+        // Second cell of the wide character
+        assert_eq!(display.to_byte(line, 5), 7);
+        // Past the end of the line
+        assert_eq!(PositionEncoding::Utf16.from_byte(line, 14), 9);
+        assert_eq!(PositionEncoding::Utf16.to_byte(line, 9), 14);
+    }
+}
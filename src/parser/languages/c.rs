@@ -9,7 +9,7 @@ define_matcher!(C {
     ],
     line_comment: ["//"],
     block_comment: ["/*" => "*/"],
-    char: ["'"],
+    char: ["'" max_chars 4],
     string: ["\""],
     block_string: []
 });
@@ -9,7 +9,7 @@ define_matcher!(Cpp {
     ],
     line_comment: ["//"],
     block_comment: ["/*" => "*/"],
-    char: ["'"],
+    char: ["'" max_chars 4],
     string: ["\""],
-    block_string: ["R\"(" => ")\""]
+    block_string: ["R\"(" => ")\"" escape none]
 });
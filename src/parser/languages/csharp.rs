@@ -11,5 +11,5 @@ define_matcher!(CSharp {
     block_comment: ["/*" => "*/"],
     char: ["'"],
     string: ["\""],
-    block_string: ["@\"" => "\""]
+    block_string: ["@\"" => "\"" escape doubled]
 });
@@ -8,7 +8,13 @@ define_matcher!(Dart {
         "{" => "}"
     ],
     line_comment: ["//"],
-    block_comment: ["/*" => "*/"],
+    block_comment: ["/*" => "*/" nested],
     string: ["\"", "'"],
-    block_string: ["\"\"\"" => "\"\"\"", "'''" => "'''"]
+    block_string: ["\"\"\"" => "\"\"\"", "'''" => "'''"],
+    interpolation: [
+        "\"" => "${" => "}",
+        "'" => "${" => "}",
+        "\"\"\"" => "${" => "}",
+        "'''" => "${" => "}"
+    ]
 });
@@ -9,5 +9,13 @@ define_matcher!(Elixir {
     ],
     line_comment: ["#"],
     string: ["\""],
-    block_string: ["\"\"\"" => "\"\"\""]
+    block_string: ["\"\"\"" => "\"\"\""],
+    interpolation: [
+        "\"" => "#{" => "}",
+        "\"\"\"" => "#{" => "}"
+    ],
+    keyword_pairs: [
+        "do" => ["else"] => "end",
+        "fn" => "end"
+    ]
 });
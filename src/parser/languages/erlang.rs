@@ -2,11 +2,21 @@ use crate::parser::*;
 use matcher_macros::define_matcher;
 
 define_matcher!(Erlang {
+    // `$` followed by any character is a character literal, i.e. `$(`
     delimiters: [
-        "(" => ")",
-        "[" => "]",
-        "{" => "}"
+        "(" not_after "$" => ")" not_after "$",
+        "[" not_after "$" => "]" not_after "$",
+        "{" not_after "$" => "}" not_after "$"
     ],
-    line_comment: ["%"],
-    string: ["\""]
+    line_comment: ["%" not_after "$"],
+    string: ["\"" not_after "$"],
+    // Function references have no `end`, i.e. `fun f/1`, so they unbalance the keywords
+    keyword_pairs: [
+        "case" => "end",
+        "if" => "end",
+        "receive" => "end",
+        "fun" => "end",
+        "try" => "end",
+        "begin" => "end"
+    ]
 });
@@ -5,10 +5,11 @@ define_matcher!(FSharp {
     delimiters: [
         "(" => ")",
         "[" => "]",
-        "{" => "}"
+        "{" => "}",
+        "[|" => "|]"
     ],
     line_comment: ["//"],
-    block_comment: ["(*" => "*)"],
+    block_comment: ["(*" => "*)" nested],
     string: ["\""],
-    block_string: ["\"\"\"" => "\"\"\""]
+    block_string: ["\"\"\"" => "\"\"\"" escape none]
 });
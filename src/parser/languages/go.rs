@@ -9,6 +9,7 @@ define_matcher!(Go {
     ],
     line_comment: ["//"],
     block_comment: ["/*" => "*/"],
+    char: ["'"],
     string: ["\""],
-    block_string: ["`" => "`"]
+    block_string: ["`" => "`" escape none]
 });
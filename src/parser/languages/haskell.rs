@@ -8,6 +8,8 @@ define_matcher!(Haskell {
         "{" => "}"
     ],
     line_comment: ["--"],
-    block_comment: ["{-" => "-}"],
-    string: ["\""]
+    block_comment: ["{-" => "-}" nested],
+    string: ["\""],
+    // `'` after an identifier is a prime, i.e. `x'`
+    char: ["'" not_after_identifier]
 });
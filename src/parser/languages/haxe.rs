@@ -10,5 +10,6 @@ define_matcher!(Haxe {
     line_comment: ["//"],
     block_comment: ["/*" => "*/"],
     char: ["'"],
-    string: ["\""]
+    string: ["\""],
+    regex: ["~/" => "/"]
 });
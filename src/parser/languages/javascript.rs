@@ -10,5 +10,7 @@ define_matcher!(JavaScript {
     line_comment: ["//"],
     block_comment: ["/*" => "*/"],
     string: ["\"", "'"],
-    block_string: ["`" => "`"]
+    block_string: ["`" => "`"],
+    interpolation: ["`" => "${" => "}"],
+    regex: ["/" => "/"]
 });
@@ -0,0 +1,34 @@
+use crate::parser::*;
+use matcher_macros::define_matcher;
+
+define_matcher!(Julia {
+    delimiters: [
+        "(" => ")",
+        "[" => "]",
+        "{" => "}"
+    ],
+    line_comment: ["#"],
+    block_comment: ["#=" => "=#" nested],
+    string: ["\""],
+    // `'` after an identifier or a closing delimiter is the adjoint, i.e. `x'`
+    char: ["'" not_after_identifier not_after ")]}'"],
+    block_string: ["\"\"\"" => "\"\"\""],
+    interpolation: [
+        "\"" => "$(" => ")",
+        "\"\"\"" => "$(" => ")"
+    ],
+    // `if` and `for` also appear in comprehensions, i.e. `[x for x in xs if x > 0]`, and
+    // `begin` and `end` as indices, i.e. `a[begin:end]`
+    keyword_pairs: [
+        "function" => "end" not_after "[:",
+        "if" after_indent => ["elseif", "else"] => "end",
+        "for" after_indent => "end",
+        "while" => "end",
+        "begin" not_after "[:" => "end",
+        "let" => "end",
+        "struct" => "end",
+        "module" => "end",
+        "do" => "end",
+        "try" => ["catch", "finally"] => "end"
+    ]
+});
@@ -8,7 +8,11 @@ define_matcher!(Kotlin {
         "{" => "}"
     ],
     line_comment: ["//"],
-    block_comment: ["/*" => "*/"],
+    block_comment: ["/*" => "*/" nested],
     string: ["\""],
-    block_string: ["\"\"\"" => "\"\"\""]
+    block_string: ["\"\"\"" => "\"\"\"" escape none],
+    interpolation: [
+        "\"" => "${" => "}",
+        "\"\"\"" => "${" => "}"
+    ]
 });
@@ -8,6 +8,6 @@ define_matcher!(Lean {
         "{" => "}"
     ],
     line_comment: ["--"],
-    block_comment: ["/-" => "-/"],
+    block_comment: ["/-" => "-/" nested],
     string: ["\""]
 });
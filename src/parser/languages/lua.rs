@@ -10,5 +10,11 @@ define_matcher!(Lua {
     line_comment: ["--"],
     block_comment: ["--[[" => "]]"],
     string: ["\"", "'"],
-    block_string: ["[[" => "]]"]
+    block_string: ["[[" => "]]" escape none],
+    keyword_pairs: [
+        "function" => "end",
+        "do" => "end",
+        "if" => ["elseif", "else"] => "end",
+        "repeat" => "until"
+    ]
 });
@@ -11,7 +11,6 @@ define_matcher!(Markdown {
     },
     block_span: {
         math: "$$" => "$$",
-        code: "```" => "```"
+        code: "```" => "```" after_indent
     },
 });
-
@@ -9,9 +9,11 @@ mod fsharp;
 mod go;
 mod haskell;
 mod haxe;
+mod hcl;
 mod java;
 mod javascript;
 mod json;
+mod julia;
 mod kotlin;
 mod latex;
 mod lean;
@@ -43,9 +45,11 @@ pub use fsharp::FSharp;
 pub use go::Go;
 pub use haskell::Haskell;
 pub use haxe::Haxe;
+pub use hcl::Hcl;
 pub use java::Java;
 pub use javascript::JavaScript;
 pub use json::Json;
+pub use julia::Julia;
 pub use kotlin::Kotlin;
 pub use latex::Latex;
 pub use lean::Lean;
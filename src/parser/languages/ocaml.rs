@@ -5,8 +5,16 @@ define_matcher!(OCaml {
     delimiters: [
         "(" => ")",
         "[" => "]",
-        "{" => "}"
+        "{" => "}",
+        "[|" => "|]"
     ],
-    block_comment: ["(*" => "*)"],
-    string: ["\""]
+    block_comment: ["(*" => "*)" nested],
+    string: ["\""],
+    keyword_pairs: [
+        "begin" => "end",
+        "struct" => "end",
+        "sig" => "end",
+        "object" => "end",
+        "do" => "done"
+    ]
 });
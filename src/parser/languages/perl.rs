@@ -8,5 +8,13 @@ define_matcher!(Perl {
         "{" => "}"
     ],
     line_comment: ["#"],
+    block_comment: [
+        "=pod" at_line_start => "=cut" at_line_start,
+        "=head1" at_line_start => "=cut" at_line_start,
+        "=begin" at_line_start => "=cut" at_line_start
+    ],
     string: ["\"", "'"],
+    heredoc: ["<<~" indented, "<<"],
+    // `//` is the defined-or operator
+    regex: ["/" not_after "/" => "/"]
 });
@@ -9,5 +9,6 @@ define_matcher!(Php {
     ],
     line_comment: ["//", "#"],
     block_comment: ["/*" => "*/"],
-    string: ["\"", "'"]
+    string: ["\"", "'"],
+    heredoc: ["<<<" indented spaced]
 });
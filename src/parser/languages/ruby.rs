@@ -8,6 +8,22 @@ define_matcher!(Ruby {
         "{" => "}"
     ],
     line_comment: ["#"],
-    block_comment: ["=begin" => "end"],
-    string: ["\"", "'"]
+    block_comment: ["=begin" at_line_start => "=end" at_line_start],
+    string: ["\"", "'"],
+    interpolation: ["\"" => "#{" => "}"],
+    heredoc: ["<<~" indented, "<<-" indented, "<<"],
+    regex: ["/" => "/"],
+    // Method calls, symbols and block comment markers aren't keywords, i.e. `x.class`, `:end`
+    // or `=begin`, and neither are modifiers, i.e. `return if x`
+    keyword_pairs: [
+        "def" not_after ".:" => "end" not_after ".:=",
+        "class" not_after ".:" => "end",
+        "module" not_after ".:" => "end",
+        "do" not_after ".:" => "end",
+        "if" after_indent => ["elsif", "else"] => "end",
+        "unless" after_indent => "end",
+        "case" not_after ".:" => ["when", "else"] => "end",
+        "while" after_indent => "end",
+        "begin" not_after ".:=" => ["rescue", "ensure"] => "end"
+    ]
 });
@@ -8,12 +8,13 @@ define_matcher!(Rust {
         "{" => "}"
     ],
     line_comment: ["//"],
-    block_comment: ["/*" => "*/"],
+    block_comment: ["/*" => "*/" nested],
     char: ["'"],
     block_string: [
         "\"" => "\"",
-        "r#\"" => "\"#",
-        "r##\"" => "\"##",
-        "r###\"" => "\"###"
+        "r\"" not_after_identifier => "\"" escape none,
+        "r#\"" not_after_identifier => "\"#" escape none,
+        "r##\"" not_after_identifier => "\"##" escape none,
+        "r###\"" not_after_identifier => "\"###" escape none
     ]
 });
@@ -8,7 +8,7 @@ define_matcher!(Scala {
         "{" => "}"
     ],
     line_comment: ["//"],
-    block_comment: ["/*" => "*/"],
+    block_comment: ["/*" => "*/" nested],
     string: ["\""],
-    block_string: ["\"\"\"" => "\"\"\""]
+    block_string: ["\"\"\"" => "\"\"\"" escape none]
 });
@@ -7,6 +7,16 @@ define_matcher!(Shell {
         "[" => "]",
         "{" => "}"
     ],
-    line_comment: ["#"],
-    string: ["\"", "'"]
+    line_comment: ["#" after_whitespace],
+    string: ["\"", "'" escape none],
+    keyword_pairs: [
+        "if" => ["elif", "else"] => "fi",
+        "case" => "esac",
+        "do" => "done"
+    ],
+    heredoc: ["<<-" indented spaced, "<<" spaced],
+    interpolation: [
+        "\"" => "$(" => ")",
+        "\"" => "${" => "}"
+    ]
 });
@@ -8,7 +8,11 @@ define_matcher!(Swift {
         "{" => "}"
     ],
     line_comment: ["//"],
-    block_comment: ["/*" => "*/"],
+    block_comment: ["/*" => "*/" nested],
     string: ["\"", "'"],
-    block_string: ["\"\"\"" => "\"\"\""]
+    block_string: ["\"\"\"" => "\"\"\""],
+    interpolation: [
+        "\"" => "\\(" => ")",
+        "\"\"\"" => "\\(" => ")"
+    ]
 });
@@ -8,6 +8,6 @@ define_matcher!(Toml {
         "{" => "}"
     ],
     line_comment: ["#"],
-    string: ["\"", "'"],
+    string: ["\"", "'" escape none],
     block_string: ["\"\"\"" => "\"\"\""]
 });
@@ -0,0 +1,59 @@
+/// Finds the closing quote of a character literal opening at the column, i.e. `'a'`, `'é'`,
+/// `'\''`, `'\x7f'` or `'\u{1F600}'`, returning its column. Up to `max_chars` characters or
+/// escape sequences are allowed between the quotes, i.e. 4 for multi-character constants in C
+///
+/// Returns `None` when the quote doesn't start a character literal, i.e. lifetimes in Rust
+pub fn char_literal_at(line: &str, col: usize, delim: u8, max_chars: usize) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut col = col + 1;
+    let mut chars = 0;
+    loop {
+        let byte = *bytes.get(col)?;
+        if byte == delim && chars > 0 {
+            return Some(col);
+        }
+        if chars == max_chars {
+            return None;
+        }
+
+        col = match byte {
+            b'\\' => escape_end(line, col + 1)?,
+            // Multi-byte UTF-8 characters count as a single character
+            _ => col + line[col..].chars().next()?.len_utf8(),
+        };
+        chars += 1;
+    }
+}
+
+/// Finds the end of the escape sequence following the backslash, i.e. `n`, `'`, `x7f`, `012`,
+/// `u00e9`, `U0001F600` or `u{1F600}`
+fn escape_end(line: &str, col: usize) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let hex_digits = |col: usize, max: usize| {
+        col + bytes[col..]
+            .iter()
+            .take(max)
+            .take_while(|byte| byte.is_ascii_hexdigit())
+            .count()
+    };
+
+    match *bytes.get(col)? {
+        b'x' => Some(hex_digits(col + 1, 2)),
+        b'u' if bytes.get(col + 1) == Some(&b'{') => {
+            let close = hex_digits(col + 2, 6);
+            (bytes.get(close) == Some(&b'}')).then_some(close + 1)
+        }
+        b'u' => Some(hex_digits(col + 1, 4)),
+        b'U' => Some(hex_digits(col + 1, 8)),
+        b'0'..=b'7' => Some(
+            col + bytes[col..]
+                .iter()
+                .take(3)
+                .take_while(|byte| matches!(byte, b'0'..=b'7'))
+                .count(),
+        ),
+        byte if byte.is_ascii() => Some(col + 1),
+        // Escaped multi-byte UTF-8 character
+        _ => Some(col + line[col..].chars().next()?.len_utf8()),
+    }
+}
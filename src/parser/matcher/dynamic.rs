@@ -0,0 +1,978 @@
+use std::borrow::Cow;
+
+use itertools::MultiPeek;
+use mlua::prelude::*;
+
+use super::{
+    char_literal_at, doubled_at, heredoc_at, keyword_at, regex_at, skip, skip_keyword, skip_to,
+    starts_with, Escape, Kind, Match, Matcher, Predicate, Token,
+};
+use crate::parser::{CharPos, Heredoc, Interpolation, State};
+
+/// Language definition with the same sections as `define_matcher!`, built from a Lua table
+/// when registering a language at runtime
+///
+/// ```lua
+/// {
+///   delimiters = { { '(', ')' }, { '{', '}' } },
+///   line_comment = { '#' },
+///   -- Nested block comments, i.e. `/* /* */ */`, must set `nested`
+///   block_comment = { { '/*', '*/', nested = true } },
+///   string = { '"' },
+///   -- Either the quote, or the quote with the maximum number of characters between the
+///   -- quotes, i.e. for multi-character literals like `'abcd'` in C
+///   char = { { "'", max_chars = 4 } },
+///   block_string = { { '"""', '"""' } },
+///   inline_span = { math = { '$', '$' } },
+///   block_span = { code = { '```', '```' } },
+///   -- Opening keyword, any middle keywords, then the closing keyword
+///   keyword_pairs = { { 'do', 'end' }, { 'if', 'elseif', 'else', 'end' } },
+///   -- Opening pattern of the string or block string, then the interpolation pair, which may
+///   -- open with a backslash, i.e. `\(` in Swift
+///   interpolation = { { '"""', '${', '}' } },
+///   -- Set `indented` when the terminator may be indented, and `spaced` when whitespace is
+///   -- allowed before the terminator, i.e. `<< EOF`
+///   heredoc = { { '<<-', indented = true, spaced = true }, { '<<', spaced = true } },
+///   -- Regex literals, which only open after operators and some keywords, i.e. `return`
+///   regex = { { '/', '/' } },
+///   -- Patterns and keywords which only match in some contexts, given the text before them
+///   at_line_start = { '=begin', '=end' },
+///   after_indent = { '```' },
+///   after_whitespace = { '#' },
+///   not_after_identifier = { "'" },
+///   -- Pattern, then the ASCII characters it must not directly follow
+///   not_after = { { '(', '$' } },
+///   -- Escapes inside strings, block strings and block comments, by their opening pattern:
+///   -- 'none', 'backslash', 'doubled' or a single ASCII character
+///   escape = { ['`'] = 'none', ['@"'] = 'doubled' },
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LanguageDef {
+    pub delimiters: Vec<(String, String)>,
+    pub line_comments: Vec<String>,
+    /// Opening and closing pattern, and whether the comments may be nested
+    pub block_comments: Vec<(String, String, bool)>,
+    pub strings: Vec<String>,
+    /// Opening quote, and the maximum number of characters between the quotes
+    pub chars: Vec<(String, usize)>,
+    pub block_strings: Vec<(String, String)>,
+    pub inline_spans: Vec<(String, String, String)>,
+    pub block_spans: Vec<(String, String, String)>,
+    /// Opening keyword, middle keywords and closing keyword
+    pub keyword_pairs: Vec<(String, Vec<String>, String)>,
+    /// Opening pattern of the string or block string, and the opening and closing pattern of
+    /// the interpolation
+    pub interpolations: Vec<(String, String, String)>,
+    /// Opening pattern, whether the terminator may be indented and whether whitespace is
+    /// allowed before the terminator
+    pub heredocs: Vec<(String, bool, bool)>,
+    /// Opening and closing pattern of regex literals
+    pub regexes: Vec<(String, String)>,
+    /// Conditions on the text before the patterns
+    pub predicates: Vec<(String, Predicate)>,
+    /// Escapes inside the strings, block strings and block comments, by their opening pattern
+    pub escapes: Vec<(String, Escape)>,
+}
+
+impl FromLua for LanguageDef {
+    fn from_lua(value: LuaValue, _lua: &Lua) -> LuaResult<Self> {
+        let table = match value {
+            LuaValue::Table(table) => table,
+            _ => {
+                return Err(LuaError::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "LanguageDef".to_string(),
+                    message: Some("expected a table".to_string()),
+                })
+            }
+        };
+
+        fn get_list(table: &LuaTable, key: &str) -> LuaResult<Vec<String>> {
+            Ok(table.get::<Option<Vec<String>>>(key)?.unwrap_or_default())
+        }
+
+        fn get_pairs(table: &LuaTable, key: &str) -> LuaResult<Vec<(String, String)>> {
+            get_list_of_pairs(table.get::<Option<Vec<Vec<String>>>>(key)?, key)
+        }
+
+        fn get_spans(table: &LuaTable, key: &str) -> LuaResult<Vec<(String, String, String)>> {
+            let Some(spans) = table.get::<Option<LuaTable>>(key)? else {
+                return Ok(vec![]);
+            };
+            let mut spans = spans
+                .pairs::<String, Vec<String>>()
+                .map(|pair| {
+                    let (name, pattern) = pair?;
+                    let (open, close) = to_pair(pattern, key)?;
+                    Ok((name, open, close))
+                })
+                .collect::<LuaResult<Vec<_>>>()?;
+            // Lua tables have no ordering so sort for deterministic priority
+            spans.sort();
+            Ok(spans)
+        }
+
+        fn get_keyword_pairs(
+            table: &LuaTable,
+            key: &str,
+        ) -> LuaResult<Vec<(String, Vec<String>, String)>> {
+            table
+                .get::<Option<Vec<Vec<String>>>>(key)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|mut keywords| {
+                    if keywords.len() < 2 {
+                        return Err(LuaError::RuntimeError(format!(
+                            "Expected {{ open, ...middle, close }} keywords in `{}`",
+                            key
+                        )));
+                    }
+                    let close = keywords.pop().unwrap();
+                    let open = keywords.remove(0);
+                    Ok((open, keywords, close))
+                })
+                .collect()
+        }
+
+        fn get_interpolations(
+            table: &LuaTable,
+            key: &str,
+        ) -> LuaResult<Vec<(String, String, String)>> {
+            table
+                .get::<Option<Vec<Vec<String>>>>(key)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(
+                    |interpolation| match <[String; 3]>::try_from(interpolation) {
+                        Ok([string, open, close]) => Ok((string, open, close)),
+                        Err(_) => Err(LuaError::RuntimeError(format!(
+                            "Expected {{ string, open, close }} in `{}`",
+                            key
+                        ))),
+                    },
+                )
+                .collect()
+        }
+
+        fn get_block_comments(
+            table: &LuaTable,
+            key: &str,
+        ) -> LuaResult<Vec<(String, String, bool)>> {
+            table
+                .get::<Option<Vec<LuaTable>>>(key)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|comment| {
+                    let pair = comment.sequence_values().collect::<LuaResult<Vec<_>>>()?;
+                    let (open, close) = to_pair(pair, key)?;
+                    let nested = comment.get::<Option<bool>>("nested")?.unwrap_or(false);
+                    Ok((open, close, nested))
+                })
+                .collect()
+        }
+
+        fn get_chars(table: &LuaTable, key: &str) -> LuaResult<Vec<(String, usize)>> {
+            table
+                .get::<Option<Vec<LuaValue>>>(key)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|char| match char {
+                    LuaValue::Table(char) => {
+                        let delim = char.get::<Option<String>>(1)?.ok_or_else(|| {
+                            LuaError::RuntimeError(format!("Expected {{ delim }} in `{}`", key))
+                        })?;
+                        let max_chars = char.get::<Option<usize>>("max_chars")?.unwrap_or(1);
+                        Ok((delim, max_chars))
+                    }
+                    LuaValue::String(delim) => Ok((delim.to_str()?.to_string(), 1)),
+                    char => Err(LuaError::RuntimeError(format!(
+                        "Expected a string or {{ delim, max_chars = n }} in `{}`, got {}",
+                        key,
+                        char.type_name()
+                    ))),
+                })
+                .collect()
+        }
+
+        fn get_heredocs(table: &LuaTable, key: &str) -> LuaResult<Vec<(String, bool, bool)>> {
+            table
+                .get::<Option<Vec<LuaTable>>>(key)?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|heredoc| {
+                    let open = heredoc.get::<Option<String>>(1)?.ok_or_else(|| {
+                        LuaError::RuntimeError(format!("Expected {{ open }} in `{}`", key))
+                    })?;
+                    let indented = heredoc.get::<Option<bool>>("indented")?.unwrap_or(false);
+                    let spaced = heredoc.get::<Option<bool>>("spaced")?.unwrap_or(false);
+                    Ok((open, indented, spaced))
+                })
+                .collect()
+        }
+
+        fn get_predicates(table: &LuaTable) -> LuaResult<Vec<(String, Predicate)>> {
+            let flags = [
+                ("at_line_start", Predicate::AtLineStart),
+                ("after_indent", Predicate::AfterIndent),
+                ("after_whitespace", Predicate::AfterWhitespace),
+                ("not_after_identifier", Predicate::NotAfterIdentifier),
+            ];
+            let mut predicates = vec![];
+            for (key, predicate) in flags {
+                for pattern in get_list(table, key)? {
+                    predicates.push((pattern, predicate.clone()));
+                }
+            }
+            for (pattern, chars) in get_pairs(table, "not_after")? {
+                predicates.push((pattern, Predicate::NotAfter(Cow::Owned(chars))));
+            }
+            Ok(predicates)
+        }
+
+        fn get_escapes(table: &LuaTable, key: &str) -> LuaResult<Vec<(String, Escape)>> {
+            let Some(escapes) = table.get::<Option<LuaTable>>(key)? else {
+                return Ok(vec![]);
+            };
+            let mut escapes = escapes
+                .pairs::<String, String>()
+                .map(|pair| {
+                    let (open, escape) = pair?;
+                    let escape = match escape.as_bytes() {
+                        b"none" => Escape::None,
+                        b"backslash" => Escape::Backslash,
+                        b"doubled" => Escape::Doubled,
+                        [byte] if byte.is_ascii() => Escape::Char(*byte),
+                        _ => {
+                            return Err(LuaError::RuntimeError(format!(
+                                "Expected 'none', 'backslash', 'doubled' or a single ASCII character in `{}`: {:?}",
+                                key, escape
+                            )))
+                        }
+                    };
+                    Ok((open, escape))
+                })
+                .collect::<LuaResult<Vec<_>>>()?;
+            // Lua tables have no ordering so sort for deterministic output
+            escapes.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Ok(escapes)
+        }
+
+        fn get_list_of_pairs(
+            pairs: Option<Vec<Vec<String>>>,
+            key: &str,
+        ) -> LuaResult<Vec<(String, String)>> {
+            pairs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|pair| to_pair(pair, key))
+                .collect()
+        }
+
+        fn to_pair(pair: Vec<String>, key: &str) -> LuaResult<(String, String)> {
+            match <[String; 2]>::try_from(pair) {
+                Ok([open, close]) => Ok((open, close)),
+                Err(_) => Err(LuaError::RuntimeError(format!(
+                    "Expected {{ open, close }} pairs in `{}`",
+                    key
+                ))),
+            }
+        }
+
+        Ok(LanguageDef {
+            delimiters: get_pairs(&table, "delimiters")?,
+            line_comments: get_list(&table, "line_comment")?,
+            block_comments: get_block_comments(&table, "block_comment")?,
+            strings: get_list(&table, "string")?,
+            chars: get_chars(&table, "char")?,
+            block_strings: get_pairs(&table, "block_string")?,
+            inline_spans: get_spans(&table, "inline_span")?,
+            block_spans: get_spans(&table, "block_span")?,
+            keyword_pairs: get_keyword_pairs(&table, "keyword_pairs")?,
+            interpolations: get_interpolations(&table, "interpolation")?,
+            heredocs: get_heredocs(&table, "heredoc")?,
+            regexes: get_pairs(&table, "regex")?,
+            predicates: get_predicates(&table)?,
+            escapes: get_escapes(&table, "escape")?,
+        })
+    }
+}
+
+type Str = Cow<'static, str>;
+
+/// Runtime equivalent of the matchers generated by `define_matcher!`. Arms are tried in the
+/// same order as the macro:
+/// - block comments, block strings, heredocs and block spans
+/// - line comments, strings, and chars
+/// - regexes
+/// - inline spans
+/// - delimiters
+/// - finally, keyword pairs
+///
+/// Interpolations are tried before closing the string containing them
+#[derive(Debug, Clone, Default)]
+pub struct DynamicMatcher {
+    tokens: Vec<u8>,
+    keyword_starts: Vec<u8>,
+    /// Index of the delimiter pair and whether it's the opening pattern, with the longest
+    /// patterns first so that i.e. `{{` takes priority over `{`
+    delimiter_arms: Vec<(usize, bool)>,
+
+    delimiters: Vec<(Str, Str)>,
+    line_comments: Vec<Str>,
+    block_comments: Vec<(Str, Str, bool)>,
+    strings: Vec<Str>,
+    chars: Vec<(Str, usize)>,
+    block_strings: Vec<(Str, Str)>,
+    inline_spans: Vec<(Str, Str, Str)>,
+    block_spans: Vec<(Str, Str, Str)>,
+    keyword_pairs: Vec<(Str, Vec<Str>, Str)>,
+    interpolations: Vec<(Str, Str, Str)>,
+    /// Longest patterns first, so that i.e. `<<-` takes priority over `<<`
+    heredocs: Vec<(Str, bool, bool)>,
+    regexes: Vec<(Str, Str)>,
+    predicates: Vec<(Str, Predicate)>,
+    escapes: Vec<(Str, Escape)>,
+}
+
+impl DynamicMatcher {
+    pub fn new(def: LanguageDef) -> Result<Self, String> {
+        fn validate(pattern: &str, single_char: bool) -> Result<(), String> {
+            if pattern.is_empty() {
+                Err("Pattern must not be empty".to_string())
+            } else if pattern.contains(['\n', '\\']) {
+                Err(format!(
+                    "Pattern must not contain a newline or backslash: {:?}",
+                    pattern
+                ))
+            } else if single_char && pattern.len() != 1 {
+                Err(format!(
+                    "Delimiter must be a single character: {:?}",
+                    pattern
+                ))
+            } else {
+                Ok(())
+            }
+        }
+
+        for (char, _) in &def.chars {
+            validate(char, true)?;
+        }
+        for pattern in def
+            .delimiters
+            .iter()
+            .flat_map(|(o, c)| [o, c])
+            .chain(def.line_comments.iter())
+            .chain(def.strings.iter())
+            .chain(def.block_comments.iter().flat_map(|(o, c, _)| [o, c]))
+            .chain(def.block_strings.iter().flat_map(|(o, c)| [o, c]))
+            .chain(def.inline_spans.iter().flat_map(|(_, o, c)| [o, c]))
+            .chain(def.block_spans.iter().flat_map(|(_, o, c)| [o, c]))
+            .chain(def.interpolations.iter().flat_map(|(s, _, c)| [s, c]))
+            .chain(def.heredocs.iter().map(|(open, _, _)| open))
+            .chain(def.regexes.iter().flat_map(|(o, c)| [o, c]))
+        {
+            validate(pattern, false)?;
+        }
+        // Interpolations may open with a backslash, i.e. `\(` in Swift
+        for (_, open, _) in &def.interpolations {
+            match open.strip_prefix('\\') {
+                Some(rest) if !rest.is_empty() => validate(rest, false)?,
+                _ => validate(open, false)?,
+            }
+        }
+        for keyword in def.keyword_pairs.iter().flat_map(|(open, middles, close)| {
+            std::iter::once(open)
+                .chain(middles)
+                .chain(std::iter::once(close))
+        }) {
+            if keyword.is_empty()
+                || !keyword
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+            {
+                return Err(format!(
+                    "Keyword must only contain ASCII letters, digits and underscores: {:?}",
+                    keyword
+                ));
+            }
+        }
+
+        for (_, predicate) in &def.predicates {
+            if let Predicate::NotAfter(chars) = predicate {
+                if !chars.is_ascii() {
+                    return Err(format!("Expected ASCII characters: {:?}", chars));
+                }
+            }
+        }
+
+        let pair = |(open, close): (String, String)| (Cow::Owned(open), Cow::Owned(close));
+        let span = |(name, open, close): (String, String, String)| {
+            (Cow::Owned(name), Cow::Owned(open), Cow::Owned(close))
+        };
+
+        let keyword_pair = |(open, middles, close): (String, Vec<String>, String)| {
+            (
+                Cow::Owned(open),
+                middles.into_iter().map(Cow::Owned).collect(),
+                Cow::Owned(close),
+            )
+        };
+
+        let mut matcher = Self {
+            tokens: vec![],
+            keyword_starts: vec![],
+            delimiter_arms: vec![],
+            delimiters: def.delimiters.into_iter().map(pair).collect(),
+            line_comments: def.line_comments.into_iter().map(Cow::Owned).collect(),
+            block_comments: def
+                .block_comments
+                .into_iter()
+                .map(|(open, close, nested)| (Cow::Owned(open), Cow::Owned(close), nested))
+                .collect(),
+            strings: def.strings.into_iter().map(Cow::Owned).collect(),
+            chars: def
+                .chars
+                .into_iter()
+                .map(|(delim, max_chars)| (Cow::Owned(delim), max_chars))
+                .collect(),
+            block_strings: def.block_strings.into_iter().map(pair).collect(),
+            inline_spans: def.inline_spans.into_iter().map(span).collect(),
+            block_spans: def.block_spans.into_iter().map(span).collect(),
+            keyword_pairs: def.keyword_pairs.into_iter().map(keyword_pair).collect(),
+            interpolations: def.interpolations.into_iter().map(span).collect(),
+            heredocs: def
+                .heredocs
+                .into_iter()
+                .map(|(open, indented, spaced)| (Cow::Owned(open), indented, spaced))
+                .collect(),
+            regexes: def.regexes.into_iter().map(pair).collect(),
+            predicates: def
+                .predicates
+                .into_iter()
+                .map(|(pattern, predicate)| (Cow::Owned(pattern), predicate))
+                .collect(),
+            escapes: def
+                .escapes
+                .into_iter()
+                .map(|(open, escape)| (Cow::Owned(open), escape))
+                .collect(),
+        };
+        matcher
+            .heredocs
+            .sort_by_key(|(open, _, _)| std::cmp::Reverse(open.len()));
+        matcher.tokens = matcher.collect_tokens();
+        matcher.keyword_starts = matcher.collect_keyword_starts();
+        let mut delimiter_arms = (0..matcher.delimiters.len())
+            .flat_map(|idx| [(idx, true), (idx, false)])
+            .collect::<Vec<_>>();
+        delimiter_arms.sort_by_key(|&(idx, is_open)| {
+            std::cmp::Reverse(matcher.delimiter(idx, is_open).len())
+        });
+        matcher.delimiter_arms = delimiter_arms;
+        Ok(matcher)
+    }
+
+    /// Opening and closing pattern of the pair on the stack, where keyword pairs come after
+    /// the delimiters
+    fn pair(&self, idx: usize) -> (&Str, &Str) {
+        match self.delimiters.get(idx) {
+            Some((open, close)) => (open, close),
+            None => {
+                let (open, _, close) = &self.keyword_pairs[idx - self.delimiters.len()];
+                (open, close)
+            }
+        }
+    }
+
+    /// Pops the pair on top of the stack when it shares the closing pattern of the given pair,
+    /// returning the index of the closed pair, or the given pair when there's none
+    fn close_pair(&self, stack: &mut Vec<usize>, idx: usize) -> usize {
+        match stack.last() {
+            Some(&top) if self.pair(top).1 == self.pair(idx).1 => {
+                stack.pop();
+                top
+            }
+            _ => idx,
+        }
+    }
+
+    /// Checks if the pattern starts at the current token, and that the text before it matches
+    /// the predicates of the pattern
+    fn starts_with<I>(
+        &self,
+        line: &str,
+        tokens: &mut MultiPeek<I>,
+        token: CharPos,
+        pattern: &str,
+    ) -> bool
+    where
+        I: Iterator<Item = CharPos>,
+    {
+        starts_with(tokens, token, pattern) && self.predicates_match(line, token.col, pattern)
+    }
+
+    /// Checks if the keyword starts at the column, and that the text before it matches the
+    /// predicates of the keyword
+    fn keyword_at(&self, line: &str, col: usize, keyword: &str) -> bool {
+        keyword_at(line, col, keyword) && self.predicates_match(line, col, keyword)
+    }
+
+    fn predicates_match(&self, line: &str, col: usize, pattern: &str) -> bool {
+        self.predicates
+            .iter()
+            .filter(|(p, _)| p == pattern)
+            .all(|(_, predicate)| predicate.matches(line, col))
+    }
+
+    /// Checks if the closing pattern is doubled, escaping it, when the state with the opening
+    /// pattern uses `Escape::Doubled`
+    fn doubled_at(&self, line: &str, token: CharPos, open: &str, close: &str) -> bool {
+        self.escapes
+            .iter()
+            .any(|(o, escape)| o == open && *escape == Escape::Doubled)
+            && doubled_at(line, token.col, close)
+    }
+
+    fn delimiter(&self, idx: usize, is_open: bool) -> &Str {
+        let (open, close) = &self.delimiters[idx];
+        if is_open {
+            open
+        } else {
+            close
+        }
+    }
+
+    /// Collects all unique bytes the tokenizer needs to find
+    fn collect_tokens(&self) -> Vec<u8> {
+        let patterns = self
+            .delimiters
+            .iter()
+            .flat_map(|(o, c)| [o, c])
+            .chain(self.line_comments.iter())
+            .chain(self.block_comments.iter().flat_map(|(o, c, _)| [o, c]))
+            .chain(self.strings.iter())
+            .chain(self.chars.iter().map(|(delim, _)| delim))
+            .chain(self.block_strings.iter().flat_map(|(o, c)| [o, c]))
+            .chain(self.inline_spans.iter().flat_map(|(_, o, c)| [o, c]))
+            .chain(self.block_spans.iter().flat_map(|(_, o, c)| [o, c]))
+            .chain(self.interpolations.iter().flat_map(|(_, o, c)| [o, c]))
+            .chain(self.heredocs.iter().map(|(open, _, _)| open))
+            .chain(self.regexes.iter().flat_map(|(o, c)| [o, c]));
+
+        let mut tokens = patterns.flat_map(|p| p.bytes()).collect::<Vec<_>>();
+        tokens.extend(self.escapes.iter().filter_map(|(_, escape)| match escape {
+            Escape::Char(byte) => Some(*byte),
+            _ => None,
+        }));
+        tokens.sort();
+        tokens.dedup();
+        tokens
+    }
+
+    /// Collects the unique first bytes of the keywords
+    fn collect_keyword_starts(&self) -> Vec<u8> {
+        let mut keyword_starts = self
+            .keyword_pairs
+            .iter()
+            .flat_map(|(open, middles, close)| {
+                std::iter::once(open)
+                    .chain(middles)
+                    .chain(std::iter::once(close))
+            })
+            .map(|keyword| keyword.as_bytes()[0])
+            .collect::<Vec<_>>();
+        keyword_starts.sort();
+        keyword_starts.dedup();
+        keyword_starts
+    }
+
+    fn call_normal<I>(
+        &self,
+        matches: &mut Vec<Match>,
+        stack: &mut Vec<usize>,
+        line: &str,
+        tokens: &mut MultiPeek<I>,
+        token: CharPos,
+        escaped: bool,
+    ) -> Option<State>
+    where
+        I: Iterator<Item = CharPos>,
+    {
+        // 1. Block comment patterns
+        for (open, close, _) in &self.block_comments {
+            if self.starts_with(line, tokens, token, open) {
+                let token_ = Token::BlockComment(open.clone(), close.clone());
+                matches.push(Match::new(Kind::Opening, token_, token.col));
+                skip(tokens, open);
+                return Some(State::InBlockComment(open.clone(), 1));
+            }
+        }
+
+        // 2. Block string patterns
+        for (open, close) in &self.block_strings {
+            if self.starts_with(line, tokens, token, open) {
+                let token_ = Token::BlockString(open.clone(), close.clone());
+                matches.push(Match::new(Kind::Opening, token_, token.col));
+                skip(tokens, open);
+                return Some(State::InBlockString(open.clone()));
+            }
+        }
+
+        // Heredoc patterns, where the parser looks for the terminator from the next line
+        for (open, indented, spaced) in &self.heredocs {
+            if !self.starts_with(line, tokens, token, open) {
+                continue;
+            }
+            if let Some((terminator, end)) = heredoc_at(line, token.col, open, *spaced) {
+                let heredoc = Heredoc::new(open.clone(), terminator.to_string(), *indented);
+                matches.push(Match::new(Kind::Opening, heredoc.token(), token.col));
+                skip_to(tokens, end);
+                return Some(State::InHeredoc(Box::new(heredoc)));
+            }
+        }
+
+        // 3. Block span patterns
+        for (name, open, close) in &self.block_spans {
+            if self.starts_with(line, tokens, token, open) {
+                let token_ = Token::BlockSpan(name.clone(), open.clone(), close.clone());
+                matches.push(Match::new(Kind::Opening, token_, token.col));
+                skip(tokens, open);
+                return Some(State::InBlockSpan(name.clone()));
+            }
+        }
+
+        // 4. Line comment patterns
+        for comment in &self.line_comments {
+            if !escaped && self.starts_with(line, tokens, token, comment) {
+                matches.push(Match::line_comment(comment.clone(), token.col));
+                skip(tokens, comment);
+                return Some(State::InLineComment);
+            }
+        }
+
+        // 5. String patterns
+        for delim in &self.strings {
+            if self.starts_with(line, tokens, token, delim) {
+                let token_ = Token::String(delim.clone());
+                matches.push(Match::new(Kind::Opening, token_, token.col));
+                skip(tokens, delim);
+                return Some(State::InString(delim.clone()));
+            }
+        }
+
+        // 6. Character literal patterns
+        for (delim, max_chars) in &self.chars {
+            if !self.starts_with(line, tokens, token, delim) {
+                continue;
+            }
+
+            if let Some(close_col) =
+                char_literal_at(line, token.col, delim.as_bytes()[0], *max_chars)
+            {
+                let token_ = Token::String(delim.clone());
+                matches.push(Match::new(Kind::Opening, token_.clone(), token.col));
+                matches.push(Match::new(Kind::Closing, token_, close_col));
+                skip_to(tokens, close_col + 1);
+                return Some(State::Normal);
+            }
+        }
+
+        // Regex literal patterns, which only open after operators and some keywords
+        for (open, close) in &self.regexes {
+            if !self.starts_with(line, tokens, token, open) {
+                continue;
+            }
+            if let Some(close_col) = regex_at(line, token.col, open, close) {
+                let token_ = Token::Regex(open.clone(), close.clone());
+                matches.push(Match::new(Kind::Opening, token_.clone(), token.col));
+                matches.push(Match::new(Kind::Closing, token_, close_col));
+                skip_to(tokens, close_col + close.len());
+                return Some(State::Normal);
+            }
+        }
+
+        // 7. Inline span patterns
+        for (name, open, close) in &self.inline_spans {
+            if self.starts_with(line, tokens, token, open) {
+                let token_ = Token::InlineSpan(name.clone(), open.clone(), close.clone());
+                matches.push(Match::new(Kind::Opening, token_, token.col));
+                skip(tokens, open);
+                return Some(State::InInlineSpan(name.clone()));
+            }
+        }
+
+        // 8. Delimiter patterns, where the stack holds the index of each open pair
+        for &(idx, is_open) in &self.delimiter_arms {
+            let pattern = self.delimiter(idx, is_open);
+            if !self.starts_with(line, tokens, token, pattern) {
+                continue;
+            }
+
+            if is_open {
+                let (open, close) = self.pair(idx);
+                let token_ = Token::Delimiter(open.clone(), close.clone());
+                matches.push(Match::new_with_stack(
+                    Kind::Opening,
+                    token_,
+                    token.col,
+                    stack.len(),
+                ));
+                stack.push(idx);
+            } else {
+                let (open, close) = self.pair(self.close_pair(stack, idx));
+                let token_ = Token::Delimiter(open.clone(), close.clone());
+                matches.push(Match::new_with_stack(
+                    Kind::Closing,
+                    token_,
+                    token.col,
+                    stack.len(),
+                ));
+            }
+            skip(tokens, pattern);
+            return Some(State::Normal);
+        }
+
+        // 9. Keyword pairs, which the tokenizer only emits at the start of identifiers
+        if !self.keyword_starts.contains(&token.byte) {
+            return None;
+        }
+        for (keyword_idx, (open, middles, close)) in self.keyword_pairs.iter().enumerate() {
+            let idx = self.delimiters.len() + keyword_idx;
+
+            if self.keyword_at(line, token.col, open) {
+                let token_ = Token::Delimiter(open.clone(), close.clone());
+                matches.push(Match::new_with_stack(
+                    Kind::Opening,
+                    token_,
+                    token.col,
+                    stack.len(),
+                ));
+                stack.push(idx);
+                skip_keyword(tokens, token, open);
+                return Some(State::Normal);
+            }
+
+            if let Some(middle) = middles
+                .iter()
+                .find(|middle| self.keyword_at(line, token.col, middle))
+            {
+                let stack_height = match stack.last() {
+                    Some(&top) if self.pair(top).1 == close => stack.len() - 1,
+                    _ => stack.len(),
+                };
+                let token_ = Token::Delimiter(middle.clone(), close.clone());
+                matches.push(Match::new_with_stack(
+                    Kind::Middle,
+                    token_,
+                    token.col,
+                    stack_height,
+                ));
+                skip_keyword(tokens, token, middle);
+                return Some(State::Normal);
+            }
+
+            if self.keyword_at(line, token.col, close) {
+                let (open, close) = self.pair(self.close_pair(stack, idx));
+                let token_ = Token::Delimiter(open.clone(), close.clone());
+                matches.push(Match::new_with_stack(
+                    Kind::Closing,
+                    token_,
+                    token.col,
+                    stack.len(),
+                ));
+                skip_keyword(tokens, token, close);
+                return Some(State::Normal);
+            }
+        }
+
+        None
+    }
+
+    /// Interpolations are tried before closing the string or block string containing them
+    #[allow(clippy::too_many_arguments)]
+    fn open_interpolation<I>(
+        &self,
+        matches: &mut Vec<Match>,
+        stack: &[usize],
+        line: &str,
+        tokens: &mut MultiPeek<I>,
+        state: &State,
+        token: CharPos,
+        escaped: bool,
+    ) -> Option<State>
+    where
+        I: Iterator<Item = CharPos>,
+    {
+        let (State::InString(state_str) | State::InBlockString(state_str)) = state else {
+            return None;
+        };
+        let (_, open, close) = self
+            .interpolations
+            .iter()
+            .filter(|(string, _, _)| !escaped && string == state_str)
+            .find(|(_, open, _)| self.starts_with(line, tokens, token, open))?;
+
+        let token_ = Token::Delimiter(open.clone(), close.clone());
+        matches.push(Match::new_with_stack(
+            Kind::Opening,
+            token_,
+            token.col,
+            stack.len(),
+        ));
+        skip(tokens, open);
+        Some(State::InInterpolation(Box::new(Interpolation::new(
+            state.clone(),
+            open.clone(),
+            close.clone(),
+        ))))
+    }
+}
+
+impl Matcher for &DynamicMatcher {
+    const TOKENS: &'static [u8] = &[];
+    fn tokens(&self) -> &[u8] {
+        &self.tokens
+    }
+
+    fn keyword_starts(&self) -> &[u8] {
+        &self.keyword_starts
+    }
+
+    fn escape(&self, state: &State) -> Escape {
+        let open = match state {
+            State::InString(open) | State::InBlockString(open) | State::InBlockComment(open, _) => {
+                open
+            }
+            _ => return Escape::Backslash,
+        };
+        match self.escapes.iter().find(|(o, _)| o == open) {
+            Some((_, escape)) => *escape,
+            None if matches!(state, State::InBlockComment(..)) => Escape::None,
+            None => Escape::Backslash,
+        }
+    }
+
+    fn escape_opens_interpolation(&self, state: &State, line: &str, col: usize) -> bool {
+        let (State::InString(state_str) | State::InBlockString(state_str)) = state else {
+            return false;
+        };
+        self.interpolations.iter().any(|(string, open, _)| {
+            string == state_str && open.starts_with('\\') && line[col..].starts_with(&**open)
+        })
+    }
+
+    fn call<I>(
+        &mut self,
+        matches: &mut Vec<Match>,
+        stack: &mut Vec<usize>,
+        line: &str,
+        tokens: &mut MultiPeek<I>,
+        state: State,
+        token: CharPos,
+        escaped: bool,
+    ) -> State
+    where
+        I: Iterator<Item = CharPos>,
+    {
+        if let Some(next_state) =
+            self.open_interpolation(matches, stack, line, tokens, &state, token, escaped)
+        {
+            return next_state;
+        }
+
+        let next_state = match &state {
+            State::Normal => self.call_normal(matches, stack, line, tokens, token, escaped),
+            State::InLineComment => None,
+
+            // Only the outermost comment gets matches
+            State::InBlockComment(state_str, depth) => self
+                .block_comments
+                .iter()
+                .filter(|(open, _, _)| open == state_str)
+                .find_map(|(open, close, nested)| {
+                    if *nested && self.starts_with(line, tokens, token, open) {
+                        skip(tokens, open);
+                        return Some(State::InBlockComment(open.clone(), depth + 1));
+                    }
+                    if escaped || !self.starts_with(line, tokens, token, close) {
+                        return None;
+                    }
+                    if self.doubled_at(line, token, open, close) {
+                        skip_to(tokens, token.col + 2 * close.len());
+                        return Some(state.clone());
+                    }
+
+                    skip(tokens, close);
+                    if *depth > 1 {
+                        return Some(State::InBlockComment(open.clone(), depth - 1));
+                    }
+                    let token_ = Token::BlockComment(open.clone(), close.clone());
+                    matches.push(Match::new(Kind::Closing, token_, token.col));
+                    Some(State::Normal)
+                }),
+
+            State::InBlockString(state_str) => self
+                .block_strings
+                .iter()
+                .filter(|(open, _)| !escaped && open == state_str)
+                .find(|(_, close)| self.starts_with(line, tokens, token, close))
+                .map(|(open, close)| {
+                    if self.doubled_at(line, token, open, close) {
+                        skip_to(tokens, token.col + 2 * close.len());
+                        return state.clone();
+                    }
+                    let token_ = Token::BlockString(open.clone(), close.clone());
+                    matches.push(Match::new(Kind::Closing, token_, token.col));
+                    skip(tokens, close);
+                    State::Normal
+                }),
+
+            State::InBlockSpan(state_str) => self
+                .block_spans
+                .iter()
+                .filter(|(name, _, _)| name == state_str)
+                .find(|(_, _, close)| self.starts_with(line, tokens, token, close))
+                .map(|(name, open, close)| {
+                    let token_ = Token::BlockSpan(name.clone(), open.clone(), close.clone());
+                    matches.push(Match::new(Kind::Closing, token_, token.col));
+                    skip(tokens, close);
+                    State::Normal
+                }),
+
+            State::InString(state_str) => self
+                .strings
+                .iter()
+                .filter(|delim| !escaped && *delim == state_str)
+                .find(|delim| self.starts_with(line, tokens, token, delim))
+                .map(|delim| {
+                    if self.doubled_at(line, token, delim, delim) {
+                        skip_to(tokens, token.col + 2 * delim.len());
+                        return state.clone();
+                    }
+                    let token_ = Token::String(delim.clone());
+                    matches.push(Match::new(Kind::Closing, token_, token.col));
+                    skip(tokens, delim);
+                    State::Normal
+                }),
+
+            State::InInlineSpan(state_str) => self
+                .inline_spans
+                .iter()
+                .filter(|(name, _, _)| name == state_str)
+                .find(|(_, _, close)| self.starts_with(line, tokens, token, close))
+                .map(|(name, open, close)| {
+                    let token_ = Token::InlineSpan(name.clone(), open.clone(), close.clone());
+                    matches.push(Match::new(Kind::Closing, token_, token.col));
+                    skip(tokens, close);
+                    State::Normal
+                }),
+
+            // Handled by the parser, which unnests the interpolations and closes the heredocs,
+            // and injections are only parsed by `ParsedBuffer`
+            State::InInterpolation(_) | State::InHeredoc(_) | State::InInjection(_) => None,
+        };
+
+        next_state.unwrap_or(state)
+    }
+}
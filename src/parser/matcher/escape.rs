@@ -0,0 +1,30 @@
+/// How characters are escaped inside a string, block string or block comment
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Escape {
+    /// No escapes, i.e. raw strings
+    None,
+    /// `\"`, the default for strings and block strings
+    #[default]
+    Backslash,
+    /// Doubling the closing pattern, i.e. `''` in SQL or `""` in C# verbatim strings
+    Doubled,
+    /// Custom escape character, i.e. `` ` `` in PowerShell
+    Char(u8),
+}
+
+impl Escape {
+    /// Character which escapes the next character, if any
+    pub fn byte(&self) -> Option<u8> {
+        match self {
+            Escape::Backslash => Some(b'\\'),
+            Escape::Char(byte) => Some(*byte),
+            Escape::None | Escape::Doubled => None,
+        }
+    }
+}
+
+/// Checks if the pattern at the column is directly followed by itself, i.e. `""` in C# verbatim
+/// strings, which escapes the pattern when the escape is `Escape::Doubled`
+pub fn doubled_at(line: &str, col: usize, pattern: &str) -> bool {
+    line.as_bytes()[col + pattern.len()..].starts_with(pattern.as_bytes())
+}
@@ -0,0 +1,93 @@
+use super::keyword::is_identifier_byte;
+
+/// Finds the terminator of a heredoc whose opening pattern starts at the column, i.e. `EOF` in
+/// `<<EOF`, `<<'EOF'` or `<<"EOF"`, returning it along with the column after it. When `spaced`,
+/// whitespace is allowed before the terminator, i.e. `<< EOF` in shell, but the pattern never
+/// opens a heredoc inside shell arithmetic, where it shifts instead, i.e. `$(( x << y ))`
+///
+/// The pattern must not directly follow its first byte, so that i.e. `<<<x` isn't read as
+/// `<` followed by `<<x`
+pub fn heredoc_at<'a>(
+    line: &'a str,
+    col: usize,
+    open: &str,
+    spaced: bool,
+) -> Option<(&'a str, usize)> {
+    let bytes = line.as_bytes();
+    if col > 0 && bytes[col - 1] == open.as_bytes()[0] {
+        return None;
+    }
+    if spaced && in_arithmetic(&bytes[..col]) {
+        return None;
+    }
+
+    let mut col = col + open.len();
+    if spaced {
+        while matches!(bytes.get(col), Some(b' ' | b'\t')) {
+            col += 1;
+        }
+    }
+
+    let quote = bytes
+        .get(col)
+        .copied()
+        .filter(|&byte| byte == b'\'' || byte == b'"');
+    if quote.is_some() {
+        col += 1;
+    }
+
+    let start = col;
+    if !bytes
+        .get(col)
+        .is_some_and(|&byte| byte.is_ascii_alphabetic() || byte == b'_')
+    {
+        return None;
+    }
+    while bytes
+        .get(col)
+        .is_some_and(|&byte| byte.is_ascii_alphanumeric() || byte == b'_')
+    {
+        col += 1;
+    }
+    let terminator = &line[start..col];
+
+    if let Some(quote) = quote {
+        if bytes.get(col) != Some(&quote) {
+            return None;
+        }
+        col += 1;
+    }
+    Some((terminator, col))
+}
+
+/// Whether the text leaves an arithmetic expression open, i.e. `(( n` or `$(( x`
+fn in_arithmetic(bytes: &[u8]) -> bool {
+    let mut depth = 0usize;
+    let mut idx = 0;
+    while idx + 1 < bytes.len() {
+        match &bytes[idx..idx + 2] {
+            b"((" => depth += 1,
+            b"))" => depth = depth.saturating_sub(1),
+            _ => {
+                idx += 1;
+                continue;
+            }
+        }
+        idx += 2;
+    }
+    depth > 0
+}
+
+/// Finds the terminator at the start of the line, optionally indented, returning its column.
+/// Code may follow the terminator, i.e. `EOT;` in PHP, but not identifier characters
+pub fn terminator_at(line: &str, terminator: &str, indented: bool) -> Option<usize> {
+    let col = match indented {
+        true => line.len() - line.trim_start().len(),
+        false => 0,
+    };
+    let rest = line[col..].strip_prefix(terminator)?;
+    rest.bytes()
+        .next()
+        .is_none_or(|byte| !is_identifier_byte(byte))
+        .then_some(col)
+}
@@ -0,0 +1,48 @@
+use itertools::MultiPeek;
+
+use crate::parser::CharPos;
+
+/// Bytes which may be part of an identifier. Non-ASCII bytes are included so that keywords
+/// directly next to a unicode identifier character aren't matched
+#[inline(always)]
+pub fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte >= 0x80
+}
+
+/// Checks if the keyword starts at the column, with no identifier characters directly before
+/// or after it. Keywords directly followed by a single `:` are keys rather than keywords, i.e.
+/// `do:` in Elixir or `if:` in Ruby
+#[inline(always)]
+pub fn keyword_at(line: &str, col: usize, keyword: &str) -> bool {
+    let bytes = line.as_bytes();
+    let after = &bytes[(col + keyword.len()).min(bytes.len())..];
+    bytes[col..].starts_with(keyword.as_bytes())
+        && (col == 0 || !is_identifier_byte(bytes[col - 1]))
+        && after.first().is_none_or(|&byte| !is_identifier_byte(byte))
+        && (!after.starts_with(b":") || after.starts_with(b"::"))
+}
+
+/// Skips the tokens inside the keyword, for languages with letters in their other patterns
+pub fn skip_keyword<I>(tokens: &mut MultiPeek<I>, token: CharPos, keyword: &str)
+where
+    I: Iterator<Item = CharPos>,
+{
+    skip_to(tokens, token.col + keyword.len());
+}
+
+/// Skips the tokens on the current line before the column
+pub fn skip_to<I>(tokens: &mut MultiPeek<I>, col: usize)
+where
+    I: Iterator<Item = CharPos>,
+{
+    loop {
+        tokens.reset_peek();
+        match tokens.peek() {
+            Some(next) if next.byte != b'\n' && next.col < col => {
+                tokens.next();
+            }
+            _ => break,
+        }
+    }
+    tokens.reset_peek();
+}
@@ -1,9 +1,25 @@
+use std::borrow::Cow;
+
 use itertools::MultiPeek;
 use mlua::IntoLua;
 
+mod char_literal;
+mod dynamic;
+mod escape;
+mod heredoc;
+mod keyword;
+mod predicate;
+mod regex;
 mod token;
 mod token_type;
 
+pub use char_literal::*;
+pub use dynamic::*;
+pub use escape::*;
+pub use heredoc::*;
+pub use keyword::*;
+pub use predicate::*;
+pub use regex::*;
 pub use token::*;
 pub use token_type::*;
 
@@ -12,14 +28,42 @@ use crate::parser::{CharPos, State};
 pub trait Matcher {
     const TOKENS: &[u8];
     #[inline(always)]
-    fn tokens(&self) -> &'static [u8] {
+    fn tokens(&self) -> &[u8] {
         Self::TOKENS
     }
 
+    /// First bytes of the keywords, which the tokenizer emits at the start of identifiers
+    const KEYWORD_STARTS: &[u8] = &[];
+    #[inline(always)]
+    fn keyword_starts(&self) -> &[u8] {
+        Self::KEYWORD_STARTS
+    }
+
+    /// How characters are escaped in the state, where block comments have no escapes and
+    /// everything else is escaped with a backslash by default
+    #[inline(always)]
+    fn escape(&self, state: &State) -> Escape {
+        match state {
+            State::InBlockComment(..) => Escape::None,
+            _ => Escape::Backslash,
+        }
+    }
+
+    /// Whether an interpolation opens with the backslash at the column, i.e. `\(` in Swift,
+    /// rather than the backslash escaping the next character
+    #[inline(always)]
+    fn escape_opens_interpolation(&self, _state: &State, _line: &str, _col: usize) -> bool {
+        false
+    }
+
+    /// The `stack` holds the index of each open delimiter pair, in the order they were defined
+    /// followed by the keyword pairs. The `line` is the text of the line containing the token
+    #[allow(clippy::too_many_arguments)]
     fn call<I>(
         &mut self,
         matches: &mut Vec<Match>,
-        stack: &mut Vec<u8>,
+        stack: &mut Vec<usize>,
+        line: &str,
         tokens: &mut MultiPeek<I>,
         state: State,
         token: CharPos,
@@ -29,6 +73,33 @@ pub trait Matcher {
         I: Iterator<Item = CharPos>;
 }
 
+/// Checks if the pattern starts at the current token, with each following byte
+/// directly adjacent to the previous one
+pub fn starts_with<I>(tokens: &mut MultiPeek<I>, token: CharPos, pattern: &str) -> bool
+where
+    I: Iterator<Item = CharPos>,
+{
+    let pattern = pattern.as_bytes();
+    if token.byte != pattern[0] {
+        return false;
+    }
+
+    tokens.reset_peek();
+    pattern[1..].iter().enumerate().all(|(idx, &byte)| {
+        matches!(tokens.peek(), Some(next) if next.byte == byte && next.col == token.col + idx + 1)
+    })
+}
+
+/// Skip tokens based on length of pattern
+pub fn skip<I>(tokens: &mut MultiPeek<I>, pattern: &str)
+where
+    I: Iterator<Item = CharPos>,
+{
+    for _ in 1..pattern.len() {
+        tokens.next();
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Match {
     pub kind: Kind,
@@ -66,18 +137,19 @@ impl Match {
         }
     }
 
-    pub fn line_comment(text: &'static str, col: usize) -> Self {
+    pub fn line_comment(text: impl Into<Cow<'static, str>>, col: usize) -> Self {
         Self {
             kind: Kind::NonPair,
-            token: Token::LineComment(text),
+            token: Token::LineComment(text.into()),
             col,
             stack_height: None,
         }
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize {
         match self.kind {
-            Kind::Opening | Kind::NonPair => self.token.opening().len(),
+            Kind::Opening | Kind::NonPair | Kind::Middle => self.token.opening().len(),
             Kind::Closing => self
                 .token
                 .closing()
@@ -91,12 +163,12 @@ impl Match {
 impl Match {
     pub fn delimiter(char: char, col: usize, stack_height: Option<usize>) -> Self {
         let (kind, token) = match char {
-            '{' => (Kind::Opening, Token::Delimiter("{", "}")),
-            '}' => (Kind::Closing, Token::Delimiter("{", "}")),
-            '[' => (Kind::Opening, Token::Delimiter("[", "]")),
-            ']' => (Kind::Closing, Token::Delimiter("[", "]")),
-            '(' => (Kind::Opening, Token::Delimiter("(", ")")),
-            ')' => (Kind::Closing, Token::Delimiter("(", ")")),
+            '{' => (Kind::Opening, Token::Delimiter("{".into(), "}".into())),
+            '}' => (Kind::Closing, Token::Delimiter("{".into(), "}".into())),
+            '[' => (Kind::Opening, Token::Delimiter("[".into(), "]".into())),
+            ']' => (Kind::Closing, Token::Delimiter("[".into(), "]".into())),
+            '(' => (Kind::Opening, Token::Delimiter("(".into(), ")".into())),
+            ')' => (Kind::Closing, Token::Delimiter("(".into(), ")".into())),
             _ => panic!("Unknown token type"),
         };
 
@@ -110,8 +182,8 @@ impl Match {
 
     pub fn block_comment(text: &'static str, col: usize) -> Self {
         let (kind, token) = match text {
-            "/*" => (Kind::Opening, Token::BlockComment("/*", "*/")),
-            "*/" => (Kind::Closing, Token::BlockComment("/*", "*/")),
+            "/*" => (Kind::Opening, Token::BlockComment("/*".into(), "*/".into())),
+            "*/" => (Kind::Closing, Token::BlockComment("/*".into(), "*/".into())),
             _ => panic!("Unknown token type"),
         };
         Self {
@@ -131,9 +203,9 @@ impl IntoLua for Match {
         if let Some(closing) = self.token.closing() {
             table.set(2, closing)?;
         }
-        match self.token {
+        match &self.token {
             Token::InlineSpan(span, _, _) | Token::BlockSpan(span, _, _) => {
-                table.set("span", span)?;
+                table.set("span", span.as_ref())?;
             }
             _ => {}
         }
@@ -154,6 +226,20 @@ pub struct MatchWithLine {
     pub stack_height: Option<usize>,
 }
 
+impl MatchWithLine {
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        match self.kind {
+            Kind::Opening | Kind::NonPair | Kind::Middle => self.token.opening().len(),
+            Kind::Closing => self
+                .token
+                .closing()
+                .unwrap_or_else(|| self.token.opening())
+                .len(),
+        }
+    }
+}
+
 impl IntoLua for MatchWithLine {
     fn into_lua(self, lua: &mlua::Lua) -> mlua::Result<mlua::Value> {
         let table = lua.create_table()?;
@@ -162,9 +248,9 @@ impl IntoLua for MatchWithLine {
         if let Some(closing) = self.token.closing() {
             table.set(2, closing)?;
         }
-        match self.token {
+        match &self.token {
             Token::InlineSpan(span, _, _) | Token::BlockSpan(span, _, _) => {
-                table.set("span", span)?;
+                table.set("span", span.as_ref())?;
             }
             _ => {}
         }
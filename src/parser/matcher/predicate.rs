@@ -0,0 +1,39 @@
+use std::borrow::Cow;
+
+use super::keyword::is_identifier_byte;
+
+/// Condition on the text before a pattern, for patterns which only match in some contexts, i.e.
+/// `#` only starts a comment after whitespace in shell. The previous byte is read from the line,
+/// since the tokenizer skips bytes which aren't part of any pattern
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// At the start of the line
+    AtLineStart,
+    /// At the start of the line, after any spaces and tabs
+    AfterIndent,
+    /// At the start of the line or after whitespace
+    AfterWhitespace,
+    /// Not directly after an identifier character
+    NotAfterIdentifier,
+    /// Not directly after any of the ASCII characters
+    NotAfter(Cow<'static, str>),
+}
+
+impl Predicate {
+    #[inline(always)]
+    pub fn matches(&self, line: &str, col: usize) -> bool {
+        let bytes = line.as_bytes();
+        let previous = col.checked_sub(1).map(|col| bytes[col]);
+        match self {
+            Predicate::AtLineStart => col == 0,
+            Predicate::AfterIndent => bytes[..col]
+                .iter()
+                .all(|&byte| byte == b' ' || byte == b'\t'),
+            Predicate::AfterWhitespace => previous.is_none_or(|byte| byte.is_ascii_whitespace()),
+            Predicate::NotAfterIdentifier => !previous.is_some_and(is_identifier_byte),
+            Predicate::NotAfter(chars) => {
+                !previous.is_some_and(|byte| chars.as_bytes().contains(&byte))
+            }
+        }
+    }
+}
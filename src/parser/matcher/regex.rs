@@ -0,0 +1,84 @@
+use super::keyword::is_identifier_byte;
+
+/// Keywords which may be directly followed by a regex, i.e. `return /a/`, across languages
+const REGEX_KEYWORDS: &[&str] = &[
+    "and",
+    "await",
+    "case",
+    "delete",
+    "do",
+    "else",
+    "elsif",
+    "grep",
+    "if",
+    "in",
+    "instanceof",
+    "new",
+    "not",
+    "of",
+    "or",
+    "return",
+    "split",
+    "then",
+    "throw",
+    "typeof",
+    "unless",
+    "until",
+    "void",
+    "when",
+    "while",
+    "yield",
+];
+
+/// Finds the closing pattern of a regex literal opening at the column, i.e. `/[(]/` in
+/// JavaScript or `~/\(/` in Haxe, returning its column. The closing pattern is ignored when
+/// escaped or inside a character class, i.e. `/\//` or `/[/]/`
+///
+/// Since `/` also divides, the regex must follow an operator, an opening delimiter or a keyword
+/// such as `return`, and never an identifier, a number or a closing delimiter
+pub fn regex_at(line: &str, col: usize, open: &str, close: &str) -> Option<usize> {
+    if !regex_allowed(&line.as_bytes()[..col]) {
+        return None;
+    }
+
+    let bytes = line.as_bytes();
+    let start = col + open.len();
+    let mut col = start;
+    let mut in_class = false;
+    while col < bytes.len() {
+        match bytes[col] {
+            b'\\' => col += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            _ if !in_class && col > start && bytes[col..].starts_with(close.as_bytes()) => {
+                return Some(col);
+            }
+            _ => {}
+        }
+        col += 1;
+    }
+    None
+}
+
+/// Checks the text before the pattern, ignoring whitespace, for whether a regex may start
+fn regex_allowed(before: &[u8]) -> bool {
+    let end = before
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace())
+        .map_or(0, |idx| idx + 1);
+    let before = &before[..end];
+    let Some(&last) = before.last() else {
+        return true;
+    };
+
+    if is_identifier_byte(last) {
+        let start = before
+            .iter()
+            .rposition(|&byte| !is_identifier_byte(byte))
+            .map_or(0, |idx| idx + 1);
+        return REGEX_KEYWORDS
+            .iter()
+            .any(|keyword| keyword.as_bytes() == &before[start..]);
+    }
+    !matches!(last, b')' | b']' | b'}' | b'"' | b'\'' | b'`')
+}
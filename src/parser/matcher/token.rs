@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 // TODO: rework with variants that make more sense for usage
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
@@ -5,44 +7,66 @@ pub enum Kind {
     Opening,
     Closing,
     NonPair,
+    /// Keyword between an opening and closing keyword, i.e. `else` in `if`/`end`, which takes
+    /// the stack height of the pair it belongs to
+    Middle,
 }
 
+/// Strings are `Cow::Borrowed` for the built-in languages generated by `define_matcher!`
+/// and `Cow::Owned` for languages registered at runtime
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Delimiter(&'static str, &'static str),
+    Delimiter(Cow<'static, str>, Cow<'static, str>),
+
+    String(Cow<'static, str>),
+    BlockString(Cow<'static, str>, Cow<'static, str>),
 
-    String(&'static str),
-    BlockString(&'static str, &'static str),
+    LineComment(Cow<'static, str>),
+    BlockComment(Cow<'static, str>, Cow<'static, str>),
 
-    LineComment(&'static str),
-    BlockComment(&'static str, &'static str),
+    /// Regex literal, i.e. `/[(]/`, whose contents aren't parsed
+    Regex(Cow<'static, str>, Cow<'static, str>),
 
-    InlineSpan(&'static str, &'static str, &'static str),
-    BlockSpan(&'static str, &'static str, &'static str),
+    InlineSpan(Cow<'static, str>, Cow<'static, str>, Cow<'static, str>),
+    BlockSpan(Cow<'static, str>, Cow<'static, str>, Cow<'static, str>),
 }
 
 impl Token {
-    pub fn opening(&self) -> &'static str {
+    pub fn opening(&self) -> &str {
         match self {
-            Token::Delimiter(open, _) => *open,
-            Token::String(open) => *open,
-            Token::BlockString(open, _) => *open,
-            Token::LineComment(open) => *open,
-            Token::BlockComment(open, _) => *open,
-            Token::InlineSpan(_, open, _) => *open,
-            Token::BlockSpan(_, open, _) => *open,
+            Token::Delimiter(open, _) => open,
+            Token::String(open) => open,
+            Token::BlockString(open, _) => open,
+            Token::LineComment(open) => open,
+            Token::BlockComment(open, _) => open,
+            Token::Regex(open, _) => open,
+            Token::InlineSpan(_, open, _) => open,
+            Token::BlockSpan(_, open, _) => open,
+        }
+    }
+
+    /// Whether this closing (or middle) delimiter belongs to the given opening delimiter. Pairs
+    /// may share a closing delimiter, i.e. `do`/`end` and `if`/`end`, so only the closing text
+    /// of delimiters is compared
+    pub fn closes(&self, opening: &Token) -> bool {
+        match (self, opening) {
+            (Token::Delimiter(_, close), Token::Delimiter(_, opening_close)) => {
+                close == opening_close
+            }
+            _ => self == opening,
         }
     }
 
-    pub fn closing(&self) -> Option<&'static str> {
+    pub fn closing(&self) -> Option<&str> {
         match self {
-            Token::Delimiter(_, close) => Some(*close),
+            Token::Delimiter(_, close) => Some(close),
             Token::String(_) => None,
-            Token::BlockString(_, close) => Some(*close),
+            Token::BlockString(_, close) => Some(close),
             Token::LineComment(_) => None,
-            Token::BlockComment(_, close) => Some(*close),
-            Token::InlineSpan(_, _, close) => Some(*close),
-            Token::BlockSpan(_, _, close) => Some(*close),
+            Token::BlockComment(_, close) => Some(close),
+            Token::Regex(_, close) => Some(close),
+            Token::InlineSpan(_, _, close) => Some(close),
+            Token::BlockSpan(_, _, close) => Some(close),
         }
     }
 }
@@ -8,20 +8,21 @@ pub enum TokenType {
     BlockString = 2,
     LineComment = 3,
     BlockComment = 4,
+    Regex = 5,
 }
 
 impl TokenType {
     pub fn matches(&self, token: &Token) -> bool {
         use TokenType::*;
-        match (self, token) {
+        matches!(
+            (self, token),
             (Delimiter, Token::Delimiter(_, _))
-            | (String, Token::String(_))
-            | (BlockString, Token::BlockString(_, _))
-            | (LineComment, Token::LineComment(_))
-            | (BlockComment, Token::BlockComment(_, _)) => true,
-
-            _ => false,
-        }
+                | (String, Token::String(_))
+                | (BlockString, Token::BlockString(_, _))
+                | (LineComment, Token::LineComment(_))
+                | (BlockComment, Token::BlockComment(_, _))
+                | (Regex, Token::Regex(_, _))
+        )
     }
 }
 
@@ -35,6 +36,7 @@ impl TryFrom<u8> for TokenType {
             2 => Ok(TokenType::BlockString),
             3 => Ok(TokenType::LineComment),
             4 => Ok(TokenType::BlockComment),
+            5 => Ok(TokenType::Regex),
             _ => Err(()),
         }
     }
@@ -1,20 +1,52 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, PoisonError, RwLock};
+
 pub type SimdVec = std::simd::Simd<u8, 16>;
 
+pub mod encoding;
 pub mod languages;
 pub mod matcher;
 pub mod parse;
 pub mod tokenize;
 
+pub use encoding::PositionEncoding;
 pub use itertools::MultiPeek;
-pub use matcher::{Kind, Match, MatchWithLine, Matcher, Token};
-pub use parse::{parse, State};
+pub use matcher::{
+    char_literal_at, doubled_at, heredoc_at, keyword_at, regex_at, skip_keyword, skip_to,
+    DynamicMatcher, Escape, Kind, LanguageDef, Match, MatchWithLine, Matcher, Predicate, Token,
+};
+pub use parse::{parse, Checkpoint, Heredoc, Injection, Interpolation, Parsed, State};
 pub use tokenize::{tokenize, CharPos};
 
+/// Languages registered at runtime via `register_language`, keyed by filetype
+static REGISTERED_LANGUAGES: LazyLock<RwLock<HashMap<String, Arc<DynamicMatcher>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Registers a language at runtime, taking priority over any built-in language
+/// with the same filetype
+pub fn register_language(filetype: &str, matcher: DynamicMatcher) {
+    REGISTERED_LANGUAGES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(filetype.to_string(), Arc::new(matcher));
+}
+
 pub fn parse_filetype(
     filetype: &str,
     lines: &[&str],
-    initial_state: State,
-) -> Option<(Vec<Vec<Match>>, Vec<State>)> {
+    initial_state: impl Into<Checkpoint>,
+) -> Option<Parsed> {
+    // Releases the lock before parsing, so that registering languages doesn't wait on parses
+    // running in the background
+    let registered = REGISTERED_LANGUAGES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(filetype)
+        .cloned();
+    if let Some(matcher) = registered {
+        return Some(parse(lines, initial_state, &*matcher));
+    }
+
     match filetype {
         "c" => Some(parse(lines, initial_state, languages::C {})),
         "clojure" => Some(parse(lines, initial_state, languages::Clojure {})),
@@ -27,9 +59,11 @@ pub fn parse_filetype(
         "go" => Some(parse(lines, initial_state, languages::Go {})),
         "haskell" => Some(parse(lines, initial_state, languages::Haskell {})),
         "haxe" => Some(parse(lines, initial_state, languages::Haxe {})),
+        "hcl" | "terraform" => Some(parse(lines, initial_state, languages::Hcl {})),
         "java" => Some(parse(lines, initial_state, languages::Java {})),
         "javascript" => Some(parse(lines, initial_state, languages::JavaScript {})),
         "json" => Some(parse(lines, initial_state, languages::Json {})),
+        "julia" => Some(parse(lines, initial_state, languages::Julia {})),
         "kotlin" => Some(parse(lines, initial_state, languages::Kotlin {})),
         "latex" | "tex" | "bib" => Some(parse(lines, initial_state, languages::Latex {})),
         "lean" => Some(parse(lines, initial_state, languages::Lean {})),
@@ -1,48 +1,322 @@
-use itertools::Itertools;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use itertools::{Itertools, MultiPeek};
 
 use super::{
-    matcher::{Match, Matcher},
-    tokenize::tokenize,
+    matcher::{skip, skip_to, starts_with, terminator_at, Kind, Match, Matcher, Token},
+    tokenize::{tokenize, CharPos},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum State {
+    #[default]
     Normal,
-    InString(&'static str),
-    InBlockString(&'static str),
+    InString(Cow<'static, str>),
+    InBlockString(Cow<'static, str>),
     InLineComment,
-    InBlockComment(&'static str),
-    InInlineSpan(&'static str),
-    InBlockSpan(&'static str),
+    /// Opening pattern and depth, which is above 1 for nested block comments
+    InBlockComment(Cow<'static, str>, usize),
+    InInlineSpan(Cow<'static, str>),
+    InBlockSpan(Cow<'static, str>),
+    /// Code inside a string interpolation. Interpolations may contain strings with their own
+    /// interpolations, forming a stack of states
+    InInterpolation(Box<Interpolation>),
+    /// Body of a heredoc, starting on the line after the opening pattern
+    InHeredoc(Box<Heredoc>),
+    /// Block span parsed with another language, i.e. a fenced code block in Markdown. Only set
+    /// by `ParsedBuffer`, which parses the lines inside the span separately
+    InInjection(Box<Injection>),
+}
+
+impl State {
+    /// Strings, line comments and inline spans can't continue onto the next line
+    pub fn at_line_end(self) -> Self {
+        match self {
+            State::InString(_) | State::InLineComment | State::InInlineSpan(_) => State::Normal,
+            // Ends along with the string containing it
+            State::InInterpolation(interpolation)
+                if matches!(interpolation.string, State::InString(_)) =>
+            {
+                State::Normal
+            }
+            State::InInterpolation(mut interpolation) => {
+                interpolation.state = interpolation.state.at_line_end();
+                State::InInterpolation(interpolation)
+            }
+            state => state,
+        }
+    }
+}
+
+/// Code embedded in a string or block string, i.e. `${` => `}` in a JavaScript template literal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpolation {
+    /// State of the string to return to once the interpolation closes
+    pub string: State,
+    pub open: Cow<'static, str>,
+    pub close: Cow<'static, str>,
+    /// Number of delimiters with the same closing pattern opened inside the interpolation,
+    /// which must be closed before the interpolation closes
+    pub depth: usize,
+    /// State of the code inside the interpolation
+    pub state: State,
+}
+
+impl Interpolation {
+    pub fn new(
+        string: State,
+        open: impl Into<Cow<'static, str>>,
+        close: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        Self {
+            string,
+            open: open.into(),
+            close: close.into(),
+            depth: 0,
+            state: State::Normal,
+        }
+    }
+}
+
+/// Heredoc with the terminator captured at the opening site, i.e. `<<EOF` in shell
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heredoc {
+    pub open: Cow<'static, str>,
+    pub terminator: Cow<'static, str>,
+    /// Whether the terminator may be indented, i.e. `<<-EOF` in shell
+    pub indented: bool,
+    /// Heredocs opened after this one on the same line, whose bodies follow in order once it
+    /// closes, i.e. `B` in `cat <<A <<B`
+    pub queued: Vec<Heredoc>,
+}
+
+impl Heredoc {
+    pub fn new(
+        open: impl Into<Cow<'static, str>>,
+        terminator: impl Into<Cow<'static, str>>,
+        indented: bool,
+    ) -> Self {
+        Self {
+            open: open.into(),
+            terminator: terminator.into(),
+            indented,
+            queued: vec![],
+        }
+    }
+
+    /// Queues the heredocs after the first, returning it, or `None` without any heredocs
+    pub fn queue(mut heredocs: Vec<Heredoc>) -> Option<Box<Heredoc>> {
+        if heredocs.is_empty() {
+            return None;
+        }
+        let mut heredoc = heredocs.remove(0);
+        heredoc.queued = heredocs;
+        Some(Box::new(heredoc))
+    }
+
+    pub fn token(&self) -> Token {
+        Token::BlockString(self.open.clone(), self.terminator.clone())
+    }
+}
+
+/// Code in another language inside a block span, i.e. ```` ```rust ```` in Markdown
+#[derive(Debug, Clone, PartialEq)]
+pub struct Injection {
+    /// Name of the block span containing the code
+    pub span: Cow<'static, str>,
+    /// Filetype of the code, read from the text after the opening pattern
+    pub language: String,
+    /// Where to resume parsing the code inside the span
+    pub checkpoint: Checkpoint,
+}
+
+impl Injection {
+    pub fn new(span: impl Into<Cow<'static, str>>, language: impl Into<String>) -> Self {
+        Self {
+            span: span.into(),
+            language: language.into(),
+            checkpoint: Checkpoint::default(),
+        }
+    }
+}
+
+/// Everything needed to resume parsing at the start of a line exactly as a full parse would,
+/// recorded at the end of each line. Consecutive lines usually share the same checkpoint, so
+/// `parse` returns them behind an `Arc`, reusing the previous line's when unchanged
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    pub state: State,
+    /// Delimiter stack of the matcher, see `Matcher::call`. Incremental reparses stop once the
+    /// state converges, so the stacks of the following lines may be stale, which only affects
+    /// the stack heights in the matches, recalculated by `ParsedBuffer` anyway
+    pub stack: Vec<usize>,
+    /// Delimiter stacks of the code around each interpolation the state is nested in, from
+    /// outermost to innermost
+    pub outer_stacks: Vec<Vec<usize>>,
+}
+
+impl Checkpoint {
+    /// Whether the following lines would parse to the same tokens from either checkpoint. The
+    /// delimiter stacks are ignored, since they only determine the stack heights and which pair
+    /// a shared closing delimiter closes, which `ParsedBuffer` recalculates for every line
+    pub fn converges_with(&self, other: &Checkpoint) -> bool {
+        match (&self.state, &other.state) {
+            (State::InInjection(injection), State::InInjection(other_injection)) => {
+                injection.span == other_injection.span
+                    && injection.language == other_injection.language
+                    && injection
+                        .checkpoint
+                        .converges_with(&other_injection.checkpoint)
+            }
+            (state, other_state) => state == other_state,
+        }
+    }
+}
+
+impl From<State> for Checkpoint {
+    fn from(state: State) -> Self {
+        Self {
+            state,
+            ..Default::default()
+        }
+    }
+}
+
+/// Matches on each line, and the checkpoint at the end of each line
+pub type Parsed = (Vec<Vec<Match>>, Vec<Arc<Checkpoint>>);
+
+/// Appends the checkpoint, sharing the previous line's checkpoint when they're equal
+fn push_checkpoint(checkpoints: &mut Vec<Arc<Checkpoint>>, checkpoint: Checkpoint) {
+    let checkpoint = match checkpoints.last() {
+        Some(last) if **last == checkpoint => last.clone(),
+        _ => Arc::new(checkpoint),
+    };
+    checkpoints.push(checkpoint);
+}
+
+/// State at the end of the line, where the first heredoc opened on the line starts its body
+fn at_line_end(state: State, heredocs: Vec<Heredoc>) -> State {
+    match (state.at_line_end(), Heredoc::queue(heredocs)) {
+        (State::Normal, Some(heredoc)) => State::InHeredoc(heredoc),
+        (state, _) => state,
+    }
+}
+
+/// Closes the heredoc when the line starts with its terminator, moving on to the next queued one
+fn close_heredoc<I>(
+    state: State,
+    line: &str,
+    tokens: &mut MultiPeek<I>,
+    matches: &mut Vec<Match>,
+) -> State
+where
+    I: Iterator<Item = CharPos>,
+{
+    let State::InHeredoc(heredoc) = state else {
+        return state;
+    };
+    let Some(col) = terminator_at(line, &heredoc.terminator, heredoc.indented) else {
+        return State::InHeredoc(heredoc);
+    };
+
+    matches.push(Match::new(Kind::Closing, heredoc.token(), col));
+    skip_to(tokens, col + heredoc.terminator.len());
+    match Heredoc::queue(heredoc.queued) {
+        Some(heredoc) => State::InHeredoc(heredoc),
+        None => State::Normal,
+    }
+}
+
+/// Interpolation being parsed, along with the delimiter stack of the code around it, since each
+/// interpolation has its own delimiter stack. The state inside is kept separately while parsing
+type Frame = (Interpolation, Vec<usize>);
+
+/// Splits the state into the interpolations it's nested in, from outermost to innermost,
+/// returning the state inside the innermost interpolation
+fn unnest(mut state: State, frames: &mut Vec<Frame>) -> State {
+    while let State::InInterpolation(interpolation) = state {
+        let mut interpolation = *interpolation;
+        state = std::mem::replace(&mut interpolation.state, State::Normal);
+        frames.push((interpolation, vec![]));
+    }
+    state
+}
+
+/// Inverse of `unnest`
+fn nest(state: State, frames: &[Frame]) -> State {
+    frames
+        .iter()
+        .rev()
+        .fold(state, |state, (interpolation, _)| {
+            State::InInterpolation(Box::new(Interpolation {
+                state,
+                ..interpolation.clone()
+            }))
+        })
+}
+
+/// Ends the line, dropping the interpolations which ended along with their string. Returns the
+/// state inside the innermost remaining interpolation, and the checkpoint for the next line
+fn end_line(
+    state: State,
+    heredocs: Vec<Heredoc>,
+    frames: &mut Vec<Frame>,
+    stack: &mut Vec<usize>,
+) -> (State, Checkpoint) {
+    let line_end_state = nest(at_line_end(state, heredocs), frames).at_line_end();
+
+    let mut nested_frames = vec![];
+    let state = unnest(line_end_state.clone(), &mut nested_frames);
+    while frames.len() > nested_frames.len() {
+        *stack = frames.pop().unwrap().1;
+    }
+
+    let checkpoint = Checkpoint {
+        state: line_end_state,
+        stack: stack.clone(),
+        outer_stacks: frames.iter().map(|(_, stack)| stack.clone()).collect(),
+    };
+    (state, checkpoint)
 }
 
 /// Given a matcher, runs the tokenizer on the lines and keeps track
-/// of the state and matches for each line
-pub fn parse<M: Matcher>(
-    lines: &[&str],
-    initial_state: State,
-    mut matcher: M,
-) -> (Vec<Vec<Match>>, Vec<State>) {
+/// of the matches for each line and the checkpoint at the end of each line
+pub fn parse<M: Matcher>(lines: &[&str], initial: impl Into<Checkpoint>, mut matcher: M) -> Parsed {
     // State
     let mut matches_by_line = Vec::with_capacity(lines.len());
     let mut line_matches = vec![];
 
-    let mut state_by_line = Vec::with_capacity(lines.len());
-    let mut state = initial_state;
+    let Checkpoint {
+        state: initial_state,
+        mut stack,
+        outer_stacks,
+    } = initial.into();
+    let mut checkpoints = Vec::with_capacity(lines.len());
+    let mut frames = vec![];
+    let mut state = unnest(initial_state, &mut frames);
+    for ((_, frame_stack), outer_stack) in frames.iter_mut().zip(outer_stacks) {
+        *frame_stack = outer_stack;
+    }
 
-    let mut stack = vec![];
     let mut escaped_col: Option<usize> = None;
+    // Heredocs opened on the current line, whose bodies start on the next line
+    let mut pending_heredocs = vec![];
+    let mut line_number = 0;
 
     let text = lines.join("\n");
 
     #[cfg(target_feature = "avx512f")]
-    let tokens = tokenize::<64>(&text, matcher.tokens());
+    let tokens = tokenize::<64>(&text, matcher.tokens(), matcher.keyword_starts());
     #[cfg(all(target_feature = "avx2", not(target_feature = "avx512f")))]
-    let tokens = tokenize::<32>(&text, matcher.tokens());
+    let tokens = tokenize::<32>(&text, matcher.tokens(), matcher.keyword_starts());
     #[cfg(not(any(target_feature = "avx2", target_feature = "avx512f")))]
-    let mut tokens = tokenize::<16>(&text, matcher.tokens());
+    let mut tokens = tokenize::<16>(&text, matcher.tokens(), matcher.keyword_starts());
 
     let mut tokens = tokens.multipeek();
+    if let Some(line) = lines.first() {
+        state = close_heredoc(state, line, &mut tokens, &mut line_matches);
+    }
 
     while let Some(token) = tokens.next() {
         // New line
@@ -50,47 +324,125 @@ pub fn parse<M: Matcher>(
             matches_by_line.push(line_matches);
             line_matches = vec![];
             escaped_col = None;
+            line_number += 1;
 
-            if matches!(
+            let checkpoint;
+            (state, checkpoint) = end_line(
                 state,
-                State::InString(_) | State::InLineComment | State::InInlineSpan(_)
-            ) {
-                state = State::Normal;
-            }
-            state_by_line.push(state);
+                std::mem::take(&mut pending_heredocs),
+                &mut frames,
+                &mut stack,
+            );
+            push_checkpoint(&mut checkpoints, checkpoint);
+
+            state = close_heredoc(state, lines[line_number], &mut tokens, &mut line_matches);
             continue;
         }
 
-        if matches!(token.byte, b'\\') {
-            if let Some(col) = escaped_col {
-                if col == token.col - 1 {
-                    escaped_col = None;
-                    continue;
-                }
+        let escaped = escaped_col.map(|col| col == token.col - 1).unwrap_or(false);
+        // Backslashes opening an interpolation are left to the matcher, i.e. `\(` in Swift
+        let opens_interpolation = token.byte == b'\\'
+            && !escaped
+            && matcher.escape_opens_interpolation(&state, lines[line_number], token.col);
+        if Some(token.byte) == matcher.escape(&state).byte() && !opens_interpolation {
+            if escaped {
+                escaped_col = None;
+                continue;
             }
             escaped_col = Some(token.col);
             continue;
         }
+        // Backslashes are always tokenized, but don't escape in every state
+        if token.byte == b'\\' && !opens_interpolation {
+            continue;
+        }
 
+        // Closing the interpolation, once the delimiters opened inside it have been closed
+        if let Some((interpolation, _)) = frames.last() {
+            if matches!(state, State::Normal)
+                && interpolation.depth == 0
+                && starts_with(&mut tokens, token, &interpolation.close)
+            {
+                let (interpolation, outer_stack) = frames.pop().unwrap();
+                stack = outer_stack;
+                line_matches.push(Match::new_with_stack(
+                    Kind::Closing,
+                    Token::Delimiter(interpolation.open, interpolation.close.clone()),
+                    token.col,
+                    stack.len(),
+                ));
+                skip(&mut tokens, &interpolation.close);
+                state = interpolation.string;
+                continue;
+            }
+        }
+
+        let in_code = matches!(state, State::Normal);
+        let matches_len = line_matches.len();
         state = matcher.call(
             &mut line_matches,
             &mut stack,
+            lines[line_number],
             &mut tokens,
             state,
             token,
-            escaped_col.map(|col| col == token.col - 1).unwrap_or(false),
+            escaped,
         );
+
+        state = match state {
+            // The body starts on the next line, so the rest of the line is code
+            State::InHeredoc(heredoc) if in_code => {
+                pending_heredocs.push(*heredoc);
+                State::Normal
+            }
+            state => state,
+        };
+
+        if let Some((interpolation, _)) = frames.last_mut().filter(|_| in_code) {
+            for match_ in &line_matches[matches_len..] {
+                match (&match_.token, match_.kind) {
+                    (Token::Delimiter(_, close), Kind::Opening)
+                        if *close == interpolation.close =>
+                    {
+                        interpolation.depth += 1
+                    }
+                    (Token::Delimiter(_, close), Kind::Closing)
+                        if *close == interpolation.close =>
+                    {
+                        interpolation.depth = interpolation.depth.saturating_sub(1)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Opened an interpolation, which starts with an empty delimiter stack
+        if matches!(state, State::InInterpolation(_)) {
+            let mut nested_frames = vec![];
+            state = unnest(state, &mut nested_frames);
+            for (interpolation, _) in nested_frames {
+                frames.push((interpolation, std::mem::take(&mut stack)));
+            }
+        }
     }
     matches_by_line.push(line_matches);
-    state_by_line.push(state);
+    let (_, checkpoint) = end_line(
+        state,
+        std::mem::take(&mut pending_heredocs),
+        &mut frames,
+        &mut stack,
+    );
+    push_checkpoint(&mut checkpoints, checkpoint);
 
-    (matches_by_line, state_by_line)
+    (matches_by_line, checkpoints)
 }
 
 // TODO: come up with a better way to do testing
 #[cfg(test)]
 mod tests {
-    use crate::parser::{parse_filetype, Match, State};
+    use super::parse as parse_lines;
+    use crate::parser::*;
+    use matcher_macros::define_matcher;
 
     fn parse(filetype: &str, lines: &str) -> Vec<Vec<Match>> {
         parse_filetype(
@@ -102,6 +454,21 @@ mod tests {
         .0
     }
 
+    fn keyword(
+        kind: Kind,
+        open: &'static str,
+        close: &'static str,
+        col: usize,
+        stack_height: usize,
+    ) -> Match {
+        Match::new_with_stack(
+            kind,
+            Token::Delimiter(open.into(), close.into()),
+            col,
+            stack_height,
+        )
+    }
+
     #[test]
     fn test_parse() {
         assert_eq!(
@@ -145,4 +512,1018 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_nested_block_comments() {
+        let lines = ["/* a /* b */ ( */ )", "/* /*", "*/ { */ }"];
+        let expected = vec![
+            vec![
+                Match::block_comment("/*", 0),
+                Match::block_comment("*/", 15),
+                Match::delimiter(')', 18, Some(0)),
+            ],
+            vec![Match::block_comment("/*", 0)],
+            vec![
+                Match::block_comment("*/", 5),
+                Match::delimiter('}', 8, Some(0)),
+            ],
+        ];
+        let (matches_by_line, checkpoints) = parse_filetype("rust", &lines, State::Normal).unwrap();
+        assert_eq!(matches_by_line, expected);
+        assert_eq!(checkpoints[1].state, State::InBlockComment("/*".into(), 2));
+
+        // Resumes at the depth of the previous line
+        let (matches_by_line, _) =
+            parse_filetype("rust", &lines[2..], Checkpoint::clone(&checkpoints[1])).unwrap();
+        assert_eq!(
+            matches_by_line[0],
+            vec![
+                Match::block_comment("*/", 5),
+                Match::delimiter('}', 8, Some(0)),
+            ]
+        );
+
+        // Not nested in C
+        assert_eq!(
+            parse("c", "/* /* */ ( */"),
+            vec![vec![
+                Match::block_comment("/*", 0),
+                Match::block_comment("*/", 6),
+                Match::delimiter('(', 9, Some(0)),
+            ]]
+        );
+
+        let def = LanguageDef {
+            delimiters: vec![
+                ("(".to_string(), ")".to_string()),
+                ("{".to_string(), "}".to_string()),
+            ],
+            block_comments: vec![("/*".to_string(), "*/".to_string(), true)],
+            ..Default::default()
+        };
+        register_language("test_rust", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_rust", &lines.join("\n")), expected);
+    }
+
+    #[test]
+    fn test_registered_language() {
+        let pair = |open: &str, close: &str| (open.to_string(), close.to_string());
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")"), pair("[", "]"), pair("{", "}")],
+            line_comments: vec!["//".to_string()],
+            block_comments: vec![("/*".to_string(), "*/".to_string(), false)],
+            strings: vec!["\"".to_string()],
+            chars: vec![("'".to_string(), 4)],
+            ..Default::default()
+        };
+        register_language("test_c", DynamicMatcher::new(def).unwrap());
+
+        let string =
+            |kind, delim: &'static str, col| Match::new(kind, Token::String(delim.into()), col);
+        let comment =
+            |kind, col| Match::new(kind, Token::BlockComment("/*".into(), "*/".into()), col);
+        let text =
+            "int main() {\n  // comment {\n  char c = '{';\n  /* { */ printf(\"%s\\n\", \"}\");\n}";
+        assert_eq!(
+            parse("test_c", text),
+            vec![
+                vec![
+                    Match::delimiter('(', 8, Some(0)),
+                    Match::delimiter(')', 9, Some(0)),
+                    Match::delimiter('{', 11, Some(0)),
+                ],
+                vec![Match::line_comment("//", 2)],
+                vec![
+                    string(Kind::Opening, "'", 11),
+                    string(Kind::Closing, "'", 13),
+                ],
+                vec![
+                    comment(Kind::Opening, 2),
+                    comment(Kind::Closing, 7),
+                    Match::delimiter('(', 16, Some(1)),
+                    string(Kind::Opening, "\"", 17),
+                    string(Kind::Closing, "\"", 22),
+                    string(Kind::Opening, "\"", 25),
+                    string(Kind::Closing, "\"", 27),
+                    Match::delimiter(')', 28, Some(1)),
+                ],
+                vec![Match::delimiter('}', 0, Some(0))],
+            ]
+        );
+
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")"), pair("[|", "|]"), pair("{", "}")],
+            chars: vec![("''".to_string(), 1)],
+            ..Default::default()
+        };
+        assert!(DynamicMatcher::new(def).is_err());
+
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")"), pair("[|", "|]"), pair("[", "]")],
+            ..Default::default()
+        };
+        register_language("test_ocaml", DynamicMatcher::new(def).unwrap());
+        let array = |kind, col, stack_height| {
+            Match::new_with_stack(
+                kind,
+                Token::Delimiter("[|".into(), "|]".into()),
+                col,
+                stack_height,
+            )
+        };
+        assert_eq!(
+            parse("test_ocaml", "let a = [| (1); [2] |]\n[|\n|]"),
+            vec![
+                vec![
+                    array(Kind::Opening, 8, 0),
+                    Match::delimiter('(', 11, Some(1)),
+                    Match::delimiter(')', 13, Some(1)),
+                    Match::delimiter('[', 16, Some(1)),
+                    Match::delimiter(']', 18, Some(1)),
+                    array(Kind::Closing, 20, 0),
+                ],
+                vec![array(Kind::Opening, 0, 0)],
+                vec![array(Kind::Closing, 0, 0)],
+            ]
+        );
+    }
+
+    define_matcher!(Parity {
+        delimiters: [
+            "(" => ")",
+            "[" => "]",
+            "{" => "}"
+        ],
+        line_comment: ["#" after_whitespace],
+        block_comment: ["/*" => "*/" nested],
+        char: ["'" max_chars 4],
+        string: ["\"" escape "`"],
+        block_string: ["`" => "`"],
+        inline_span: {
+            strikethrough: "~~" => "~~"
+        },
+        block_span: {
+            math: "$$" => "$$" after_indent
+        },
+        keyword_pairs: [
+            "if" after_indent => ["else"] => "fi",
+            "do" => "done"
+        ],
+        interpolation: ["`" => "${" => "}"],
+        heredoc: ["<<-" indented spaced, "<<"],
+        regex: ["/" => "/"]
+    });
+
+    #[test]
+    fn test_dynamic_matcher_parity() {
+        let pair = |open: &str, close: &str| (open.to_string(), close.to_string());
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")"), pair("[", "]"), pair("{", "}")],
+            line_comments: vec!["#".to_string()],
+            block_comments: vec![("/*".to_string(), "*/".to_string(), true)],
+            chars: vec![("'".to_string(), 4)],
+            strings: vec!["\"".to_string()],
+            block_strings: vec![pair("`", "`")],
+            inline_spans: vec![(
+                "strikethrough".to_string(),
+                "~~".to_string(),
+                "~~".to_string(),
+            )],
+            block_spans: vec![("math".to_string(), "$$".to_string(), "$$".to_string())],
+            keyword_pairs: vec![
+                ("if".to_string(), vec!["else".to_string()], "fi".to_string()),
+                ("do".to_string(), vec![], "done".to_string()),
+            ],
+            interpolations: vec![("`".to_string(), "${".to_string(), "}".to_string())],
+            heredocs: vec![
+                ("<<-".to_string(), true, true),
+                ("<<".to_string(), false, false),
+            ],
+            regexes: vec![pair("/", "/")],
+            predicates: vec![
+                ("#".to_string(), Predicate::AfterWhitespace),
+                ("$$".to_string(), Predicate::AfterIndent),
+                ("if".to_string(), Predicate::AfterIndent),
+            ],
+            escapes: vec![("\"".to_string(), Escape::Char(b'`'))],
+        };
+        let matcher = DynamicMatcher::new(def).unwrap();
+
+        let lines = [
+            "if x; then /* a /* b */ ( */ [y] $# # c (",
+            "  f('{', \"`\"(\", `t ${ {z} } (`, a / b, /(/) <<- EOF",
+            "  ) ${ ( {",
+            "  EOF",
+            "else do (a) ~~ ) ~~ done # )",
+            "  $$",
+            "  ( ` x $$",
+            "$$",
+            "fi return if",
+        ];
+        assert_eq!(
+            parse_lines(&lines, State::Normal, &matcher),
+            parse_lines(&lines, State::Normal, Parity)
+        );
+    }
+
+    define_matcher!(Template {
+        delimiters: [
+            "{" => "}",
+            "{{" => "}}",
+            "{%" => "%}"
+        ],
+        string: ["\""]
+    });
+
+    #[test]
+    fn test_multi_char_delimiters() {
+        let delimiter = |kind, open: &'static str, close: &'static str, col, stack_height| {
+            Match::new_with_stack(
+                kind,
+                Token::Delimiter(open.into(), close.into()),
+                col,
+                stack_height,
+            )
+        };
+        let lines = ["{{ a }} {% if {b} %}", "\"}}\" }}"];
+        let (matches_by_line, _) = parse_lines(&lines, State::Normal, Template);
+
+        assert_eq!(
+            matches_by_line[0],
+            vec![
+                delimiter(Kind::Opening, "{{", "}}", 0, 0),
+                delimiter(Kind::Closing, "{{", "}}", 5, 0),
+                delimiter(Kind::Opening, "{%", "%}", 8, 0),
+                delimiter(Kind::Opening, "{", "}", 14, 1),
+                delimiter(Kind::Closing, "{", "}", 16, 1),
+                delimiter(Kind::Closing, "{%", "%}", 18, 0),
+            ]
+        );
+        assert_eq!(
+            matches_by_line[1][2],
+            delimiter(Kind::Closing, "{{", "}}", 5, 0)
+        );
+        assert_eq!(matches_by_line[1][2].len(), 2);
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        let lines = ["function a()", "  if b then", "    c()", "  end", "end"];
+        let (matches_by_line, checkpoints) = parse_filetype("lua", &lines, State::Normal).unwrap();
+
+        // Resumes with the delimiter stack, so the stack heights match a full parse
+        assert_eq!(checkpoints[1].stack.len(), 2);
+        let (resumed, _) =
+            parse_filetype("lua", &lines[2..], Checkpoint::clone(&checkpoints[1])).unwrap();
+        assert_eq!(resumed, matches_by_line[2..]);
+
+        // Lines which don't change the checkpoint share it with the previous line
+        assert!(std::sync::Arc::ptr_eq(&checkpoints[1], &checkpoints[2]));
+        assert!(!std::sync::Arc::ptr_eq(&checkpoints[2], &checkpoints[3]));
+
+        // Resumes with the delimiter stack around the interpolation
+        let lines = ["a(`${ (", ") }`)"];
+        let (matches_by_line, checkpoints) =
+            parse_filetype("javascript", &lines, State::Normal).unwrap();
+        assert_eq!(checkpoints[0].outer_stacks.len(), 1);
+        let (resumed, _) = parse_filetype(
+            "javascript",
+            &lines[1..],
+            Checkpoint::clone(&checkpoints[0]),
+        )
+        .unwrap();
+        assert_eq!(resumed, matches_by_line[1..]);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let delimiter = |kind, open: &'static str, close: &'static str, col, stack_height| {
+            Match::new_with_stack(
+                kind,
+                Token::Delimiter(open.into(), close.into()),
+                col,
+                stack_height,
+            )
+        };
+        let template =
+            |kind, col| Match::new(kind, Token::BlockString("`".into(), "`".into()), col);
+        let lines = ["const a = `x ${ {b: \"}\"} } y ${", "  f(`${c}`) } z`;"];
+        let expected = vec![
+            vec![
+                template(Kind::Opening, 10),
+                delimiter(Kind::Opening, "${", "}", 13, 0),
+                delimiter(Kind::Opening, "{", "}", 16, 0),
+                Match::new(Kind::Opening, Token::String("\"".into()), 20),
+                Match::new(Kind::Closing, Token::String("\"".into()), 22),
+                delimiter(Kind::Closing, "{", "}", 23, 0),
+                delimiter(Kind::Closing, "${", "}", 25, 0),
+                delimiter(Kind::Opening, "${", "}", 29, 0),
+            ],
+            vec![
+                delimiter(Kind::Opening, "(", ")", 3, 0),
+                template(Kind::Opening, 4),
+                delimiter(Kind::Opening, "${", "}", 5, 1),
+                delimiter(Kind::Closing, "${", "}", 8, 1),
+                template(Kind::Closing, 9),
+                delimiter(Kind::Closing, "(", ")", 10, 0),
+                delimiter(Kind::Closing, "${", "}", 12, 0),
+                template(Kind::Closing, 15),
+            ],
+        ];
+        let (matches_by_line, checkpoints) =
+            parse_filetype("javascript", &lines, State::Normal).unwrap();
+        assert_eq!(matches_by_line, expected);
+        assert_eq!(
+            checkpoints[0].state,
+            State::InInterpolation(Box::new(Interpolation::new(
+                State::InBlockString("`".into()),
+                "${",
+                "}"
+            )))
+        );
+        assert_eq!(checkpoints[1].state, State::Normal);
+
+        // Resumes inside the interpolation
+        let (matches_by_line_, _) = parse_filetype(
+            "javascript",
+            &lines[1..],
+            Checkpoint::clone(&checkpoints[0]),
+        )
+        .unwrap();
+        assert_eq!(matches_by_line_[0], matches_by_line[1]);
+
+        // Ends along with the string containing it
+        let (_, checkpoints) = parse_filetype("ruby", &["\"#{ (", ")"], State::Normal).unwrap();
+        assert_eq!(checkpoints[0].state, State::Normal);
+
+        let pair = |open: &str, close: &str| (open.to_string(), close.to_string());
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")"), pair("[", "]"), pair("{", "}")],
+            line_comments: vec!["//".to_string()],
+            block_comments: vec![("/*".to_string(), "*/".to_string(), false)],
+            strings: vec!["\"".to_string(), "'".to_string()],
+            block_strings: vec![pair("`", "`")],
+            interpolations: vec![("`".to_string(), "${".to_string(), "}".to_string())],
+            ..Default::default()
+        };
+        register_language("test_javascript", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_javascript", &lines.join("\n")), expected);
+
+        // Opens with a backslash, unless the backslash is escaped
+        let text = "print(\"\\(f(x)) \\\\(y)\")";
+        let expected = vec![vec![
+            delimiter(Kind::Opening, "(", ")", 5, 0),
+            Match::new(Kind::Opening, Token::String("\"".into()), 6),
+            delimiter(Kind::Opening, "\\(", ")", 7, 1),
+            delimiter(Kind::Opening, "(", ")", 10, 0),
+            delimiter(Kind::Closing, "(", ")", 12, 0),
+            delimiter(Kind::Closing, "\\(", ")", 13, 1),
+            Match::new(Kind::Closing, Token::String("\"".into()), 20),
+            delimiter(Kind::Closing, "(", ")", 21, 0),
+        ]];
+        assert_eq!(parse("swift", text), expected);
+
+        let def = LanguageDef {
+            delimiters: vec![pair("(", ")")],
+            strings: vec!["\"".to_string()],
+            interpolations: vec![("\"".to_string(), "\\(".to_string(), ")".to_string())],
+            ..Default::default()
+        };
+        register_language("test_swift", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_swift", text), expected);
+
+        // Only a single backslash followed by the rest of the pattern
+        let def = LanguageDef {
+            strings: vec!["\"".to_string()],
+            interpolations: vec![("\"".to_string(), "\\".to_string(), ")".to_string())],
+            ..Default::default()
+        };
+        assert!(DynamicMatcher::new(def).is_err());
+    }
+
+    #[test]
+    fn test_heredoc() {
+        let heredoc = |kind, open: &'static str, col| {
+            Match::new(kind, Token::BlockString(open.into(), "EOF".into()), col)
+        };
+        let lines = [
+            "cat <<-'EOF' | (sort)",
+            "  ) ( { \"",
+            "\tEOF",
+            "cat <<<x (y)",
+        ];
+        let expected = vec![
+            vec![
+                heredoc(Kind::Opening, "<<-", 4),
+                Match::delimiter('(', 15, Some(0)),
+                Match::delimiter(')', 20, Some(0)),
+            ],
+            vec![],
+            vec![heredoc(Kind::Closing, "<<-", 1)],
+            vec![
+                Match::delimiter('(', 9, Some(0)),
+                Match::delimiter(')', 11, Some(0)),
+            ],
+        ];
+        let (matches_by_line, checkpoints) =
+            parse_filetype("shell", &lines, State::Normal).unwrap();
+        assert_eq!(matches_by_line, expected);
+        let in_heredoc = State::InHeredoc(Box::new(Heredoc::new("<<-", "EOF", true)));
+        assert_eq!(checkpoints[0].state, in_heredoc);
+        assert_eq!(checkpoints[1].state, in_heredoc);
+        assert_eq!(checkpoints[2].state, State::Normal);
+
+        // Resumes inside the heredoc
+        let (matches_by_line_, _) =
+            parse_filetype("shell", &lines[1..], Checkpoint::clone(&checkpoints[0])).unwrap();
+        assert_eq!(matches_by_line_, matches_by_line[1..]);
+
+        // Terminator must be alone at the start of the line, unless indented
+        let (_, checkpoints) = parse_filetype(
+            "shell",
+            &["cat << EOF", "  EOF", "EOFX", "EOF"],
+            State::Normal,
+        )
+        .unwrap();
+        assert!(matches!(checkpoints[2].state, State::InHeredoc(_)));
+        assert_eq!(checkpoints[3].state, State::Normal);
+
+        // Shifts inside arithmetic, spaced or not
+        let (matches_by_line, checkpoints) = parse_filetype(
+            "shell",
+            &["echo $(( x << y )) (z)", "(( n <<= 2, m <<y ))", "y"],
+            State::Normal,
+        )
+        .unwrap();
+        assert!(matches_by_line
+            .iter()
+            .flatten()
+            .all(|match_| matches!(match_.token, Token::Delimiter(..))));
+        assert!(checkpoints
+            .iter()
+            .all(|checkpoint| checkpoint.state == State::Normal));
+        let (_, checkpoints) =
+            parse_filetype("shell", &["(( n )) && cat <<y", "y"], State::Normal).unwrap();
+        assert!(matches!(checkpoints[0].state, State::InHeredoc(_)));
+
+        // Bodies of several heredocs on one line follow each other
+        let block_string = |kind, open: &'static str, terminator: &'static str, col| {
+            Match::new(
+                kind,
+                Token::BlockString(open.into(), terminator.into()),
+                col,
+            )
+        };
+        let queued = ["cat <<A <<-B (x)", "( A", "A", ") B", "\tB", "(y)"];
+        let (matches_by_line, checkpoints) =
+            parse_filetype("shell", &queued, State::Normal).unwrap();
+        assert_eq!(
+            matches_by_line,
+            vec![
+                vec![
+                    block_string(Kind::Opening, "<<", "A", 4),
+                    block_string(Kind::Opening, "<<-", "B", 8),
+                    Match::delimiter('(', 13, Some(0)),
+                    Match::delimiter(')', 15, Some(0)),
+                ],
+                vec![],
+                vec![block_string(Kind::Closing, "<<", "A", 0)],
+                vec![],
+                vec![block_string(Kind::Closing, "<<-", "B", 1)],
+                vec![
+                    Match::delimiter('(', 0, Some(0)),
+                    Match::delimiter(')', 2, Some(0)),
+                ],
+            ]
+        );
+        let mut first = Heredoc::new("<<", "A", false);
+        first.queued = vec![Heredoc::new("<<-", "B", true)];
+        assert_eq!(checkpoints[0].state, State::InHeredoc(Box::new(first)));
+        assert_eq!(
+            checkpoints[2].state,
+            State::InHeredoc(Box::new(Heredoc::new("<<-", "B", true)))
+        );
+        assert_eq!(checkpoints[4].state, State::Normal);
+        let (matches_by_line_, _) =
+            parse_filetype("shell", &queued[1..], Checkpoint::clone(&checkpoints[0])).unwrap();
+        assert_eq!(matches_by_line_, matches_by_line[1..]);
+
+        assert_eq!(
+            parse("ruby", "foo(<<~X, <<~Y)\n  )\n  X\n  (\n  Y\n()")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            vec![
+                Match::delimiter('(', 3, Some(0)),
+                block_string(Kind::Opening, "<<~", "X", 4),
+                block_string(Kind::Opening, "<<~", "Y", 10),
+                Match::delimiter(')', 14, Some(0)),
+                block_string(Kind::Closing, "<<~", "X", 2),
+                block_string(Kind::Closing, "<<~", "Y", 2),
+                Match::delimiter('(', 0, Some(0)),
+                Match::delimiter(')', 1, Some(0)),
+            ]
+        );
+        assert_eq!(
+            parse("perl", "print <<A, <<B;\n(\nA\n)\nB\n()")
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>(),
+            vec![
+                block_string(Kind::Opening, "<<", "A", 6),
+                block_string(Kind::Opening, "<<", "B", 11),
+                block_string(Kind::Closing, "<<", "A", 0),
+                block_string(Kind::Closing, "<<", "B", 0),
+                Match::delimiter('(', 0, Some(0)),
+                Match::delimiter(')', 1, Some(0)),
+            ]
+        );
+
+        // Code may follow the terminator
+        assert_eq!(
+            parse("php", "$a = <<<\"EOT\"\n  ( EOT\n  EOT;")[2],
+            vec![Match::new(
+                Kind::Closing,
+                Token::BlockString("<<<".into(), "EOT".into()),
+                2
+            )]
+        );
+
+        let def = LanguageDef {
+            delimiters: vec![
+                ("(".to_string(), ")".to_string()),
+                ("{".to_string(), "}".to_string()),
+            ],
+            line_comments: vec!["#".to_string()],
+            strings: vec!["\"".to_string(), "'".to_string()],
+            heredocs: vec![
+                ("<<".to_string(), false, true),
+                ("<<-".to_string(), true, true),
+            ],
+            ..Default::default()
+        };
+        register_language("test_shell", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_shell", &lines.join("\n")), expected);
+    }
+
+    #[test]
+    fn test_anchored_patterns() {
+        let code = |kind, col| {
+            Match::new(
+                kind,
+                Token::BlockSpan("code".into(), "```".into(), "```".into()),
+                col,
+            )
+        };
+        let lines = ["a ```b``` c", "  ```lua", "a ```", "```"];
+        let expected = vec![
+            vec![],
+            vec![code(Kind::Opening, 2)],
+            vec![],
+            vec![code(Kind::Closing, 0)],
+        ];
+        let (matches_by_line, checkpoints) =
+            parse_filetype("markdown", &lines, State::Normal).unwrap();
+        assert_eq!(matches_by_line, expected);
+        assert_eq!(checkpoints[0].state, State::Normal);
+
+        let comment = |kind, col| {
+            Match::new(
+                kind,
+                Token::BlockComment("=begin".into(), "=end".into()),
+                col,
+            )
+        };
+        assert_eq!(
+            parse("ruby", "x =begin\n=begin\n (\n =end\n=end ("),
+            vec![
+                vec![],
+                vec![comment(Kind::Opening, 0)],
+                vec![],
+                vec![],
+                vec![comment(Kind::Closing, 0), Match::delimiter('(', 5, Some(0))],
+            ]
+        );
+
+        let def = LanguageDef {
+            block_spans: vec![("code".to_string(), "```".to_string(), "```".to_string())],
+            predicates: vec![("```".to_string(), Predicate::AfterIndent)],
+            ..Default::default()
+        };
+        register_language("test_markdown", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_markdown", &lines.join("\n")), expected);
+    }
+
+    #[test]
+    fn test_lookbehind_predicates() {
+        // `#` only starts a comment after whitespace
+        let text = "echo ${#a[@]} $# # (c)";
+        let expected = vec![vec![
+            Match::delimiter('{', 6, Some(0)),
+            Match::delimiter('[', 9, Some(1)),
+            Match::delimiter(']', 11, Some(1)),
+            Match::delimiter('}', 12, Some(0)),
+            Match::line_comment("#", 17),
+        ]];
+        assert_eq!(parse("shell", text), expected);
+
+        // `$` followed by any character is a character literal
+        assert_eq!(
+            parse("erlang", "f($(, $), ${)"),
+            vec![vec![
+                Match::delimiter('(', 1, Some(0)),
+                Match::delimiter(')', 12, Some(0)),
+            ]]
+        );
+
+        // `'` after an identifier is a prime
+        let quote = |kind, col| Match::new(kind, Token::String("'".into()), col);
+        assert_eq!(
+            parse("haskell", "f x' = g 'a' x''"),
+            vec![vec![quote(Kind::Opening, 9), quote(Kind::Closing, 11)]]
+        );
+
+        let def = LanguageDef {
+            delimiters: vec![
+                ("(".to_string(), ")".to_string()),
+                ("[".to_string(), "]".to_string()),
+                ("{".to_string(), "}".to_string()),
+            ],
+            line_comments: vec!["#".to_string()],
+            predicates: vec![("#".to_string(), Predicate::AfterWhitespace)],
+            ..Default::default()
+        };
+        register_language("test_sh", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_sh", text), expected);
+    }
+
+    define_matcher!(Quoted {
+        delimiters: ["(" => ")"],
+        string: ["'" escape doubled, "\"" escape "`"]
+    });
+
+    #[test]
+    fn test_escapes() {
+        // Verbatim strings escape quotes by doubling them, not with backslashes
+        let verbatim =
+            |kind, col| Match::new(kind, Token::BlockString("@\"".into(), "\"".into()), col);
+        let string =
+            |kind, delim: &'static str, col| Match::new(kind, Token::String(delim.into()), col);
+        assert_eq!(
+            parse("csharp", "(@\"a\"\")\" \"\\\"\")"),
+            vec![vec![
+                Match::delimiter('(', 0, Some(0)),
+                verbatim(Kind::Opening, 1),
+                verbatim(Kind::Closing, 7),
+                string(Kind::Opening, "\"", 9),
+                string(Kind::Closing, "\"", 12),
+                Match::delimiter(')', 13, Some(0)),
+            ]]
+        );
+
+        // Raw strings have no escapes
+        let raw = |kind, col| Match::new(kind, Token::BlockString("`".into(), "`".into()), col);
+        assert_eq!(
+            parse("go", "(`\\`)"),
+            vec![vec![
+                Match::delimiter('(', 0, Some(0)),
+                raw(Kind::Opening, 1),
+                raw(Kind::Closing, 3),
+                Match::delimiter(')', 4, Some(0)),
+            ]]
+        );
+
+        let lines = ["('a''b' \"c`\"d\\\")"];
+        let expected = vec![vec![
+            Match::delimiter('(', 0, Some(0)),
+            string(Kind::Opening, "'", 1),
+            string(Kind::Closing, "'", 6),
+            string(Kind::Opening, "\"", 8),
+            string(Kind::Closing, "\"", 14),
+            Match::delimiter(')', 15, Some(0)),
+        ]];
+        assert_eq!(parse_lines(&lines, State::Normal, Quoted).0, expected);
+
+        let def = LanguageDef {
+            delimiters: vec![("(".to_string(), ")".to_string())],
+            strings: vec!["'".to_string(), "\"".to_string()],
+            escapes: vec![
+                ("'".to_string(), Escape::Doubled),
+                ("\"".to_string(), Escape::Char(b'`')),
+            ],
+            ..Default::default()
+        };
+        register_language("test_quoted", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_quoted", lines[0]), expected);
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let quote = |kind, col| Match::new(kind, Token::String("'".into()), col);
+        let literals = |cols: &[(usize, usize)]| {
+            cols.iter()
+                .flat_map(|&(open, close)| {
+                    [quote(Kind::Opening, open), quote(Kind::Closing, close)]
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Escapes and multi-character constants
+        let mut expected = vec![Match::delimiter('(', 1, Some(0))];
+        expected.extend(literals(&[(2, 5), (8, 10), (13, 18), (21, 26), (29, 32)]));
+        expected.push(Match::delimiter(')', 33, Some(0)));
+        assert_eq!(
+            parse("c", "f('\\'', '{', 'abcd', '\\x7f', '\\0')"),
+            vec![expected]
+        );
+
+        // Unicode escapes and multi-byte characters, but not lifetimes
+        let mut expected = vec![Match::delimiter('(', 7, Some(0))];
+        expected.extend(literals(&[(8, 18), (21, 24), (27, 32), (35, 38)]));
+        expected.push(Match::delimiter(')', 39, Some(0)));
+        assert_eq!(
+            parse("rust", "f::<'a>('\\u{1F600}', 'é', '😀', '\\\\')"),
+            vec![expected]
+        );
+
+        let mut expected = vec![
+            Match::delimiter('[', 5, Some(0)),
+            Match::delimiter(']', 6, Some(0)),
+            Match::delimiter('{', 11, Some(0)),
+        ];
+        expected.extend(literals(&[(12, 19), (22, 33), (36, 38)]));
+        expected.push(Match::delimiter('}', 39, Some(0)));
+        assert_eq!(
+            parse("go", "r := []rune{'\\u00e9', '\\U0001F600', '\"'}"),
+            vec![expected]
+        );
+
+        // Stray quotes don't unbalance the rest of the line
+        let mut expected = vec![Match::delimiter('(', 1, Some(0))];
+        expected.extend(literals(&[(2, 9), (12, 14)]));
+        expected.push(Match::delimiter(')', 21, Some(0)));
+        assert_eq!(parse("java", "f('\\ud83d', ')', 'ab')"), vec![expected]);
+    }
+
+    #[test]
+    fn test_regex_literals() {
+        let regex = |kind, open: &'static str, col| {
+            Match::new(kind, Token::Regex(open.into(), "/".into()), col)
+        };
+
+        // Division after identifiers and closing delimiters, regex after operators
+        let text = "x = a / b / (c); y = /[(]\"/.test(s)";
+        let expected = vec![vec![
+            Match::delimiter('(', 12, Some(0)),
+            Match::delimiter(')', 14, Some(0)),
+            regex(Kind::Opening, "/", 21),
+            regex(Kind::Closing, "/", 26),
+            Match::delimiter('(', 32, Some(0)),
+            Match::delimiter(')', 34, Some(0)),
+        ]];
+        assert_eq!(parse("javascript", text), expected);
+        assert_eq!(
+            parse("javascript", "return /\\/(/"),
+            vec![vec![
+                regex(Kind::Opening, "/", 7),
+                regex(Kind::Closing, "/", 11)
+            ]]
+        );
+        assert_eq!(
+            parse("haxe", "f(~/\\(/)"),
+            vec![vec![
+                Match::delimiter('(', 1, Some(0)),
+                regex(Kind::Opening, "~/", 2),
+                regex(Kind::Closing, "~/", 6),
+                Match::delimiter(')', 7, Some(0)),
+            ]]
+        );
+
+        let def = LanguageDef {
+            delimiters: vec![("(".to_string(), ")".to_string())],
+            strings: vec!["\"".to_string()],
+            regexes: vec![("/".to_string(), "/".to_string())],
+            ..Default::default()
+        };
+        register_language("test_js", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_js", text), expected);
+    }
+
+    #[test]
+    fn test_keyword_pairs() {
+        let text = [
+            "local function f(x)",
+            "  if x then return 'end' elseif y then",
+            "    for i = 1, 2 do end",
+            "  else undo() end -- end",
+            "end",
+        ]
+        .join("\n");
+        let expected = vec![
+            vec![
+                keyword(Kind::Opening, "function", "end", 6, 0),
+                Match::delimiter('(', 16, Some(1)),
+                Match::delimiter(')', 18, Some(1)),
+            ],
+            vec![
+                keyword(Kind::Opening, "if", "end", 2, 1),
+                Match::new(Kind::Opening, Token::String("'".into()), 19),
+                Match::new(Kind::Closing, Token::String("'".into()), 23),
+                keyword(Kind::Middle, "elseif", "end", 25, 1),
+            ],
+            vec![
+                keyword(Kind::Opening, "do", "end", 17, 2),
+                keyword(Kind::Closing, "do", "end", 20, 2),
+            ],
+            vec![
+                keyword(Kind::Middle, "else", "end", 2, 1),
+                Match::delimiter('(', 11, Some(2)),
+                Match::delimiter(')', 12, Some(2)),
+                keyword(Kind::Closing, "if", "end", 14, 1),
+                Match::line_comment("--", 18),
+            ],
+            vec![keyword(Kind::Closing, "function", "end", 0, 0)],
+        ];
+        let matches_by_line = parse("lua", &text);
+        assert_eq!(matches_by_line, expected);
+        assert_eq!(matches_by_line[1][3].len(), 6);
+
+        let keywords = |keywords: &[&str]| {
+            let mut keywords = keywords.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+            let close = keywords.pop().unwrap();
+            let open = keywords.remove(0);
+            (open, keywords, close)
+        };
+        let def = LanguageDef {
+            delimiters: vec![("(".to_string(), ")".to_string())],
+            line_comments: vec!["--".to_string()],
+            strings: vec!["'".to_string()],
+            keyword_pairs: vec![
+                keywords(&["function", "end"]),
+                keywords(&["do", "end"]),
+                keywords(&["if", "elseif", "else", "end"]),
+            ],
+            ..Default::default()
+        };
+        register_language("test_lua", DynamicMatcher::new(def).unwrap());
+        assert_eq!(parse("test_lua", &text), expected);
+
+        let def = LanguageDef {
+            keyword_pairs: vec![keywords(&["do", "end-do"])],
+            ..Default::default()
+        };
+        assert!(DynamicMatcher::new(def).is_err());
+    }
+
+    #[test]
+    fn test_ruby_keywords() {
+        // Modifiers, method calls, symbols and hash keys aren't keywords
+        let text = [
+            "class A < B",
+            "  def f(x)",
+            "    return if x.nil?",
+            "    case x when 1 then :end else y.end end",
+            "  end",
+            "  h = {if: 1}",
+            "end",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse("ruby", &text),
+            vec![
+                vec![keyword(Kind::Opening, "class", "end", 0, 0)],
+                vec![
+                    keyword(Kind::Opening, "def", "end", 2, 1),
+                    Match::delimiter('(', 7, Some(2)),
+                    Match::delimiter(')', 9, Some(2)),
+                ],
+                vec![],
+                vec![
+                    keyword(Kind::Opening, "case", "end", 4, 2),
+                    keyword(Kind::Middle, "when", "end", 11, 2),
+                    keyword(Kind::Middle, "else", "end", 28, 2),
+                    keyword(Kind::Closing, "case", "end", 39, 2),
+                ],
+                vec![keyword(Kind::Closing, "def", "end", 2, 1)],
+                vec![
+                    Match::delimiter('{', 6, Some(1)),
+                    Match::delimiter('}', 12, Some(1)),
+                ],
+                vec![keyword(Kind::Closing, "class", "end", 0, 0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_elixir_keywords() {
+        // Keyword lists aren't keywords, i.e. `do: x`
+        let text = [
+            "defmodule A do",
+            "  def f(x), do: x",
+            "  Enum.map(xs, fn x -> x end)",
+            "  if x do 1 else 2 end",
+            "end",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse("elixir", &text),
+            vec![
+                vec![keyword(Kind::Opening, "do", "end", 12, 0)],
+                vec![
+                    Match::delimiter('(', 7, Some(1)),
+                    Match::delimiter(')', 9, Some(1)),
+                ],
+                vec![
+                    Match::delimiter('(', 10, Some(1)),
+                    keyword(Kind::Opening, "fn", "end", 15, 2),
+                    keyword(Kind::Closing, "fn", "end", 25, 2),
+                    Match::delimiter(')', 28, Some(1)),
+                ],
+                vec![
+                    keyword(Kind::Opening, "do", "end", 7, 1),
+                    keyword(Kind::Middle, "else", "end", 12, 1),
+                    keyword(Kind::Closing, "do", "end", 19, 1),
+                ],
+                vec![keyword(Kind::Closing, "do", "end", 0, 0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_erlang_keywords() {
+        let text = [
+            "f(X) ->",
+            "  case X of",
+            "    1 -> fun() -> ok end;",
+            "    _ -> begin ok end",
+            "  end.",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse("erlang", &text),
+            vec![
+                vec![
+                    Match::delimiter('(', 1, Some(0)),
+                    Match::delimiter(')', 3, Some(0)),
+                ],
+                vec![keyword(Kind::Opening, "case", "end", 2, 0)],
+                vec![
+                    keyword(Kind::Opening, "fun", "end", 9, 1),
+                    Match::delimiter('(', 12, Some(2)),
+                    Match::delimiter(')', 13, Some(2)),
+                    keyword(Kind::Closing, "fun", "end", 21, 1),
+                ],
+                vec![
+                    keyword(Kind::Opening, "begin", "end", 9, 1),
+                    keyword(Kind::Closing, "begin", "end", 18, 1),
+                ],
+                vec![keyword(Kind::Closing, "case", "end", 2, 0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_julia_keywords() {
+        // Comprehensions and indices aren't keywords, and `'` after `]` is the adjoint
+        let text = [
+            "function f(x)",
+            "  y = [i for i in x if i > 0]",
+            "  for i in y",
+            "    x[end] = a[begin:end]'",
+            "  end",
+            "  try g() catch e end",
+            "end",
+        ]
+        .join("\n");
+        assert_eq!(
+            parse("julia", &text),
+            vec![
+                vec![
+                    keyword(Kind::Opening, "function", "end", 0, 0),
+                    Match::delimiter('(', 10, Some(1)),
+                    Match::delimiter(')', 12, Some(1)),
+                ],
+                vec![
+                    Match::delimiter('[', 6, Some(1)),
+                    Match::delimiter(']', 28, Some(1)),
+                ],
+                vec![keyword(Kind::Opening, "for", "end", 2, 1)],
+                vec![
+                    Match::delimiter('[', 5, Some(2)),
+                    Match::delimiter(']', 9, Some(2)),
+                    Match::delimiter('[', 14, Some(2)),
+                    Match::delimiter(']', 24, Some(2)),
+                ],
+                vec![keyword(Kind::Closing, "for", "end", 2, 1)],
+                vec![
+                    keyword(Kind::Opening, "try", "end", 2, 1),
+                    Match::delimiter('(', 7, Some(2)),
+                    Match::delimiter(')', 8, Some(2)),
+                    keyword(Kind::Middle, "catch", "end", 10, 1),
+                    keyword(Kind::Closing, "try", "end", 18, 1),
+                ],
+                vec![keyword(Kind::Closing, "function", "end", 0, 0)],
+            ]
+        );
+    }
 }
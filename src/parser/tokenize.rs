@@ -1,7 +1,10 @@
 use std::{
     cell::Cell,
     rc::Rc,
-    simd::{cmp::SimdPartialEq, LaneCount, Simd, SupportedLaneCount},
+    simd::{
+        cmp::{SimdPartialEq, SimdPartialOrd},
+        LaneCount, Mask, Simd, SupportedLaneCount,
+    },
 };
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,12 +19,32 @@ impl CharPos {
     }
 }
 
+/// Finds the bytes which may be part of an identifier, see [`is_identifier_byte`]
+///
+/// [`is_identifier_byte`]: crate::parser::matcher::is_identifier_byte
+#[inline(always)]
+fn identifier_mask<const N: usize>(chunk: Simd<u8, N>) -> Mask<i8, N>
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    // Setting the 0x20 bit maps A-Z to a-z without mapping any other bytes into a-z
+    let lower = chunk | Simd::splat(0x20);
+    (lower.simd_ge(Simd::splat(b'a')) & lower.simd_le(Simd::splat(b'z')))
+        | (chunk.simd_ge(Simd::splat(b'0')) & chunk.simd_le(Simd::splat(b'9')))
+        | chunk.simd_eq(Simd::splat(b'_'))
+        | chunk.simd_ge(Simd::splat(0x80))
+}
+
 /// Takes input text and uses SIMD to find the provided list of tokens in the text
 /// returning the byte and column position of each token. You can get the row by counting
 /// every incoming `\n` token
+///
+/// Bytes in `keyword_starts` are only returned at the start of an identifier, i.e. `d` in
+/// `do` but not in `undo`, so that keywords can be found without returning every letter
 pub fn tokenize<'s, const N: usize>(
     text: &'s str,
-    tokens: &'static [u8],
+    tokens: &[u8],
+    keyword_starts: &[u8],
 ) -> impl Iterator<Item = CharPos> + 's
 where
     LaneCount<N>: SupportedLaneCount,
@@ -41,8 +64,13 @@ where
             }
         })
         .collect::<Vec<_>>();
+    let keyword_starts_to_find = keyword_starts
+        .iter()
+        .map(|&c| Simd::<u8, N>::splat(c))
+        .collect::<Vec<_>>();
 
-    //
+    // Whether the last byte of the previous chunk was part of an identifier
+    let mut identifier_carry = 0;
 
     // TODO: must use Rc and Cell here since we need to mutate the value inside a closure
     // which uses `move`, so otherwise we would copy, and the value would be reset on every
@@ -61,6 +89,20 @@ where
                 tokens = tokens | char.simd_eq(chunk).select(char, none);
             }
 
+            if !keyword_starts_to_find.is_empty() {
+                let identifiers = identifier_mask(chunk).to_bitmask();
+                let identifier_starts = identifiers & !((identifiers << 1) | identifier_carry);
+                identifier_carry = identifiers >> (N - 1);
+
+                let mut keyword_starts = Mask::<i8, N>::splat(false);
+                for &char in keyword_starts_to_find.iter() {
+                    keyword_starts |= char.simd_eq(chunk);
+                }
+                let keyword_starts =
+                    Mask::from_bitmask(keyword_starts.to_bitmask() & identifier_starts);
+                tokens |= keyword_starts.select(chunk, none);
+            }
+
             // Apply parsed tokens
             let chunk_col = chunk_idx * N;
             let col_offset = col_offset.clone();
@@ -103,7 +145,7 @@ mod tests {
         .join("\n");
 
         assert_eq!(
-            tokenize::<16>(&text, &[b'(', b')', b'{', b'}']).collect::<Vec<_>>(),
+            tokenize::<16>(&text, &[b'(', b')', b'{', b'}'], &[]).collect::<Vec<_>>(),
             vec![
                 CharPos::new(b'\n', 0),
                 CharPos::new(b'\n', 0),
@@ -114,4 +156,22 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_tokenize_keyword_starts() {
+        // Identifiers crossing the chunk boundary must not start a new identifier
+        let text = "do undo\ndone_ x:do(éd) dddddddddddd d";
+        assert_eq!(
+            tokenize::<16>(text, b"(", b"d").collect::<Vec<_>>(),
+            vec![
+                CharPos::new(b'd', 0),
+                CharPos::new(b'\n', 0),
+                CharPos::new(b'd', 0),
+                CharPos::new(b'd', 8),
+                CharPos::new(b'(', 10),
+                CharPos::new(b'd', 16),
+                CharPos::new(b'd', 29),
+            ]
+        );
+    }
 }